@@ -2,6 +2,7 @@ use crate::index::BabelIndex;
 use crate::version::{BabelVersion, BabelVersionSet};
 use core::fmt::Display;
 use std::collections::HashMap;
+use std::str::FromStr;
 use pubgrub::{Dependencies, DependencyConstraints, DependencyProvider, Map, Range};
 
 use pubgrub_alpine::deps::AlpinePackage;
@@ -25,15 +26,97 @@ pub enum BabelPackage<'a> {
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum PlatformPackage {
     OS,
+    /// The distribution's OCaml, at the version `ocaml-system` reports as
+    /// `sys-ocaml-version`. Its versions are the platforms.
+    SystemOCaml(OpamVersion),
     // TODO (not now),
     // Architecture
 }
 
+/// The opam variable holding the version of the compiler already installed,
+/// which is the only version `ocaml-system` is available at.
+const SYS_OCAML_VERSION: &str = "sys-ocaml-version";
+/// The distribution packages that install the system compiler.
+const DEBIAN_OCAML: &str = "ocaml-nox";
+const ALPINE_OCAML: &str = "ocaml";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum Ecosystem {
+    Opam,
+    Debian,
+    Alpine,
+    Cargo,
+}
+
+impl Display for Ecosystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ecosystem::Opam => write!(f, "Opam"),
+            Ecosystem::Debian => write!(f, "Debian"),
+            Ecosystem::Alpine => write!(f, "Alpine"),
+            Ecosystem::Cargo => write!(f, "Cargo"),
+        }
+    }
+}
+
+impl FromStr for Ecosystem {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "opam" => Ok(Ecosystem::Opam),
+            "debian" => Ok(Ecosystem::Debian),
+            "alpine" => Ok(Ecosystem::Alpine),
+            "cargo" => Ok(Ecosystem::Cargo),
+            _ => Err(format!("Invalid ecosystem: {}", s)),
+        }
+    }
+}
+
+impl<'a> BabelPackage<'a> {
+    /// The ecosystem and name of a concrete package, or `None` for the
+    /// synthetic packages (roots, proxies, variables, features) the solver
+    /// introduces.
+    pub fn base_name(&self) -> Option<(Ecosystem, String)> {
+        match self {
+            BabelPackage::Opam(OpamPackage::Base(name)) => Some((Ecosystem::Opam, name.clone())),
+            BabelPackage::Debian(DebianPackage::Base(name)) => {
+                Some((Ecosystem::Debian, name.clone()))
+            }
+            BabelPackage::Alpine(AlpinePackage::Base(name)) => {
+                Some((Ecosystem::Alpine, name.clone()))
+            }
+            BabelPackage::Cargo(CargoPackage::Bucket(name, _, _)) => {
+                Some((Ecosystem::Cargo, name.to_string()))
+            }
+            _ => None,
+        }
+    }
+
+    /// The opam packages asked for by a solve starting from this package.
+    pub fn opam_roots(&self) -> Vec<String> {
+        match self {
+            BabelPackage::Root(deps) => deps
+                .iter()
+                .filter_map(|(package, _)| match package {
+                    BabelPackage::Opam(OpamPackage::Base(name)) => Some(name.clone()),
+                    _ => None,
+                })
+                .collect(),
+            BabelPackage::Opam(OpamPackage::Base(name)) => vec![name.clone()],
+            _ => vec![],
+        }
+    }
+}
+
 impl<'a> Display for BabelPackage<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             BabelPackage::Root(_) => write!(f, "Root"),
             BabelPackage::Platform(PlatformPackage::OS) => write!(f, "Platform OS"),
+            BabelPackage::Platform(PlatformPackage::SystemOCaml(version)) => {
+                write!(f, "Platform OCaml {}", version)
+            }
             BabelPackage::Opam(pkg) => write!(f, "Opam {}", pkg),
             BabelPackage::Debian(pkg) => write!(f, "Debian {}", pkg),
             BabelPackage::Alpine(pkg) => write!(f, "Alpine {}", pkg),
@@ -77,6 +160,44 @@ fn contains_os_condition(formula: &pubgrub_opam::index::VersionFormula, os_name:
     }
 }
 
+impl<'a> BabelIndex<'a> {
+    /// The first version in `range`, trying versions the policy avoids or
+    /// rules out only after every other one.
+    fn pick(
+        &self,
+        package: &BabelPackage<'a>,
+        range: &BabelVersionSet,
+        versions: impl Iterator<Item = BabelVersion>,
+    ) -> Option<BabelVersion> {
+        let mut versions = versions.filter(|v| range.contains(v));
+        if self.policy.is_empty() {
+            return versions.next();
+        }
+        // min_by_key keeps the first of equally ranked versions
+        versions.min_by_key(|v| self.policy.rank(package, v))
+    }
+
+    /// The versions of Debian's OCaml package that are this OCaml version.
+    fn debian_ocaml(&self, ocaml: &OpamVersion) -> Range<DebianVersion> {
+        self.debian
+            .list_versions(&DebianPackage::Base(DEBIAN_OCAML.to_string()))
+            .filter(|version| upstream_version(debian_upstream(&version.0)) == ocaml.0)
+            .fold(Range::empty(), |range, version| {
+                range.union(&Range::singleton(version))
+            })
+    }
+
+    /// The versions of Alpine's OCaml package that are this OCaml version.
+    fn alpine_ocaml(&self, ocaml: &OpamVersion) -> Range<AlpineVersion> {
+        self.alpine
+            .list_versions(&AlpinePackage::Base(ALPINE_OCAML.to_string()))
+            .filter(|version| upstream_version(alpine_upstream(&version.0)) == ocaml.0)
+            .fold(Range::empty(), |range, version| {
+                range.union(&Range::singleton(version))
+            })
+    }
+}
+
 impl<'a> DependencyProvider for BabelIndex<'a> {
     type P = BabelPackage<'a>;
 
@@ -92,10 +213,18 @@ impl<'a> DependencyProvider for BabelIndex<'a> {
 
     fn prioritize(
         &self,
-        _package: &Self::P,
-        _range: &Self::VS,
-        _package_conflicts_counts: &pubgrub::PackageResolutionStatistics,
+        package: &Self::P,
+        range: &Self::VS,
+        package_conflicts_counts: &pubgrub::PackageResolutionStatistics,
     ) -> Self::Priority {
+        if package_conflicts_counts.conflict_count() > 0 {
+            tracing::debug!(
+                package = %package,
+                range = %range,
+                conflicts = package_conflicts_counts.conflict_count(),
+                "prioritize"
+            );
+        }
         1
     }
 
@@ -123,25 +252,22 @@ impl<'a> DependencyProvider for BabelIndex<'a> {
             .into_iter()
             .filter(|v| range.contains(v))
             .next()),
-            BabelPackage::Opam(pkg) => Ok(self
-                .opam
-                .list_versions(pkg)
-                .map(|x| BabelVersion::Opam(x))
-                .filter(|v| range.contains(v))
-                .next()),
-            BabelPackage::Debian(pkg) => Ok(self
-                .debian
-                .list_versions(pkg)
-                .map(|x| BabelVersion::Debian(x))
-                .filter(|v| range.contains(v))
-                .next()),
-            BabelPackage::Alpine(pkg) => Ok(self
-                .alpine
-                .list_versions(pkg)
-                .map(|x| BabelVersion::Alpine(x))
-                .filter(|v| range.contains(v))
-                .next()),
-            BabelPackage::Platform(PlatformPackage::OS) => Ok(vec![
+            BabelPackage::Opam(pkg) => Ok(self.pick(
+                package,
+                range,
+                self.opam.list_versions(pkg).map(|x| BabelVersion::Opam(x)),
+            )),
+            BabelPackage::Debian(pkg) => Ok(self.pick(
+                package,
+                range,
+                self.debian.list_versions(pkg).map(|x| BabelVersion::Debian(x)),
+            )),
+            BabelPackage::Alpine(pkg) => Ok(self.pick(
+                package,
+                range,
+                self.alpine.list_versions(pkg).map(|x| BabelVersion::Alpine(x)),
+            )),
+            BabelPackage::Platform(_) => Ok(vec![
                 BabelVersion::Babel("debian".to_string()),
                 BabelVersion::Babel("alpine".to_string()),
             ]
@@ -149,8 +275,13 @@ impl<'a> DependencyProvider for BabelIndex<'a> {
             .filter(|v| range.contains(v))
             .next()),
         };
-        if self.version_debug.get() {
-            println!("version of {}: {:?}", package, ver);
+        if let Ok(version) = &ver {
+            tracing::debug!(
+                package = %package,
+                range = %range,
+                version = version.as_ref().map(tracing::field::display),
+                "choose_version"
+            );
         }
         ver
     }
@@ -160,6 +291,15 @@ impl<'a> DependencyProvider for BabelIndex<'a> {
         package: &BabelPackage<'a>,
         version: &BabelVersion,
     ) -> Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err> {
+        let _span =
+            tracing::debug_span!("get_dependencies", package = %package, version = %version)
+                .entered();
+        // Versions the policy rules out are still offered as a last resort, so
+        // that the conflict report can say which rule was in the way.
+        if let Some(reason) = self.policy.violation(package, version) {
+            tracing::debug!(reason = %reason, "policy");
+            return Ok(Dependencies::Unavailable(reason));
+        }
         let deps = match package {
             BabelPackage::Root(deps) => {
                 Ok(Dependencies::Available(deps.into_iter().cloned().collect()))
@@ -214,6 +354,36 @@ impl<'a> DependencyProvider for BabelIndex<'a> {
                 }
                 Ok(Dependencies::Available(map))
             }
+            BabelPackage::Platform(PlatformPackage::SystemOCaml(ocaml)) => {
+                let mut map = Map::default();
+                match version {
+                    BabelVersion::Babel(ver) => match ver.as_str() {
+                        "debian" => {
+                            map.insert(
+                                BabelPackage::Platform(PlatformPackage::OS),
+                                BabelVersionSet::Babel(Range::singleton("debian")),
+                            );
+                            map.insert(
+                                BabelPackage::Debian(DebianPackage::Base(DEBIAN_OCAML.to_string())),
+                                BabelVersionSet::Debian(self.debian_ocaml(ocaml)),
+                            );
+                        }
+                        "alpine" => {
+                            map.insert(
+                                BabelPackage::Platform(PlatformPackage::OS),
+                                BabelVersionSet::Babel(Range::singleton("alpine")),
+                            );
+                            map.insert(
+                                BabelPackage::Alpine(AlpinePackage::Base(ALPINE_OCAML.to_string())),
+                                BabelVersionSet::Alpine(self.alpine_ocaml(ocaml)),
+                            );
+                        }
+                        _ => panic![],
+                    },
+                    _ => panic![],
+                }
+                Ok(Dependencies::Available(map))
+            }
             BabelPackage::Opam(pkg) => {
                 if let BabelVersion::Opam(ver) = version {
                     let deps = match pkg {
@@ -257,13 +427,25 @@ impl<'a> DependencyProvider for BabelIndex<'a> {
                         _ => {
                             let deps = match self.opam.get_dependencies(pkg, ver) {
                                 Ok(Dependencies::Unavailable(m)) => Dependencies::Unavailable(m),
-                                Ok(Dependencies::Available(dc)) => Dependencies::Available(
-                                    dc.into_iter()
+                                Ok(Dependencies::Available(dc)) => {
+                                    let mut dc: DependencyConstraints<Self::P, Self::VS> = dc
+                                        .into_iter()
                                         .map(|(p, vs)| {
                                             (BabelPackage::Opam(p), BabelVersionSet::Opam(vs))
                                         })
-                                        .collect(),
-                                ),
+                                        .collect();
+                                    // the system compiler is the distribution's
+                                    if matches!(pkg, OpamPackage::Var(var) if var == SYS_OCAML_VERSION)
+                                    {
+                                        dc.insert(
+                                            BabelPackage::Platform(PlatformPackage::SystemOCaml(
+                                                ver.clone(),
+                                            )),
+                                            BabelVersionSet::Babel(Range::full()),
+                                        );
+                                    }
+                                    Dependencies::Available(dc)
+                                }
                                 _ => panic!(),
                             };
                             deps
@@ -332,26 +514,32 @@ impl<'a> DependencyProvider for BabelIndex<'a> {
                 }
             }
         };
-        if self.debug.get() {
-            match &deps {
-                Ok(Dependencies::Available(deps)) => {
-                    print!("({}, {})", package, version);
-                    if deps.len() > 0 {
-                        print!(" -> ")
-                    }
-                    let mut first = true;
-                    for (package, range) in deps.clone() {
-                        if !first {
-                            print!(", ");
-                        }
-                        print!("({}, {})", package, range);
-                        first = false;
-                    }
-                    println!();
-                }
-                _ => {}
-            }
+        if let Ok(Dependencies::Available(deps)) = &deps {
+            tracing::debug!(dependencies = ?deps, "dependencies");
         }
         deps
     }
 }
+
+// the upstream part of a Debian version, without the epoch or revision
+fn debian_upstream(version: &str) -> &str {
+    let version = version
+        .split_once(':')
+        .map_or(version, |(_, version)| version);
+    version
+        .rsplit_once('-')
+        .map_or(version, |(version, _)| version)
+}
+
+// the upstream part of an Alpine version, without the package release
+fn alpine_upstream(version: &str) -> &str {
+    version
+        .rsplit_once("-r")
+        .map_or(version, |(version, _)| version)
+}
+
+// a distribution's upstream version without repackaging suffixes like
+// `+dfsg`, which is what `ocamlc -version` reports
+fn upstream_version(version: &str) -> &str {
+    version.split(['+', '~']).next().unwrap_or(version)
+}
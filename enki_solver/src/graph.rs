@@ -0,0 +1,436 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::fmt::Write;
+
+use pubgrub::{Dependencies, DependencyProvider, SelectedDependencies};
+use pubgrub_alpine::deps::AlpinePackage;
+use pubgrub_opam::deps::OpamPackage;
+
+use crate::deps::{BabelPackage, Ecosystem, PlatformPackage};
+use crate::index::BabelIndex;
+use crate::version::BabelVersion;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GraphFormat {
+    Text,
+    Dot,
+    Mermaid,
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphOptions {
+    /// Hide Alpine `so:` packages that have a single provider, linking their
+    /// dependents straight to the providing package.
+    pub collapse_so: bool,
+    /// Only keep nodes at most this many edges away from the root.
+    pub max_depth: Option<usize>,
+}
+
+impl Default for GraphOptions {
+    fn default() -> Self {
+        Self {
+            collapse_so: true,
+            max_depth: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NodeKind {
+    Package(Ecosystem),
+    Variable,
+    Platform,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub name: String,
+    pub version: String,
+}
+
+impl Node {
+    fn label(&self) -> String {
+        match self.kind {
+            NodeKind::Package(_) => format!("{} {}", self.name, self.version),
+            NodeKind::Variable => format!("{} = {}", self.name, self.version),
+            NodeKind::Platform => format!("platform {} = {}", self.name, self.version),
+        }
+    }
+
+    fn colour(&self) -> &'static str {
+        match self.kind {
+            NodeKind::Package(Ecosystem::Opam) => "#fdd49e",
+            NodeKind::Package(Ecosystem::Debian) => "#f4a6b7",
+            NodeKind::Package(Ecosystem::Alpine) => "#a6cee3",
+            NodeKind::Package(Ecosystem::Cargo) => "#deb887",
+            NodeKind::Variable => "#eeeeee",
+            NodeKind::Platform => "#cccccc",
+        }
+    }
+
+    fn class(&self) -> &'static str {
+        match self.kind {
+            NodeKind::Package(Ecosystem::Opam) => "opam",
+            NodeKind::Package(Ecosystem::Debian) => "debian",
+            NodeKind::Package(Ecosystem::Alpine) => "alpine",
+            NodeKind::Package(Ecosystem::Cargo) => "cargo",
+            NodeKind::Variable => "variable",
+            NodeKind::Platform => "platform",
+        }
+    }
+}
+
+impl std::fmt::Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            NodeKind::Package(ecosystem) => {
+                write!(f, "({} {}, {})", ecosystem, self.name, self.version)
+            }
+            NodeKind::Variable => write!(f, "(Opam {}, {})", self.name, self.version),
+            NodeKind::Platform => write!(f, "(Platform {}, {})", self.name, self.version),
+        }
+    }
+}
+
+/// The solution of a resolve as a graph between the packages a user cares
+/// about, with the solver's proxy packages walked through.
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedGraph {
+    pub roots: BTreeSet<Node>,
+    pub edges: BTreeMap<Node, BTreeSet<Node>>,
+    /// Nodes whose package version came from an overlay.
+    pub overlays: BTreeSet<Node>,
+}
+
+fn is_collapsed_so(index: &BabelIndex, name: &str, options: &GraphOptions) -> bool {
+    options.collapse_so
+        && name.starts_with("so:")
+        && index
+            .alpine
+            .list_versions(&AlpinePackage::Base(name.to_string()))
+            .count()
+            == 1
+}
+
+fn to_node(
+    index: &BabelIndex,
+    package: &BabelPackage,
+    version: &BabelVersion,
+    options: &GraphOptions,
+) -> Option<Node> {
+    let (kind, name) = match package {
+        BabelPackage::Opam(OpamPackage::Var(name)) => (NodeKind::Variable, name.clone()),
+        BabelPackage::Platform(PlatformPackage::OS) => (NodeKind::Platform, "os".to_string()),
+        BabelPackage::Alpine(AlpinePackage::Base(name))
+            if is_collapsed_so(index, name, options) =>
+        {
+            return None
+        }
+        _ => {
+            let (ecosystem, name) = package.base_name()?;
+            (NodeKind::Package(ecosystem), name)
+        }
+    };
+    Some(Node {
+        kind,
+        name,
+        version: version.to_string(),
+    })
+}
+
+impl ResolvedGraph {
+    pub fn new<'a>(
+        index: &BabelIndex<'a>,
+        sol: &SelectedDependencies<BabelIndex<'a>>,
+        root: &BabelPackage<'a>,
+        options: &GraphOptions,
+    ) -> Self {
+        let mut graph = ResolvedGraph::default();
+        let root_version = match sol.get(root) {
+            Some(version) => version,
+            None => return graph,
+        };
+
+        let mut queue = VecDeque::new();
+        match to_node(index, root, root_version, options) {
+            Some(node) => queue.push_back((node, root.clone(), root_version.clone())),
+            None => queue.extend(Self::direct_dependencies(
+                index,
+                sol,
+                root,
+                root_version,
+                options,
+            )),
+        }
+        graph.roots = queue.iter().map(|(node, _, _)| node.clone()).collect();
+
+        let mut depths: BTreeMap<Node, usize> =
+            graph.roots.iter().map(|node| (node.clone(), 0)).collect();
+        while let Some((node, package, version)) = queue.pop_front() {
+            if index.is_overlay(&package, &version) {
+                graph.overlays.insert(node.clone());
+            }
+            let depth = depths[&node];
+            if options.max_depth.map_or(false, |max| depth >= max) {
+                graph.edges.entry(node).or_default();
+                continue;
+            }
+            let dependencies = Self::direct_dependencies(index, sol, &package, &version, options);
+            let mut targets = BTreeSet::new();
+            for (dep_node, dep_package, dep_version) in dependencies {
+                if !depths.contains_key(&dep_node) {
+                    depths.insert(dep_node.clone(), depth + 1);
+                    queue.push_back((dep_node.clone(), dep_package, dep_version));
+                }
+                targets.insert(dep_node);
+            }
+            graph.edges.insert(node, targets);
+        }
+        graph
+    }
+
+    /// The nearest displayable dependencies of a package, looking through any
+    /// proxy packages in between.
+    fn direct_dependencies<'a>(
+        index: &BabelIndex<'a>,
+        sol: &SelectedDependencies<BabelIndex<'a>>,
+        package: &BabelPackage<'a>,
+        version: &BabelVersion,
+        options: &GraphOptions,
+    ) -> Vec<(Node, BabelPackage<'a>, BabelVersion)> {
+        let mut found = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![(package.clone(), version.clone())];
+        while let Some((package, version)) = stack.pop() {
+            if !visited.insert((package.clone(), version.clone())) {
+                continue;
+            }
+            let constraints = match index.get_dependencies(&package, &version) {
+                Ok(Dependencies::Available(constraints)) => constraints,
+                _ => continue,
+            };
+            for (dep_package, _dep_versions) in constraints {
+                let Some(dep_version) = sol.get(&dep_package) else {
+                    continue;
+                };
+                match to_node(index, &dep_package, dep_version, options) {
+                    Some(node) => found.push((node, dep_package, dep_version.clone())),
+                    None => stack.push((dep_package, dep_version.clone())),
+                }
+            }
+        }
+        found
+    }
+
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Text => self.to_text(),
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Mermaid => self.to_mermaid(),
+        }
+    }
+
+    fn nodes(&self) -> BTreeSet<&Node> {
+        self.edges
+            .iter()
+            .flat_map(|(node, targets)| std::iter::once(node).chain(targets))
+            .chain(&self.roots)
+            .collect()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for (node, targets) in &self.edges {
+            if !matches!(node.kind, NodeKind::Package(_)) {
+                continue;
+            }
+            write!(out, "\t{}", node).unwrap();
+            if self.overlays.contains(node) {
+                out.push_str(" [overlay]");
+            }
+            if !targets.is_empty() {
+                out.push_str(" -> ");
+                let targets: Vec<String> = targets.iter().map(|t| t.to_string()).collect();
+                out.push_str(&targets.join(", "));
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn to_dot(&self) -> String {
+        let ids: BTreeMap<&Node, usize> = self.nodes().into_iter().zip(0..).collect();
+        let mut out = String::new();
+        out.push_str("digraph resolved {\n");
+        out.push_str("\trankdir=LR;\n");
+        out.push_str("\tnode [style=filled, fontname=\"Helvetica\"];\n");
+        for (node, id) in &ids {
+            let shape = match node.kind {
+                NodeKind::Package(_) => "box",
+                NodeKind::Variable => "diamond",
+                NodeKind::Platform => "hexagon",
+            };
+            let penwidth = if self.roots.contains(*node) { 2 } else { 1 };
+            let style = if self.overlays.contains(*node) {
+                ", style=\"filled,dashed\""
+            } else {
+                ""
+            };
+            writeln!(
+                out,
+                "\tn{} [label=\"{}\", shape={}, fillcolor=\"{}\", penwidth={}{}];",
+                id,
+                dot_escape(&node.label()),
+                shape,
+                node.colour(),
+                penwidth,
+                style
+            )
+            .unwrap();
+        }
+        for (node, targets) in &self.edges {
+            for target in targets {
+                writeln!(out, "\tn{} -> n{};", ids[node], ids[target]).unwrap();
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        let ids: BTreeMap<&Node, usize> = self.nodes().into_iter().zip(0..).collect();
+        let mut out = String::new();
+        out.push_str("graph LR\n");
+        let mut classes: BTreeMap<&'static str, Vec<String>> = BTreeMap::new();
+        for (node, id) in &ids {
+            let label = mermaid_escape(&node.label());
+            match node.kind {
+                NodeKind::Package(_) => writeln!(out, "    n{}[\"{}\"]", id, label),
+                NodeKind::Variable => writeln!(out, "    n{}{{\"{}\"}}", id, label),
+                NodeKind::Platform => writeln!(out, "    n{}{{{{\"{}\"}}}}", id, label),
+            }
+            .unwrap();
+            classes
+                .entry(node.class())
+                .or_default()
+                .push(format!("n{}", id));
+        }
+        for (node, targets) in &self.edges {
+            for target in targets {
+                writeln!(out, "    n{} --> n{}", ids[node], ids[target]).unwrap();
+            }
+        }
+        for (class, members) in &classes {
+            let colour = ids
+                .keys()
+                .find(|node| node.class() == *class)
+                .map(|node| node.colour())
+                .unwrap_or("#ffffff");
+            writeln!(out, "    classDef {} fill:{},stroke:#333", class, colour).unwrap();
+            writeln!(out, "    class {} {}", members.join(","), class).unwrap();
+        }
+        if !self.roots.is_empty() {
+            let roots: Vec<String> = self.roots.iter().map(|r| format!("n{}", ids[r])).collect();
+            writeln!(out, "    style {} stroke-width:3px", roots.join(",")).unwrap();
+        }
+        for node in &self.overlays {
+            if let Some(id) = ids.get(node) {
+                writeln!(out, "    style n{} stroke-dasharray:5 5", id).unwrap();
+            }
+        }
+        out
+    }
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn mermaid_escape(s: &str) -> String {
+    s.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(kind: NodeKind, name: &str, version: &str) -> Node {
+        Node {
+            kind,
+            name: name.to_string(),
+            version: version.to_string(),
+        }
+    }
+
+    fn example() -> ResolvedGraph {
+        let conf_gmp = node(NodeKind::Package(Ecosystem::Opam), "conf-gmp", "4");
+        let libgmp = node(NodeKind::Package(Ecosystem::Debian), "libgmp-dev", "2:6.1.2");
+        let os = node(NodeKind::Variable, "os-family", "debian");
+        let mut graph = ResolvedGraph::default();
+        graph.roots.insert(conf_gmp.clone());
+        graph
+            .edges
+            .insert(conf_gmp, BTreeSet::from([libgmp.clone(), os.clone()]));
+        graph.edges.insert(libgmp, BTreeSet::new());
+        graph.edges.insert(os, BTreeSet::new());
+        graph
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            example().to_text(),
+            "\t(Opam conf-gmp, 4) -> (Debian libgmp-dev, 2:6.1.2), (Opam os-family, debian)\n\
+             \t(Debian libgmp-dev, 2:6.1.2)\n"
+        );
+    }
+
+    #[test]
+    fn test_dot() {
+        let dot = example().to_dot();
+        assert!(dot.starts_with("digraph resolved {\n"));
+        assert!(dot.contains(
+            "\tn0 [label=\"conf-gmp 4\", shape=box, fillcolor=\"#fdd49e\", penwidth=2];"
+        ));
+        assert!(dot.contains(
+            "\tn2 [label=\"os-family = debian\", shape=diamond, fillcolor=\"#eeeeee\", penwidth=1];"
+        ));
+        assert!(dot.contains("\tn0 -> n1;"));
+        assert!(dot.contains("\tn0 -> n2;"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_mermaid() {
+        let mermaid = example().to_mermaid();
+        assert!(mermaid.starts_with("graph LR\n"));
+        assert!(mermaid.contains("    n1[\"libgmp-dev 2:6.1.2\"]"));
+        assert!(mermaid.contains("    n2{\"os-family = debian\"}"));
+        assert!(mermaid.contains("    n0 --> n1"));
+        assert!(mermaid.contains("    class n1 debian"));
+        assert!(mermaid.contains("    style n0 stroke-width:3px"));
+    }
+
+    #[test]
+    fn test_overlay() {
+        let mut graph = example();
+        graph.overlays.insert(node(
+            NodeKind::Package(Ecosystem::Debian),
+            "libgmp-dev",
+            "2:6.1.2",
+        ));
+        assert!(graph
+            .to_text()
+            .contains("\t(Debian libgmp-dev, 2:6.1.2) [overlay]\n"));
+        assert!(graph.to_dot().contains(
+            "\tn1 [label=\"libgmp-dev 2:6.1.2\", shape=box, fillcolor=\"#f4a6b7\", penwidth=1, style=\"filled,dashed\"];"
+        ));
+        assert!(graph.to_mermaid().contains("    style n1 stroke-dasharray:5 5"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(dot_escape("a \"b\""), "a \\\"b\\\"");
+        assert_eq!(mermaid_escape("a \"b\""), "a #quot;b#quot;");
+    }
+}
@@ -1,40 +1,140 @@
-use std::cell::Cell;
+use std::sync::Arc;
 
 use pubgrub_alpine::index::AlpineIndex;
-use pubgrub_cargo::Index as CargoIndex;
+use pubgrub_cargo::{Index as CargoIndex, IndexMapLookup};
 use pubgrub_debian::index::DebianIndex;
-use pubgrub_opam::index::OpamIndex;
+use pubgrub_opam::index::{OpamFlags, OpamIndex, OpamRepository};
+use pubgrub_opam::switch::SwitchState;
+use pubgrub_opam::version::OpamVersion;
 
-pub struct BabelIndex<'a> {
-    pub opam: OpamIndex,
+use crate::deps::{BabelPackage, Ecosystem};
+use crate::overlay::Overlays;
+use crate::policy::Policy;
+use crate::version::BabelVersion;
+
+/// Every ecosystem's package data, loaded once and shared read-only between
+/// solves. Call `index` to start a resolution.
+pub struct BabelRepository<'c> {
+    pub opam: Arc<OpamRepository>,
     pub debian: DebianIndex,
     pub alpine: AlpineIndex,
+    pub cargo: &'c IndexMapLookup,
+    pub overlays: Overlays,
+}
+
+impl<'c> BabelRepository<'c> {
+    pub fn new(
+        opam: OpamRepository,
+        debian: DebianIndex,
+        alpine: AlpineIndex,
+        cargo: &'c IndexMapLookup,
+    ) -> Self {
+        Self {
+            opam: Arc::new(opam),
+            debian,
+            alpine,
+            cargo,
+            overlays: Overlays::default(),
+        }
+    }
+
+    /// Record which package versions were merged in from overlays.
+    pub fn with_overlays(mut self, overlays: Overlays) -> Self {
+        self.overlays = overlays;
+        self
+    }
+
+    /// A dependency provider for a single solve. It borrows the repository
+    /// and only owns the state that solve builds up.
+    pub fn index(&self) -> BabelIndex<'_> {
+        BabelIndex::new(
+            OpamIndex::from_repository(self.opam.clone()),
+            &self.debian,
+            &self.alpine,
+            CargoIndex::new(self.cargo),
+            &self.overlays,
+        )
+    }
+}
+
+pub struct BabelIndex<'a> {
+    pub opam: OpamIndex,
+    pub debian: &'a DebianIndex,
+    pub alpine: &'a AlpineIndex,
     pub cargo: CargoIndex<'a>,
-    pub debug: Cell<bool>,
-    pub version_debug: Cell<bool>,
+    pub overlays: &'a Overlays,
+    pub policy: Policy,
 }
 
 impl<'a> BabelIndex<'a> {
     pub fn new(
         opam: OpamIndex,
-        debian: DebianIndex,
-        alpine: AlpineIndex,
+        debian: &'a DebianIndex,
+        alpine: &'a AlpineIndex,
         cargo: CargoIndex<'a>,
+        overlays: &'a Overlays,
     ) -> Self {
         Self {
             opam,
             debian,
             alpine,
             cargo,
-            debug: false.into(),
-            version_debug: false.into(),
+            overlays,
+            policy: Policy::default(),
         }
     }
-    pub fn set_debug(&self, flag: bool) {
-        self.debug.set(flag);
+
+    /// Restrict what this solve may select.
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Require the optional dependencies of opam packages.
+    pub fn with_depopts(mut self, with_depopts: bool) -> Self {
+        self.opam = self.opam.with_depopts(with_depopts);
+        self
+    }
+
+    /// Set the flags opam would for this solve.
+    pub fn with_opam_flags(mut self, flags: OpamFlags) -> Self {
+        self.opam = self.opam.with_flags(flags);
+        self
+    }
+
+    /// Give an opam variable a value. A package flag like `with-test` then
+    /// holds or not for every package, whatever the opam flags say.
+    pub fn with_opam_variable(mut self, var: &str, value: OpamVersion) -> Self {
+        self.opam = self.opam.with_variable(var, vec![value]);
+        self
+    }
+
+    /// Prefer what this opam switch has installed, and set its variables.
+    pub fn with_opam_switch(mut self, switch: &SwitchState) -> Self {
+        self.opam = self.opam.with_switch(switch);
+        self
+    }
+
+    /// Whether this package version was defined by an overlay.
+    pub fn is_overlay(&self, package: &BabelPackage, version: &BabelVersion) -> bool {
+        let version = version.to_string();
+        match package.base_name() {
+            Some((Ecosystem::Opam, name)) => self.opam.repository.is_overlay(&name, &version),
+            Some((ecosystem, name)) => self.overlays.contains(ecosystem, &name, &version),
+            None => false,
+        }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
 
-    pub fn set_version_debug(&self, flag: bool) {
-        self.version_debug.set(flag);
+    #[test]
+    fn test_thread_safe() {
+        assert_send_sync::<BabelRepository<'static>>();
+        assert_send_sync::<BabelIndex<'static>>();
     }
 }
@@ -1,3 +1,7 @@
 pub mod deps;
+pub mod graph;
 pub mod index;
+pub mod overlay;
+pub mod policy;
+pub mod trace;
 pub mod version;
@@ -1,37 +1,109 @@
 use cargo::util::interning::InternedString;
-use clap::Parser;
-use pubgrub::{DefaultStringReporter, Dependencies, DependencyProvider, PubGrubError, Reporter};
-use pubgrub::{Map, Range};
+use clap::{Parser, Subcommand};
+use pubgrub::{DefaultStringReporter, PubGrubError, Reporter, SelectedDependencies};
+use pubgrub::Range;
 use pubgrub_alpine::deps::AlpinePackage;
 use pubgrub_alpine::version::AlpineVersion;
 use enki_solver::deps::{BabelPackage, PlatformPackage};
-use enki_solver::index::BabelIndex;
+use enki_solver::graph::{GraphFormat, GraphOptions, ResolvedGraph};
+use enki_solver::index::{BabelIndex, BabelRepository};
+use enki_solver::overlay::{OverlayPaths, OverlayPrecedence};
+use enki_solver::policy::{Policy, Rule};
 use enki_solver::version::{BabelVersion, BabelVersionSet};
 use pubgrub_cargo::index_data;
 use pubgrub_cargo::names::Names as CargoPackage;
 use pubgrub_cargo::rc_semver_pubgrub::RcSemverPubgrub;
-use pubgrub_cargo::{read_index::read_index, Index as CargoIndex};
+use pubgrub_cargo::{read_index::read_index, IndexMapLookup};
 use pubgrub_debian::deps::DebianPackage;
 use pubgrub_debian::version::DebianVersion;
 use pubgrub_opam::deps::OpamPackage;
-use pubgrub_opam::index::OpamIndex;
+use pubgrub_opam::export::{lock_file_project, OpamSolution};
+use pubgrub_opam::index::{OpamFlags, OpamRepository, RepositorySource};
+use pubgrub_opam::switch::SwitchState;
 use pubgrub_opam::version::OpamVersion;
 use semver::Version as CargoVersion;
 use semver_pubgrub::SemverPubgrub;
-use std::collections::{BTreeMap, HashSet};
 use std::error::Error;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+struct SolveOptions {
+    format: GraphFormat,
+    graph: GraphOptions,
+    graph_output: Option<String>,
+    /// Write the opam packages as a `<project>.opam.locked` file here.
+    opam_lock: Option<String>,
+    /// Write the opam packages for `opam switch import` here.
+    opam_switch_export: Option<String>,
+    /// Write where to fetch each opam package's source from here, as JSON.
+    opam_fetch_manifest: Option<String>,
+    /// Pin the `pin-depends:` of this project opam file.
+    opam_pin_depends: Option<String>,
+    /// Start from this opam switch and report the changes made to it.
+    opam_switch: Option<String>,
+    overlays: OverlayPaths,
+    /// Extra opam repositories, as `PATH[@RANK]`.
+    opam_repositories: Vec<String>,
+    policy: Policy,
+    with_depopts: bool,
+    /// Set opam's flags, with `roots` filled in from the packages solved for.
+    /// Without them every flag is left for the solver to choose.
+    opam_flags: Option<OpamFlags>,
+    /// Values given to opam variables with `--variables`.
+    opam_variables: Vec<(String, OpamVersion)>,
+    /// Read the whole opam repository up front, in parallel.
+    preload_opam: bool,
+}
+
+impl Default for SolveOptions {
+    fn default() -> Self {
+        Self {
+            format: GraphFormat::Text,
+            graph: GraphOptions::default(),
+            graph_output: None,
+            opam_lock: None,
+            opam_switch_export: None,
+            opam_fetch_manifest: None,
+            opam_pin_depends: None,
+            opam_switch: None,
+            overlays: OverlayPaths::default(),
+            opam_repositories: vec![],
+            policy: Policy::default(),
+            with_depopts: false,
+            opam_flags: None,
+            opam_variables: vec![],
+            preload_opam: false,
+        }
+    }
+}
+
+/// Add an opam repository given as `PATH[@RANK]`. Rank 1, the default, is
+/// looked at before every other repository, as with `opam repository add`.
+fn with_opam_repository(
+    repository: OpamRepository,
+    spec: &str,
+) -> Result<OpamRepository, Box<dyn Error>> {
+    let (path, rank) = match spec.rsplit_once('@') {
+        Some((path, rank)) => match rank.parse::<usize>() {
+            Ok(rank) => (path, rank),
+            Err(_) => return Err(format!("Invalid opam repository rank: {}", spec).into()),
+        },
+        None => (spec, 1),
+    };
+    Ok(repository.with_repository(path.to_string(), RepositorySource::open(path)?, rank))
+}
 
 fn solve_repo(
-    pkg: BabelPackage<'static>,
+    mut pkg: BabelPackage<'static>,
     version: BabelVersion,
     opam_repo: &str,
     debian_repo: &str,
     alpine_repo: &str,
     cargo_repo: &str,
+    options: &SolveOptions,
 ) -> Result<(), Box<dyn Error>> {
-    let opam_index = OpamIndex::new(opam_repo.to_string());
-    let debian_index = pubgrub_debian::parse::create_index(debian_repo.to_string())?;
-    let alpine_index = pubgrub_alpine::parse::create_index(alpine_repo.to_string())?;
+    let mut debian_index = pubgrub_debian::parse::create_index(debian_repo.to_string())?;
+    let mut alpine_index = pubgrub_alpine::parse::create_index(alpine_repo.to_string())?;
 
     let crates_index = crates_index::GitIndex::with_path(
         cargo_repo,
@@ -42,18 +114,78 @@ fn solve_repo(
     // let create_filter = |_name: &str| true;
     // let version_filter = |version: &index_data::Version| !version.yanked;
     // let data = read_index(&crates_index, create_filter, version_filter);
-    let data = Map::default();
-    let cargo_index = CargoIndex::new(&data);
+    let mut data = IndexMapLookup::default();
 
-    let index = BabelIndex::new(opam_index, debian_index, alpine_index, cargo_index);
-    index.set_debug(true);
-    index.set_version_debug(true);
+    let mut opam_repository = OpamRepository::new(opam_repo.to_string());
+    for spec in &options.opam_repositories {
+        opam_repository = with_opam_repository(opam_repository, spec)?;
+    }
+    if let Some(project) = &options.opam_pin_depends {
+        opam_repository = opam_repository.with_pin_depends(Path::new(project))?;
+    }
+    let switch = match &options.opam_switch {
+        Some(path) => Some(SwitchState::read(Path::new(path))?),
+        None => None,
+    };
+    if let Some(switch) = &switch {
+        opam_repository = switch.pin(opam_repository);
+    }
+    let (opam_repository, overlays) = options.overlays.apply(
+        opam_repository,
+        &mut debian_index,
+        &mut alpine_index,
+        &mut data,
+    )?;
+    let repository = BabelRepository::new(opam_repository, debian_index, alpine_index, &data)
+        .with_overlays(overlays);
+    if options.preload_opam {
+        tracing::info_span!("preload").in_scope(|| repository.opam.preload());
+    }
+    let mut index = repository
+        .index()
+        .with_policy(options.policy.clone())
+        .with_depopts(options.with_depopts);
+    if let Some(flags) = &options.opam_flags {
+        index = index.with_opam_flags(OpamFlags {
+            roots: pkg.opam_roots().into_iter().collect(),
+            ..flags.clone()
+        });
+    }
+    for (var, value) in &options.opam_variables {
+        index = index.with_opam_variable(var, value.clone());
+    }
+    if let Some(switch) = &switch {
+        index = index.with_opam_switch(switch);
+        // keep the switch's roots and pins, unless asked for explicitly
+        let requested = pkg.opam_roots();
+        if let BabelPackage::Root(deps) = &mut pkg {
+            deps.extend(
+                switch
+                    .requirements()
+                    .into_iter()
+                    .filter(|(package, _)| match package {
+                        OpamPackage::Base(name) | OpamPackage::Installed(name) => {
+                            !requested.contains(name)
+                        }
+                        _ => true,
+                    })
+                    .map(|(package, range)| {
+                        (BabelPackage::Opam(package), BabelVersionSet::Opam(range))
+                    }),
+            );
+        }
+    }
 
-    let sol = match pubgrub::resolve(&index, pkg, version) {
+    let root = pkg.clone();
+    let resolved = tracing::info_span!("resolve", package = %pkg, version = %version)
+        .in_scope(|| pubgrub::resolve(&index, pkg, version));
+    let sol = match resolved {
         Ok(sol) => sol,
         Err(PubGrubError::NoSolution(mut derivation_tree)) => {
             derivation_tree.collapse_no_versions();
-            eprintln!("\n\n\n{}", DefaultStringReporter::report(&derivation_tree));
+            let report = DefaultStringReporter::report(&derivation_tree);
+            tracing::info!(report = %report, "no solution");
+            eprintln!("\n\n\n{}", report);
             return Err(Box::new(std::io::Error::new(
                 std::io::ErrorKind::Other,
                 "No solution found",
@@ -62,18 +194,25 @@ fn solve_repo(
         Err(err) => panic!("{:?}", err),
     };
 
-    index.set_debug(false);
-    index.set_version_debug(false);
-
     println!("\nSolution Set:");
     for (package, version) in &sol {
+        let marker = if index.is_overlay(package, version) {
+            "\t[overlay]"
+        } else {
+            ""
+        };
         match package {
             BabelPackage::Platform(PlatformPackage::OS) => {
                 println!("\t(OS, {})", version);
             }
             BabelPackage::Opam(pkg) => match pkg {
                 OpamPackage::Base(name) => {
-                    println!("\tOpam\t({}, {})", name, version);
+                    // a version opam avoids was the only one that worked
+                    let avoided = match index.opam.repository.avoided(name, &version.to_string()) {
+                        Some(avoidance) => format!("\t[{}]", avoidance),
+                        None => String::new(),
+                    };
+                    println!("\tOpam\t({}, {}){}{}", name, version, marker, avoided);
                 }
                 OpamPackage::Var(name) => {
                     println!("\tOpam\t{} = {}", name, version);
@@ -82,7 +221,7 @@ fn solve_repo(
             },
             BabelPackage::Debian(pkg) => match pkg {
                 DebianPackage::Base(name) => {
-                    println!("\tDebian\t({}, {})", name, version);
+                    println!("\tDebian\t({}, {}){}", name, version, marker);
                 }
                 _ => (),
             },
@@ -95,7 +234,7 @@ fn solve_repo(
                             .count()
                             == 1)
                     {
-                        println!("\tAlpine\t({}, {})", name, version);
+                        println!("\tAlpine\t({}, {}){}", name, version, marker);
                     }
                 }
                 _ => (),
@@ -104,111 +243,270 @@ fn solve_repo(
         }
     }
 
-    let mut resolved_graph = BTreeMap::new();
-    for (root_package, root_version) in &sol {
-        let mut transative_deps = vec![(root_package.clone(), root_version.clone())];
-        let mut deps = HashSet::new();
-        while let Some((package, version)) = transative_deps.pop() {
-            let dependencies = index.get_dependencies(&package, &version);
-            match dependencies {
-                Ok(Dependencies::Available(constraints)) => {
-                    for (dep_package, _dep_versions) in constraints {
-                        let solved_version = sol.get(&dep_package).unwrap();
-                        if let Some(dep_version) = sol.get(&dep_package) {
-                            match &dep_package {
-                                BabelPackage::Opam(OpamPackage::Base(name)) => {
-                                    deps.insert((format!("Opam {}", name), dep_version.clone()));
-                                }
-                                BabelPackage::Opam(OpamPackage::Var(name)) => {
-                                    deps.insert((format!("Opam {}", name), dep_version.clone()));
-                                }
-                                BabelPackage::Debian(DebianPackage::Base(name)) => {
-                                    deps.insert((format!("Debian {}", name), dep_version.clone()));
-                                }
-                                BabelPackage::Alpine(AlpinePackage::Base(name)) => {
-                                    if name.starts_with("so:")
-                                        && index
-                                            .alpine
-                                            .list_versions(&AlpinePackage::Base(name.clone()))
-                                            .count()
-                                            == 1
-                                    {
-                                        transative_deps.push((dep_package, solved_version.clone()));
-                                    } else {
-                                        deps.insert((
-                                            format!("Alpine {}", name),
-                                            dep_version.clone(),
-                                        ));
-                                    }
-                                }
-                                BabelPackage::Cargo(CargoPackage::Bucket(name, _, _)) => {
-                                    deps.insert((format!("Cargo {}", name), dep_version.clone()));
-                                }
-                                _ => {
-                                    transative_deps.push((dep_package, solved_version.clone()));
-                                }
-                            }
-                        }
-                    }
-                    Vec::from_iter(deps.clone().into_iter()).sort_by(|(p1, _), (p2, _)| p1.cmp(p2));
-                }
-                _ => {}
-            };
+    let graph = tracing::info_span!("graph")
+        .in_scope(|| ResolvedGraph::new(&index, &sol, &root, &options.graph));
+    let rendered = graph.render(options.format);
+    match &options.graph_output {
+        Some(path) => {
+            std::fs::write(path, rendered)?;
+            println!("\nResolved Dependency Graph written to {}", path);
         }
-        match root_package {
-            BabelPackage::Opam(OpamPackage::Base(name)) => {
-                resolved_graph.insert((format!("Opam {}", name), root_version.clone()), deps);
-            }
-            BabelPackage::Debian(DebianPackage::Base(name)) => {
-                resolved_graph.insert((format!("Debian {}", name), root_version.clone()), deps);
-            }
-            BabelPackage::Alpine(AlpinePackage::Base(name)) => {
-                if !(name.starts_with("so:")
-                    && index
-                        .alpine
-                        .list_versions(&AlpinePackage::Base(name.clone()))
-                        .count()
-                        == 1)
-                {
-                    resolved_graph.insert((format!("Alpine {}", name), root_version.clone()), deps);
-                }
-            }
-            _ => {}
+        None => {
+            println!("\nResolved Dependency Graph:");
+            print!("{}", rendered);
         }
     }
 
-    println!("\nResolved Dependency Graph:");
-    for ((name, version), dependents) in resolved_graph {
-        print!("\t({}, {})", name, version);
-        if !dependents.is_empty() {
-            print!(" -> ");
-            let mut first = true;
-            for (dep_name, dep_version) in dependents {
-                if !first {
-                    print!(", ");
-                }
-                print!("({}, {})", dep_name, dep_version);
-                first = false;
-            }
-        }
-        println!();
+    write_opam_exports(&sol, &root, &index.opam.repository, options)?;
+    if let Some(switch) = &switch {
+        print_switch_actions(&sol, switch);
+    }
+
+    Ok(())
+}
+
+/// Write the opam part of a solution in the formats opam reads back.
+fn write_opam_exports(
+    sol: &SelectedDependencies<BabelIndex>,
+    root: &BabelPackage,
+    repository: &OpamRepository,
+    options: &SolveOptions,
+) -> Result<(), Box<dyn Error>> {
+    let opam = sol
+        .iter()
+        .filter_map(|(package, version)| match (package, version) {
+            (BabelPackage::Opam(package), BabelVersion::Opam(version)) => Some((package, version)),
+            _ => None,
+        });
+    let solution = OpamSolution::new(opam);
+    if let Some(path) = &options.opam_lock {
+        let project = lock_file_project(path)
+            .ok_or_else(|| format!("Lock file {} isn't named <project>.opam.locked", path))?;
+        std::fs::write(path, solution.lock_file(project))?;
+        println!("\nOpam lock file written to {}", path);
+    }
+    if let Some(path) = &options.opam_switch_export {
+        std::fs::write(path, solution.switch_export(&root.opam_roots()))?;
+        println!("\nOpam switch export written to {}", path);
     }
+    if let Some(path) = &options.opam_fetch_manifest {
+        std::fs::write(path, solution.fetch_manifest(repository)?)?;
+        println!("\nOpam fetch manifest written to {}", path);
+    }
+    Ok(())
+}
+
+/// Print what opam would do to take the switch to the solution.
+fn print_switch_actions(sol: &SelectedDependencies<BabelIndex>, switch: &SwitchState) {
+    let opam = sol
+        .iter()
+        .filter_map(|(package, version)| match (package, version) {
+            (BabelPackage::Opam(package), BabelVersion::Opam(version)) => Some((package, version)),
+            _ => None,
+        });
+    let actions = switch.actions(&OpamSolution::new(opam));
+    println!("\nSwitch actions:");
+    if actions.is_empty() {
+        println!("\tnothing to do");
+    }
+    for action in actions {
+        println!("\t{}", action);
+    }
+}
 
+/// Print the metadata of an opam package, `PACKAGE` for its latest version or
+/// `PACKAGE.VERSION`, and the packages that depend on it.
+fn show_opam_package(
+    opam_repo: &str,
+    opam_repositories: &[String],
+    spec: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut repository = OpamRepository::new(opam_repo.to_string());
+    for spec in opam_repositories {
+        repository = with_opam_repository(repository, spec)?;
+    }
+    let (name, version) = match spec.split_once('.') {
+        Some((name, version)) => (name.to_string(), OpamVersion(version.to_string())),
+        None => {
+            let name = spec.to_string();
+            let version = repository
+                .available_versions(&name)
+                .into_iter()
+                .next()
+                .ok_or_else(|| format!("No opam package {}", name))?;
+            (name, version)
+        }
+    };
+    let info = repository.package_info(&name, &version.to_string())?;
+    println!("{} {}", info.name, info.version);
+    let fields = [
+        ("synopsis", info.synopsis.into_iter().collect()),
+        ("maintainer", info.maintainers),
+        ("license", info.licenses),
+        ("homepage", info.homepages),
+        ("dev-repo", info.dev_repo.into_iter().collect()),
+        ("tags", info.tags),
+        ("flags", info.flags),
+    ];
+    for (field, values) in fields {
+        if !values.is_empty() {
+            println!("\t{}\t{}", field, values.join(" "));
+        }
+    }
+    if let Some(description) = &info.description {
+        println!("\n{}", description.trim());
+    }
+    for (heading, dependencies) in [("Depends", info.depends), ("Depopts", info.depopts)] {
+        if !dependencies.is_empty() {
+            println!("\n{}:", heading);
+        }
+        for dependency in dependencies {
+            println!("\t{}", dependency.formula);
+        }
+    }
+    let reverse = repository.search_index().reverse_dependencies(&name);
+    println!("\nRequired by:");
+    if reverse.is_empty() {
+        println!("\tnothing");
+    }
+    for (package, versions) in reverse {
+        let versions: Vec<String> = versions.iter().map(|v| v.to_string()).collect();
+        println!("\t{}\t{}", package, versions.join(" "));
+    }
     Ok(())
 }
 
 #[derive(Parser)]
 #[command(name = "solver", about = "Solve repository dependencies")]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// List of packages with their ecosystems and versions in the form `ecosystem:package_name:version`
     packages: Vec<String>,
     /// List of variable assignments in the form `variable_name=value`
     #[clap(short, long, value_name = "VAR=value")]
     variables: Option<Vec<String>>,
+    /// Log solver progress to stderr; repeat for more detail
+    #[clap(long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Write every solver event to this file as JSON lines
+    #[clap(long, value_name = "FILE")]
+    trace_file: Option<String>,
+    /// Print a trace written with --trace-file and exit
+    #[clap(long, value_name = "FILE")]
+    replay: Option<String>,
+    /// Format to print the resolved dependency graph in
+    #[clap(long, value_enum, default_value_t = GraphFormat::Text)]
+    format: GraphFormat,
+    /// Keep Alpine `so:` packages with a single provider apart from that provider
+    #[clap(long)]
+    no_collapse_so: bool,
+    /// Only show packages up to this many edges from the root
+    #[clap(long, value_name = "N")]
+    depth: Option<usize>,
+    /// Write the resolved dependency graph to this file instead of stdout
+    #[clap(long, value_name = "FILE")]
+    graph_output: Option<String>,
+    /// Write the opam packages of the solution to a `<project>.opam.locked` file
+    #[clap(long, value_name = "FILE")]
+    opam_lock: Option<String>,
+    /// Write the opam packages of the solution in `opam switch import` format
+    #[clap(long, value_name = "FILE")]
+    opam_switch_export: Option<String>,
+    /// Write the source URL and checksums of each opam package of the solution as JSON
+    #[clap(long, value_name = "FILE")]
+    opam_fetch_manifest: Option<String>,
+    /// Pin the opam packages in the `pin-depends:` of this project opam file
+    #[clap(long, value_name = "FILE")]
+    opam_pin_depends: Option<String>,
+    /// Solve starting from this opam switch: a `switch-state` file, an `opam switch export`
+    /// file, or a switch prefix directory. Prints what would be installed, upgraded,
+    /// downgraded and removed.
+    #[clap(long, value_name = "PATH")]
+    opam_switch: Option<String>,
+    /// Directory of extra opam packages, laid out like a repository's `packages`
+    #[clap(long, value_name = "DIR")]
+    opam_overlay: Option<String>,
+    /// Another opam repository, a `packages` directory or an `index.tar.gz`,
+    /// looked at in rank order before or after the default one; repeatable
+    #[clap(long, value_name = "PATH[@RANK]")]
+    opam_repository: Vec<String>,
+    /// Extra Debian packages in `Packages` file format
+    #[clap(long, value_name = "FILE")]
+    debian_overlay: Option<String>,
+    /// Extra Alpine packages in `APKINDEX` format
+    #[clap(long, value_name = "FILE")]
+    alpine_overlay: Option<String>,
+    /// Extra crates in registry index format, a file or a directory of them
+    #[clap(long, value_name = "PATH")]
+    cargo_overlay: Option<String>,
+    /// How overlays combine with the base indexes: `shadow` hides every base
+    /// version of a package the overlay defines, `overlay` and `base` offer
+    /// both and pick that side where the same version is defined twice
+    #[clap(long, default_value_t = OverlayPrecedence::Overlay)]
+    overlay_precedence: OverlayPrecedence,
+    /// Never select a package, or one version of it
+    #[clap(long, value_name = "ECOSYSTEM:PACKAGE[:VERSION]")]
+    forbid: Vec<String>,
+    /// Only ever select this version of a package
+    #[clap(long, value_name = "ECOSYSTEM:PACKAGE:VERSION")]
+    pin: Vec<String>,
+    /// Select a package, or one version of it, only if nothing else works; not for cargo
+    #[clap(long, value_name = "ECOSYSTEM:PACKAGE[:VERSION]")]
+    avoid: Vec<String>,
+    /// File of policy rules, one `forbid|pin|avoid ECOSYSTEM:PACKAGE[:VERSION]` per line
+    #[clap(long, value_name = "FILE")]
+    policy: Option<String>,
+    /// Require the optional dependencies (`depopts`) of opam packages
+    #[clap(long)]
+    with_depopts: bool,
+    /// Include the test dependencies of the opam packages asked for
+    #[clap(long)]
+    with_test: bool,
+    /// Include the documentation dependencies of the opam packages asked for
+    #[clap(long)]
+    with_doc: bool,
+    /// Include the development setup dependencies of the opam packages asked for
+    #[clap(long)]
+    with_dev_setup: bool,
+    /// An opam package pinned to a development source, for which `dev` holds
+    #[clap(long, value_name = "PACKAGE")]
+    dev: Vec<String>,
+    /// Read every opam package before solving, spread over all cores
+    #[clap(long)]
+    preload_opam: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show an opam package's metadata and the packages that depend on it
+    Show {
+        /// `PACKAGE` for its latest version, or `PACKAGE.VERSION`
+        package: String,
+        /// Another opam repository, as for solving; repeatable
+        #[clap(long, value_name = "PATH[@RANK]")]
+        opam_repository: Vec<String>,
+    },
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Cli::parse();
+    if let Some(Command::Show {
+        package,
+        opam_repository,
+    }) = &args.command
+    {
+        return show_opam_package(
+            "pubgrub_opam/opam-repository/packages",
+            opam_repository,
+            package,
+        );
+    }
+    if let Some(path) = &args.replay {
+        return enki_solver::trace::replay(path, &mut std::io::stdout().lock());
+    }
+    enki_solver::trace::init(args.verbose, args.trace_file.as_deref())?;
     let mut packages = args
         .packages
         .into_iter()
@@ -255,27 +553,33 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
         })
         .collect::<Vec<_>>();
-    match args.variables {
-        Some(v) => {
-            let variables = v
-                .into_iter()
-                .map(|var_val| {
-                    let parts: Vec<&str> = var_val.split('=').collect();
-                    if parts.len() != 2 {
-                        eprintln!("Invalid variable format: {}", var_val);
-                        std::process::exit(1);
-                    }
-                    let var = parts[0];
-                    let val = parts[1];
-                    (
-                        BabelPackage::Opam(OpamPackage::Var(var.to_string())),
-                        BabelVersionSet::Opam(Range::singleton(OpamVersion(val.to_string()))),
-                    )
-                })
-                .collect::<Vec<_>>();
-            packages.extend(variables);
+    let variables = args
+        .variables
+        .unwrap_or_default()
+        .into_iter()
+        .map(|var_val| {
+            let parts: Vec<&str> = var_val.split('=').collect();
+            if parts.len() != 2 {
+                eprintln!("Invalid variable format: {}", var_val);
+                std::process::exit(1);
+            }
+            (parts[0].to_string(), OpamVersion(parts[1].to_string()))
+        })
+        .collect::<Vec<_>>();
+    packages.extend(variables.iter().map(|(var, val)| {
+        (
+            BabelPackage::Opam(OpamPackage::Var(var.clone())),
+            BabelVersionSet::Opam(Range::singleton(val.clone())),
+        )
+    }));
+    let mut policy = match &args.policy {
+        Some(path) => Policy::from_file(path)?,
+        None => Policy::default(),
+    };
+    for (kind, specs) in [("forbid", &args.forbid), ("pin", &args.pin), ("avoid", &args.avoid)] {
+        for spec in specs {
+            policy.add(Rule::parse(kind, spec)?);
         }
-        None => {}
     }
     let root = BabelPackage::Root(packages);
     solve_repo(
@@ -285,6 +589,38 @@ fn main() -> Result<(), Box<dyn Error>> {
         "pubgrub_debian/repositories/buster/Packages",
         "pubgrub_alpine/repositories/3.20/APKINDEX",
         "pubgrub_cargo/index",
+        &SolveOptions {
+            format: args.format,
+            graph: GraphOptions {
+                collapse_so: !args.no_collapse_so,
+                max_depth: args.depth,
+            },
+            graph_output: args.graph_output,
+            opam_lock: args.opam_lock,
+            opam_switch_export: args.opam_switch_export,
+            opam_fetch_manifest: args.opam_fetch_manifest,
+            opam_pin_depends: args.opam_pin_depends,
+            opam_switch: args.opam_switch,
+            overlays: OverlayPaths {
+                opam: args.opam_overlay,
+                debian: args.debian_overlay,
+                alpine: args.alpine_overlay,
+                cargo: args.cargo_overlay,
+                precedence: args.overlay_precedence,
+            },
+            opam_repositories: args.opam_repository,
+            policy,
+            with_depopts: args.with_depopts,
+            opam_flags: Some(OpamFlags {
+                with_test: args.with_test,
+                with_doc: args.with_doc,
+                with_dev_setup: args.with_dev_setup,
+                dev: args.dev.into_iter().collect(),
+                ..OpamFlags::default()
+            }),
+            opam_variables: variables,
+            preload_opam: args.preload_opam,
+        },
     )
 }
 
@@ -303,6 +639,7 @@ mod tests {
             "../pubgrub_debian/repositories/buster/Packages",
             "../pubgrub_alpine/repositories/3.20/APKINDEX",
             "../pubgrub_cargo/index",
+            &SolveOptions::default(),
         )
     }
 
@@ -333,6 +670,23 @@ mod tests {
             "../pubgrub_debian/repositories/buster/Packages",
             "../pubgrub_alpine/repositories/3.20/APKINDEX",
             "../pubgrub_cargo/index",
+            &SolveOptions::default(),
+        )
+    }
+
+    #[test]
+    fn test_opam_dune_flags() -> Result<(), Box<dyn Error>> {
+        solve_repo(
+            BabelPackage::Opam(OpamPackage::Base("dune".to_string())),
+            BabelVersion::Opam(OpamVersion("3.17.2".to_string())),
+            "../pubgrub_opam/opam-repository/packages",
+            "../pubgrub_debian/repositories/buster/Packages",
+            "../pubgrub_alpine/repositories/3.20/APKINDEX",
+            "../pubgrub_cargo/index",
+            &SolveOptions {
+                opam_flags: Some(OpamFlags::default()),
+                ..SolveOptions::default()
+            },
         )
     }
 
@@ -345,6 +699,7 @@ mod tests {
             "../pubgrub_debian/repositories/buster/Packages",
             "../pubgrub_alpine/repositories/3.20/APKINDEX",
             "../pubgrub_cargo/index",
+            &SolveOptions::default(),
         )
     }
 
@@ -361,6 +716,7 @@ mod tests {
             "../pubgrub_debian/repositories/buster/Packages",
             "../pubgrub_alpine/repositories/3.20/APKINDEX",
             "../pubgrub_cargo/index",
+            &SolveOptions::default(),
         )
     }
 
@@ -387,6 +743,49 @@ mod tests {
             "../pubgrub_debian/repositories/buster/Packages",
             "../pubgrub_alpine/repositories/3.20/APKINDEX",
             "../pubgrub_cargo/index",
+            &SolveOptions::default(),
+        )
+    }
+
+    #[test]
+    fn test_conf_gmp_alpine_dot() -> Result<(), Box<dyn Error>> {
+        let root = BabelPackage::Root(vec![
+            (
+                BabelPackage::Opam(OpamPackage::Base("conf-gmp".to_string())),
+                BabelVersionSet::Opam(Range::singleton(OpamVersion("4".to_string()))),
+            ),
+            (
+                BabelPackage::Platform(PlatformPackage::OS),
+                BabelVersionSet::Babel(Range::singleton("alpine")),
+            ),
+        ]);
+        solve_repo(
+            root,
+            BabelVersion::Babel("root".to_string()),
+            "../pubgrub_opam/opam-repository/packages",
+            "../pubgrub_debian/repositories/buster/Packages",
+            "../pubgrub_alpine/repositories/3.20/APKINDEX",
+            "../pubgrub_cargo/index",
+            &SolveOptions {
+                format: GraphFormat::Dot,
+                graph: GraphOptions {
+                    collapse_so: true,
+                    max_depth: Some(2),
+                },
+                graph_output: None,
+                opam_lock: None,
+                opam_switch_export: None,
+                opam_fetch_manifest: None,
+                opam_pin_depends: None,
+                opam_switch: None,
+                overlays: OverlayPaths::default(),
+                opam_repositories: vec![],
+                policy: Policy::default(),
+                with_depopts: false,
+                opam_flags: None,
+                opam_variables: vec![],
+                preload_opam: false,
+            },
         )
     }
 
@@ -413,6 +812,7 @@ mod tests {
             "../pubgrub_debian/repositories/buster/Packages",
             "../pubgrub_alpine/repositories/3.20/APKINDEX",
             "../pubgrub_cargo/index",
+            &SolveOptions::default(),
         )
     }
 
@@ -443,6 +843,7 @@ mod tests {
             "../pubgrub_debian/repositories/buster/Packages",
             "../pubgrub_alpine/repositories/3.20/APKINDEX",
             "../pubgrub_cargo/index",
+            &SolveOptions::default(),
         )
     }
 
@@ -473,6 +874,7 @@ mod tests {
             "../pubgrub_debian/repositories/buster/Packages",
             "../pubgrub_alpine/repositories/3.20/APKINDEX",
             "../pubgrub_cargo/index",
+            &SolveOptions::default(),
         )
     }
 
@@ -495,6 +897,7 @@ mod tests {
             "../pubgrub_debian/repositories/buster/Packages",
             "../pubgrub_alpine/repositories/3.20/APKINDEX",
             "../pubgrub_cargo/index",
+            &SolveOptions::default(),
         )
     }
 
@@ -518,6 +921,7 @@ mod tests {
             "../pubgrub_debian/repositories/buster/Packages",
             "../pubgrub_alpine/repositories/3.20/APKINDEX",
             "../pubgrub_cargo/index",
+            &SolveOptions::default(),
         )
     }
 
@@ -540,6 +944,7 @@ mod tests {
             "../pubgrub_debian/repositories/buster/Packages",
             "../pubgrub_alpine/repositories/3.20/APKINDEX",
             "../pubgrub_cargo/index",
+            &SolveOptions::default(),
         )
     }
 
@@ -563,6 +968,183 @@ mod tests {
             "../pubgrub_debian/repositories/buster/Packages",
             "../pubgrub_alpine/repositories/3.20/APKINDEX",
             "../pubgrub_cargo/index",
+            &SolveOptions::default(),
         )
     }
+
+    #[test]
+    fn test_debian_overlay() -> Result<(), Box<dyn Error>> {
+        let mut debian_index = pubgrub_debian::parse::create_index(
+            "../pubgrub_debian/repositories/buster/Packages".to_string(),
+        )?;
+        let mut alpine_index = pubgrub_alpine::parse::create_index(
+            "../pubgrub_alpine/repositories/3.20/APKINDEX".to_string(),
+        )?;
+        let mut data = IndexMapLookup::default();
+        let overlay_paths = OverlayPaths {
+            debian: Some("example-overlay/Packages".to_string()),
+            ..OverlayPaths::default()
+        };
+        let (opam_repository, overlays) = overlay_paths.apply(
+            OpamRepository::new("../pubgrub_opam/opam-repository/packages".to_string()),
+            &mut debian_index,
+            &mut alpine_index,
+            &mut data,
+        )?;
+        let repository = BabelRepository::new(opam_repository, debian_index, alpine_index, &data)
+            .with_overlays(overlays);
+        let index = repository.index();
+        let tool = BabelPackage::Debian(DebianPackage::Base("enki-example-tool".to_string()));
+        let version = BabelVersion::Debian(DebianVersion("1.0-1".to_string()));
+        let Ok(sol) = pubgrub::resolve(&index, tool.clone(), version) else {
+            return Err("No solution found".into());
+        };
+        let libgmp = BabelPackage::Debian(DebianPackage::Base("libgmp-dev".to_string()));
+        assert!(index.is_overlay(&tool, &sol[&tool]));
+        assert!(!index.is_overlay(&libgmp, &sol[&libgmp]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_debian_policy() -> Result<(), Box<dyn Error>> {
+        let data = IndexMapLookup::default();
+        let repository = BabelRepository::new(
+            OpamRepository::new("../pubgrub_opam/opam-repository/packages".to_string()),
+            pubgrub_debian::parse::create_index(
+                "../pubgrub_debian/repositories/buster/Packages".to_string(),
+            )?,
+            pubgrub_alpine::parse::create_index(
+                "../pubgrub_alpine/repositories/3.20/APKINDEX".to_string(),
+            )?,
+            &data,
+        );
+        let mut policy = Policy::default();
+        policy.add(Rule::parse("forbid", "debian:libssl1.1")?);
+        let index = repository.index().with_policy(policy);
+        let resolved = pubgrub::resolve(
+            &index,
+            BabelPackage::Debian(DebianPackage::Base("openssh-server".to_string())),
+            BabelVersion::Debian(DebianVersion("1:7.9p1-10+deb10u2".to_string())),
+        );
+        match resolved {
+            Err(PubGrubError::NoSolution(derivation_tree)) => {
+                let report = DefaultStringReporter::report(&derivation_tree);
+                assert!(
+                    report.contains("forbidden by policy `forbid debian:libssl1.1`"),
+                    "{}",
+                    report
+                );
+                Ok(())
+            }
+            _ => Err("expected the policy to rule out every solution".into()),
+        }
+    }
+
+    #[test]
+    fn test_ocaml_system_debian() -> Result<(), Box<dyn Error>> {
+        let data = IndexMapLookup::default();
+        let repository = BabelRepository::new(
+            OpamRepository::new("../pubgrub_opam/opam-repository/packages".to_string()),
+            pubgrub_debian::parse::create_index(
+                "../pubgrub_debian/repositories/buster/Packages".to_string(),
+            )?,
+            pubgrub_alpine::parse::create_index(
+                "../pubgrub_alpine/repositories/3.20/APKINDEX".to_string(),
+            )?,
+            &data,
+        );
+        let index = repository.index();
+        let root = BabelPackage::Root(vec![
+            (
+                BabelPackage::Opam(OpamPackage::Base("ocaml-system".to_string())),
+                BabelVersionSet::Opam(Range::full()),
+            ),
+            (
+                BabelPackage::Platform(PlatformPackage::OS),
+                BabelVersionSet::Babel(Range::singleton("debian")),
+            ),
+        ]);
+        let sol = pubgrub::resolve(&index, root, BabelVersion::Babel("root".to_string()))?;
+        let ocaml_system = BabelPackage::Opam(OpamPackage::Base("ocaml-system".to_string()));
+        let ocaml_nox = BabelPackage::Debian(DebianPackage::Base("ocaml-nox".to_string()));
+        let ocaml_system = sol
+            .get(&ocaml_system)
+            .ok_or("ocaml-system isn't selected")?;
+        let ocaml_nox = sol.get(&ocaml_nox).ok_or("ocaml-nox isn't selected")?;
+        // buster's ocaml-nox is a Debian revision of the same OCaml
+        assert!(ocaml_nox
+            .to_string()
+            .starts_with(&format!("{}-", ocaml_system)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_opam_variable_flag() -> Result<(), Box<dyn Error>> {
+        let data = IndexMapLookup::default();
+        let repository = BabelRepository::new(
+            OpamRepository::new("../pubgrub_opam/example-repo/packages".to_string()),
+            pubgrub_debian::parse::create_index(
+                "../pubgrub_debian/repositories/buster/Packages".to_string(),
+            )?,
+            pubgrub_alpine::parse::create_index(
+                "../pubgrub_alpine/repositories/3.20/APKINDEX".to_string(),
+            )?,
+            &data,
+        );
+        // `--variables with-test=true` holds for every package, flags or not
+        let index = repository
+            .index()
+            .with_opam_flags(OpamFlags::new(["flags".to_string()]))
+            .with_opam_variable("with-test", TRUE_VERSION.clone());
+        let sol = pubgrub::resolve(
+            &index,
+            BabelPackage::Opam(OpamPackage::Base("flags".to_string())),
+            BabelVersion::Opam(OpamVersion("1.0.0".to_string())),
+        )?;
+        assert!(sol.contains_key(&BabelPackage::Opam(OpamPackage::Base("D".to_string()))));
+        assert!(sol.contains_key(&BabelPackage::Opam(OpamPackage::Base("B".to_string()))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_solves() -> Result<(), Box<dyn Error>> {
+        let data = IndexMapLookup::default();
+        let repository = BabelRepository::new(
+            OpamRepository::new("../pubgrub_opam/opam-repository/packages".to_string()),
+            pubgrub_debian::parse::create_index(
+                "../pubgrub_debian/repositories/buster/Packages".to_string(),
+            )?,
+            pubgrub_alpine::parse::create_index(
+                "../pubgrub_alpine/repositories/3.20/APKINDEX".to_string(),
+            )?,
+            &data,
+        );
+        let os_family = |os: &'static str| {
+            let index = repository.index();
+            let root = BabelPackage::Root(vec![
+                (
+                    BabelPackage::Opam(OpamPackage::Base("conf-gmp".to_string())),
+                    BabelVersionSet::Opam(Range::singleton(OpamVersion("4".to_string()))),
+                ),
+                (
+                    BabelPackage::Platform(PlatformPackage::OS),
+                    BabelVersionSet::Babel(Range::singleton(os)),
+                ),
+            ]);
+            pubgrub::resolve(&index, root, BabelVersion::Babel("root".to_string()))
+                .ok()
+                .and_then(|sol| {
+                    sol.get(&BabelPackage::Opam(OpamPackage::Var("os-family".to_string())))
+                        .map(|version| version.to_string())
+                })
+        };
+        let (debian, alpine) = std::thread::scope(|s| {
+            let debian = s.spawn(|| os_family("debian"));
+            let alpine = s.spawn(|| os_family("alpine"));
+            (debian.join().unwrap(), alpine.join().unwrap())
+        });
+        assert_eq!(debian.as_deref(), Some("debian"));
+        assert_eq!(alpine.as_deref(), Some("alpine"));
+        Ok(())
+    }
 }
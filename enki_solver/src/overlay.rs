@@ -0,0 +1,181 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::hash::{BuildHasher, Hash};
+use std::path::Path;
+
+use pubgrub_alpine::index::AlpineIndex;
+use pubgrub_cargo::IndexMapLookup;
+use pubgrub_debian::index::DebianIndex;
+use pubgrub_opam::index::OpamRepository;
+
+pub use pubgrub_opam::index::OverlayPrecedence;
+
+use crate::deps::Ecosystem;
+
+/// Local package definitions layered over the base indexes.
+#[derive(Debug, Clone, Default)]
+pub struct OverlayPaths {
+    /// A directory laid out like an opam repository's `packages` directory.
+    pub opam: Option<String>,
+    /// A Debian `Packages` file.
+    pub debian: Option<String>,
+    /// An Alpine `APKINDEX` file.
+    pub alpine: Option<String>,
+    /// A crates registry index file, or a directory of them.
+    pub cargo: Option<String>,
+    pub precedence: OverlayPrecedence,
+}
+
+/// The package versions that came from an overlay rather than a base index.
+/// Opam overlays are read lazily, so `OpamRepository::is_overlay` answers for
+/// those instead.
+#[derive(Debug, Default)]
+pub struct Overlays {
+    packages: HashSet<(Ecosystem, String, String)>,
+}
+
+impl Overlays {
+    pub fn contains(&self, ecosystem: Ecosystem, name: &str, version: &str) -> bool {
+        self.packages
+            .contains(&(ecosystem, name.to_string(), version.to_string()))
+    }
+
+    fn extend<K: ToString, V: ToString>(&mut self, ecosystem: Ecosystem, versions: Vec<(K, V)>) {
+        self.packages.extend(
+            versions
+                .into_iter()
+                .map(|(name, version)| (ecosystem, name.to_string(), version.to_string())),
+        );
+    }
+}
+
+impl OverlayPaths {
+    /// Load every configured overlay and merge it into the matching base.
+    pub fn apply(
+        &self,
+        opam: OpamRepository,
+        debian: &mut DebianIndex,
+        alpine: &mut AlpineIndex,
+        cargo: &mut IndexMapLookup,
+    ) -> Result<(OpamRepository, Overlays), Box<dyn Error>> {
+        let mut overlays = Overlays::default();
+        let opam = match &self.opam {
+            Some(path) => {
+                if !Path::new(path).is_dir() {
+                    return Err(format!("Opam overlay {} is not a directory", path).into());
+                }
+                opam.with_overlay(path.clone(), self.precedence)
+            }
+            None => opam,
+        };
+        if let Some(path) = &self.debian {
+            let overlay = pubgrub_debian::parse::create_index(path.clone())?;
+            let merged = merge(&mut debian.packages, overlay.packages, self.precedence);
+            overlays.extend(Ecosystem::Debian, merged);
+        }
+        if let Some(path) = &self.alpine {
+            let overlay = pubgrub_alpine::parse::create_index(path.clone())?;
+            let merged = merge(&mut alpine.packages, overlay.packages, self.precedence);
+            overlays.extend(Ecosystem::Alpine, merged);
+        }
+        if let Some(path) = &self.cargo {
+            let overlay =
+                pubgrub_cargo::read_index::read_index_files(Path::new(path), |_| true)?;
+            let merged = merge(cargo, overlay, self.precedence);
+            overlays.extend(Ecosystem::Cargo, merged);
+        }
+        Ok((opam, overlays))
+    }
+}
+
+/// Merge the versions of each package in `overlay` into `base`, returning the
+/// package versions the overlay provided.
+pub fn merge<K, V, D, S>(
+    base: &mut HashMap<K, BTreeMap<V, D>, S>,
+    overlay: HashMap<K, BTreeMap<V, D>, S>,
+    precedence: OverlayPrecedence,
+) -> Vec<(K, V)>
+where
+    K: Eq + Hash + Clone,
+    V: Ord + Clone,
+    S: BuildHasher,
+{
+    let mut merged = Vec::new();
+    for (name, versions) in overlay {
+        let existing = base.entry(name.clone()).or_default();
+        if precedence == OverlayPrecedence::Shadow {
+            existing.clear();
+        }
+        for (version, data) in versions {
+            if precedence == OverlayPrecedence::Base && existing.contains_key(&version) {
+                continue;
+            }
+            merged.push((name.clone(), version.clone()));
+            existing.insert(version, data);
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions(entries: &[(u32, &str)]) -> BTreeMap<u32, String> {
+        entries
+            .iter()
+            .map(|(version, data)| (*version, data.to_string()))
+            .collect()
+    }
+
+    fn index(entries: &[(&str, &[(u32, &str)])]) -> HashMap<String, BTreeMap<u32, String>> {
+        entries
+            .iter()
+            .map(|(name, entries)| (name.to_string(), versions(entries)))
+            .collect()
+    }
+
+    fn base() -> HashMap<String, BTreeMap<u32, String>> {
+        index(&[("a", &[(1, "base"), (2, "base")]), ("b", &[(1, "base")])])
+    }
+
+    fn overlay() -> HashMap<String, BTreeMap<u32, String>> {
+        index(&[("a", &[(2, "overlay"), (3, "overlay")]), ("c", &[(1, "overlay")])])
+    }
+
+    #[test]
+    fn test_merge_overlay() {
+        let mut merged = base();
+        let mut from_overlay = merge(&mut merged, overlay(), OverlayPrecedence::Overlay);
+        from_overlay.sort();
+        assert_eq!(
+            merged,
+            index(&[
+                ("a", &[(1, "base"), (2, "overlay"), (3, "overlay")]),
+                ("b", &[(1, "base")]),
+                ("c", &[(1, "overlay")]),
+            ])
+        );
+        assert_eq!(
+            from_overlay,
+            vec![("a".to_string(), 2), ("a".to_string(), 3), ("c".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_merge_base() {
+        let mut merged = base();
+        let mut from_overlay = merge(&mut merged, overlay(), OverlayPrecedence::Base);
+        from_overlay.sort();
+        assert_eq!(merged["a"], versions(&[(1, "base"), (2, "base"), (3, "overlay")]));
+        assert_eq!(from_overlay, vec![("a".to_string(), 3), ("c".to_string(), 1)]);
+    }
+
+    #[test]
+    fn test_merge_shadow() {
+        let mut merged = base();
+        merge(&mut merged, overlay(), OverlayPrecedence::Shadow);
+        assert_eq!(merged["a"], versions(&[(2, "overlay"), (3, "overlay")]));
+        assert_eq!(merged["b"], versions(&[(1, "base")]));
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+
+use pubgrub_debian::deps::DebianPackage;
+use pubgrub_opam::deps::{choice, OpamPackage};
+use pubgrub_opam::index::PackageFormula;
+
+use crate::deps::{BabelPackage, Ecosystem};
+use crate::version::BabelVersion;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    /// Never select the package, or just the given version of it.
+    Forbid,
+    /// Only ever select the given version of the package.
+    Pin,
+    /// Select the package, or the given version of it, only when nothing
+    /// else satisfies the dependencies.
+    Avoid,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub kind: RuleKind,
+    pub ecosystem: Ecosystem,
+    pub name: String,
+    pub version: Option<String>,
+}
+
+/// How acceptable a candidate is to the policy. Candidates are tried in
+/// this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rank {
+    Allowed,
+    Avoided,
+    Violation,
+}
+
+/// User rules restricting what a solve may select, keyed by the package
+/// they apply to.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    rules: HashMap<(Ecosystem, String), Vec<Rule>>,
+}
+
+impl Display for RuleKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleKind::Forbid => write!(f, "forbid"),
+            RuleKind::Pin => write!(f, "pin"),
+            RuleKind::Avoid => write!(f, "avoid"),
+        }
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ecosystem = self.ecosystem.to_string().to_lowercase();
+        write!(f, "{} {}:{}", self.kind, ecosystem, self.name)?;
+        if let Some(version) = &self.version {
+            write!(f, ":{}", version)?;
+        }
+        Ok(())
+    }
+}
+
+impl Rule {
+    /// Parse a rule from its kind and an `ecosystem:package[:version]` spec.
+    /// Everything after the second colon is the version, so Debian epochs
+    /// can be given.
+    pub fn parse(kind: &str, spec: &str) -> Result<Self, String> {
+        let kind = match kind {
+            "forbid" => RuleKind::Forbid,
+            "pin" => RuleKind::Pin,
+            "avoid" => RuleKind::Avoid,
+            _ => return Err(format!("Invalid policy rule: {}", kind)),
+        };
+        let mut parts = spec.splitn(3, ':');
+        let (ecosystem, name, version) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(ecosystem), Some(name), version) if !name.is_empty() => {
+                (ecosystem, name, version)
+            }
+            _ => {
+                return Err(format!(
+                    "Invalid policy package {}, expected ecosystem:package",
+                    spec
+                ))
+            }
+        };
+        if kind == RuleKind::Pin && version.is_none() {
+            return Err(format!("Pin needs a version: {}", spec));
+        }
+        let ecosystem = ecosystem.parse()?;
+        // Crate versions are picked by the cargo index, which knows nothing
+        // of the policy's ranking
+        if kind == RuleKind::Avoid && ecosystem == Ecosystem::Cargo {
+            return Err(format!(
+                "Avoid is not supported for cargo packages, use forbid instead: {}",
+                spec
+            ));
+        }
+        Ok(Rule {
+            kind,
+            ecosystem,
+            name: name.to_string(),
+            version: version.map(|v| v.to_string()),
+        })
+    }
+
+    fn matches(&self, version: &str) -> bool {
+        self.version.as_deref().map_or(true, |v| v == version)
+    }
+}
+
+impl Policy {
+    pub fn add(&mut self, rule: Rule) {
+        self.rules
+            .entry((rule.ecosystem, rule.name.clone()))
+            .or_default()
+            .push(rule);
+    }
+
+    /// Read rules from a file with one `kind ecosystem:package[:version]` rule
+    /// per line. Blank lines and lines starting with `#` are ignored.
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let mut policy = Policy::default();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [kind, spec] => Rule::parse(kind, spec),
+                _ => Err("expected `kind ecosystem:package[:version]`".to_string()),
+            }
+            .map_err(|e| format!("{}:{}: {}", path, number + 1, e))?;
+            policy.add(rule);
+        }
+        Ok(policy)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    fn rules_for(&self, ecosystem: Ecosystem, name: &str) -> &[Rule] {
+        self.rules
+            .get(&(ecosystem, name.to_string()))
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+
+    /// Why selecting this package version breaks the policy, if it does.
+    pub fn violation(&self, package: &BabelPackage, version: &BabelVersion) -> Option<String> {
+        let (ecosystem, name) = package.base_name()?;
+        let version = version.to_string();
+        self.rules_for(ecosystem, &name)
+            .iter()
+            .find_map(|rule| match (rule.kind, &rule.version) {
+                (RuleKind::Forbid, _) if rule.matches(&version) => {
+                    Some(format!("forbidden by policy `{}`", rule))
+                }
+                (RuleKind::Pin, Some(pinned)) if *pinned != version => {
+                    Some(format!("held at {} by policy `{}`", pinned, rule))
+                }
+                _ => None,
+            })
+    }
+
+    /// How acceptable a version is. Concrete packages are ranked by the rules
+    /// on them, and the choices of the solver's proxy packages by the rules on
+    /// the packages each choice leads to.
+    pub fn rank(&self, package: &BabelPackage, version: &BabelVersion) -> Rank {
+        match package {
+            BabelPackage::Opam(OpamPackage::Lor(alternatives)) => match version {
+                BabelVersion::Opam(version) => match choice(version, alternatives.len()) {
+                    Some(i) => self.formula_rank(&alternatives[i]),
+                    None => Rank::Allowed,
+                },
+                _ => Rank::Allowed,
+            },
+            BabelPackage::Debian(DebianPackage::Proxy(_)) => {
+                self.package_rank(Ecosystem::Debian, &version.to_string())
+            }
+            _ => {
+                if self.violation(package, version).is_some() {
+                    return Rank::Violation;
+                }
+                let Some((ecosystem, name)) = package.base_name() else {
+                    return Rank::Allowed;
+                };
+                let version = version.to_string();
+                let avoided = self
+                    .rules_for(ecosystem, &name)
+                    .iter()
+                    .any(|rule| rule.kind == RuleKind::Avoid && rule.matches(&version));
+                if avoided {
+                    Rank::Avoided
+                } else {
+                    Rank::Allowed
+                }
+            }
+        }
+    }
+
+    /// The rank of a package as a whole, from the rules that apply to every
+    /// version of it.
+    fn package_rank(&self, ecosystem: Ecosystem, name: &str) -> Rank {
+        self.rules_for(ecosystem, name)
+            .iter()
+            .filter(|rule| rule.version.is_none())
+            .map(|rule| match rule.kind {
+                RuleKind::Forbid => Rank::Violation,
+                RuleKind::Avoid => Rank::Avoided,
+                RuleKind::Pin => Rank::Allowed,
+            })
+            .max()
+            .unwrap_or(Rank::Allowed)
+    }
+
+    fn formula_rank(&self, formula: &PackageFormula) -> Rank {
+        match formula {
+            PackageFormula::Base { name, .. } => self.package_rank(Ecosystem::Opam, name),
+            PackageFormula::Or(binary) => self
+                .formula_rank(&binary.lhs)
+                .min(self.formula_rank(&binary.rhs)),
+            PackageFormula::And(binary) => self
+                .formula_rank(&binary.lhs)
+                .max(self.formula_rank(&binary.rhs)),
+            _ => Rank::Allowed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pubgrub_opam::index::VersionFormula;
+    use pubgrub_opam::version::OpamVersion;
+
+    fn debian(name: &str) -> BabelPackage<'static> {
+        BabelPackage::Debian(DebianPackage::Base(name.to_string()))
+    }
+
+    fn version(version: &str) -> BabelVersion {
+        BabelVersion::Debian(pubgrub_debian::version::DebianVersion(version.to_string()))
+    }
+
+    #[test]
+    fn test_parse() {
+        let rule = Rule::parse("pin", "debian:openssh-server:1:7.9p1-10+deb10u2").unwrap();
+        assert_eq!(rule.ecosystem, Ecosystem::Debian);
+        assert_eq!(rule.name, "openssh-server");
+        assert_eq!(rule.version.as_deref(), Some("1:7.9p1-10+deb10u2"));
+        assert_eq!(rule.to_string(), "pin debian:openssh-server:1:7.9p1-10+deb10u2");
+        assert!(Rule::parse("pin", "debian:openssh-server").is_err());
+        assert!(Rule::parse("prefer", "debian:openssh-server").is_err());
+        assert!(Rule::parse("forbid", "debian").is_err());
+        assert!(Rule::parse("avoid", "cargo:serde").is_err());
+        assert!(Rule::parse("forbid", "cargo:serde").is_ok());
+    }
+
+    #[test]
+    fn test_violation() {
+        let mut policy = Policy::default();
+        policy.add(Rule::parse("forbid", "debian:systemd-sysv").unwrap());
+        policy.add(Rule::parse("pin", "debian:libssl1.1:1.1.1n-0+deb10u3").unwrap());
+        assert_eq!(
+            policy.violation(&debian("systemd-sysv"), &version("241-7")),
+            Some("forbidden by policy `forbid debian:systemd-sysv`".to_string())
+        );
+        assert_eq!(
+            policy.violation(&debian("libssl1.1"), &version("1.1.1d-0+deb10u6")),
+            Some(
+                "held at 1.1.1n-0+deb10u3 by policy `pin debian:libssl1.1:1.1.1n-0+deb10u3`"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            policy.violation(&debian("libssl1.1"), &version("1.1.1n-0+deb10u3")),
+            None
+        );
+        assert_eq!(policy.violation(&debian("libc6"), &version("2.28-10")), None);
+    }
+
+    #[test]
+    fn test_rank() {
+        let mut policy = Policy::default();
+        policy.add(Rule::parse("avoid", "opam:ocaml-variants").unwrap());
+        let base = |name: &str| PackageFormula::Base {
+            name: name.to_string(),
+            formula: VersionFormula::Lit(OpamVersion("true".to_string())),
+        };
+        let lor = BabelPackage::Opam(OpamPackage::Lor(vec![
+            base("ocaml-variants"),
+            base("ocaml-base-compiler"),
+            base("ocaml-system"),
+        ]));
+        let side = |side: &str| BabelVersion::Opam(OpamVersion(side.to_string()));
+        assert_eq!(policy.rank(&lor, &side("1")), Rank::Avoided);
+        assert_eq!(policy.rank(&lor, &side("2")), Rank::Allowed);
+        assert_eq!(policy.rank(&lor, &side("3")), Rank::Allowed);
+        assert_eq!(
+            policy.rank(
+                &BabelPackage::Opam(OpamPackage::Base("ocaml-variants".to_string())),
+                &side("5.3.0+trunk")
+            ),
+            Rank::Avoided
+        );
+    }
+
+    #[test]
+    fn test_from_file() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("enki-policy-test-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "# site policy\nforbid debian:systemd-sysv\n\navoid opam:ocaml-variants\n",
+        )?;
+        let policy = Policy::from_file(path.to_str().unwrap())?;
+        assert_eq!(policy.rules.len(), 2);
+        std::fs::write(&path, "forbid\n")?;
+        let invalid = Policy::from_file(path.to_str().unwrap());
+        std::fs::remove_file(&path)?;
+        assert!(invalid.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Mutex;
+
+use serde_json::Value;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+
+/// Install the global subscriber. Human readable output goes to stderr at a
+/// level picked by `verbosity` (or `RUST_LOG`), so stdout stays machine
+/// readable. When `trace_file` is given every event is also written to it as
+/// one JSON object per line, which `replay` can read back.
+pub fn init(verbosity: u8, trace_file: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let level = match verbosity {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+    let stderr = fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .with_filter(filter);
+    let json = match trace_file {
+        Some(path) => Some(
+            fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .with_writer(Mutex::new(File::create(path)?))
+                .with_filter(LevelFilter::TRACE),
+        ),
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(stderr)
+        .with(json)
+        .try_init()?;
+    Ok(())
+}
+
+/// Render one line of a JSON trace, indented by how deeply nested the event
+/// was in solver spans.
+pub fn format_event(event: &Value) -> String {
+    let depth = event
+        .get("spans")
+        .and_then(Value::as_array)
+        .map_or(0, |spans| spans.len());
+    let level = event.get("level").and_then(Value::as_str).unwrap_or("");
+    let mut line = format!("{}{:<5}", "  ".repeat(depth), level);
+    if let Some(span) = event.get("span") {
+        line.push_str(&format!(" [{}", field(span, "name")));
+        for key in ["package", "version"] {
+            if let Some(value) = span.get(key) {
+                line.push_str(&format!(" {}={}", key, as_text(value)));
+            }
+        }
+        line.push(']');
+    }
+    if let Some(fields) = event.get("fields").and_then(Value::as_object) {
+        if let Some(message) = fields.get("message") {
+            line.push(' ');
+            line.push_str(&as_text(message));
+        }
+        for (key, value) in fields {
+            if key != "message" {
+                line.push_str(&format!(" {}={}", key, as_text(value)));
+            }
+        }
+    }
+    line
+}
+
+/// Print a trace written by `init` to `out`, one event per line.
+pub fn replay(path: &str, out: &mut impl Write) -> Result<(), Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: Value = serde_json::from_str(&line)
+            .map_err(|e| format!("{}:{}: invalid trace event: {}", path, number + 1, e))?;
+        writeln!(out, "{}", format_event(&event))?;
+    }
+    Ok(())
+}
+
+fn field(value: &Value, key: &str) -> String {
+    value.get(key).map(as_text).unwrap_or_default()
+}
+
+fn as_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_event() {
+        let event: Value = serde_json::from_str(
+            r#"{"timestamp":"2025-01-01T00:00:00Z","level":"DEBUG",
+                "fields":{"message":"dependencies","dependencies":"(ocaml, [4.08, ∞[)"},
+                "target":"pubgrub_opam::deps",
+                "span":{"name":"get_dependencies","package":"dune","version":"3.17.2"},
+                "spans":[{"name":"resolve"},{"name":"get_dependencies","package":"dune","version":"3.17.2"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            format_event(&event),
+            "    DEBUG [get_dependencies package=dune version=3.17.2] dependencies dependencies=(ocaml, [4.08, ∞[)"
+        );
+    }
+
+    #[test]
+    fn test_format_event_without_span() {
+        let event: Value = serde_json::from_str(
+            r#"{"level":"DEBUG","fields":{"message":"prioritize","conflicts":2}}"#,
+        )
+        .unwrap();
+        assert_eq!(format_event(&event), "DEBUG prioritize conflicts=2");
+    }
+}
@@ -1,22 +1,24 @@
-use std::sync::Arc;
+use std::sync::{Arc, LazyLock, OnceLock};
 
 use cargo::util::interning::InternedString;
 use rmcp::{
     self, ServerHandler, tool, Error as McpError,
     model::{self, CallToolResult, Content, ServerCapabilities, ServerInfo, ProtocolVersion, Implementation},
 };
-use pubgrub::{DefaultStringReporter, Map, PubGrubError, Reporter, VersionSet};
+use pubgrub::{DefaultStringReporter, PubGrubError, Reporter, VersionSet};
 use enki_solver::deps::{BabelPackage, PlatformPackage};
-use enki_solver::index::BabelIndex;
+use enki_solver::index::BabelRepository;
 use enki_solver::version::BabelVersion;
 use pubgrub_cargo::names::Names as CargoPackage;
 use pubgrub_debian::deps::DebianPackage;
 use pubgrub_debian::version::DebianVersion;
 use pubgrub_opam::deps::OpamPackage;
+use pubgrub_opam::index::OpamFlags;
 use pubgrub_opam::version::OpamVersion;
 use semver::Version as CargoVersion;
 use semver_pubgrub::SemverPubgrub;
 use pubgrub_cargo::rc_semver_pubgrub::RcSemverPubgrub;
+use pubgrub_cargo::IndexMapLookup;
 use serde_json::json;
 use tokio::sync::Mutex;
 
@@ -33,22 +35,34 @@ impl BabelHandler {
         }
     }
 
-    #[tool(description = "Search for a package by name")]
+    #[tool(description = "Search opam packages by name, tag or synopsis")]
     async fn search_package(
         &self,
         #[tool(param)]
-        #[schemars(description = "The package name to search for")]
+        #[schemars(description = "Words to look for in package names, tags and synopses")]
         query: String,
+        #[tool(param)]
+        #[schemars(description = "The most packages to return, best matches first (default 20)")]
+        limit: Option<usize>,
     ) -> Result<CallToolResult, McpError> {
-        // TODO: Implement actual package search using babel repositories
-        let result = json!({
-            "status": "not_implemented",
-            "message": "Package search not yet implemented",
-            "query": query
+        // The first search reads every opam file to build the index, so run
+        // it off the async runtime like a solve.
+        let searched = tokio::task::spawn_blocking(move || {
+            search_packages(&query, limit.unwrap_or(20))
         })
-        .to_string();
-
-        Ok(CallToolResult::success(vec![Content::text(result)]))
+        .await
+        .unwrap_or_else(|e| Err(format!("Search task failed: {}", e)));
+        match searched {
+            Ok(result) => Ok(CallToolResult::success(vec![Content::text(result)])),
+            Err(e) => {
+                tracing::warn!("Failed to search packages: {}", e);
+                Err(McpError::new(
+                    model::ErrorCode::INTERNAL_ERROR,
+                    "search_error",
+                    Some(json!({ "error": e })),
+                ))
+            }
+        }
     }
 
     #[tool(description = "Resolve dependencies for a package")]
@@ -66,8 +80,40 @@ impl BabelHandler {
         #[tool(param)]
         #[schemars(description = "The platform to use (alpine, debian)")]
         platform: Option<String>,
+        #[tool(param)]
+        #[schemars(description = "Include the opam package's test dependencies (with-test)")]
+        with_test: Option<bool>,
+        #[tool(param)]
+        #[schemars(description = "Include the opam package's documentation dependencies (with-doc)")]
+        with_doc: Option<bool>,
+        #[tool(param)]
+        #[schemars(description = "Include the opam package's development setup dependencies (with-dev-setup)")]
+        with_dev_setup: Option<bool>,
+        #[tool(param)]
+        #[schemars(description = "Opam packages pinned to a development source, for which dev holds")]
+        dev: Option<Vec<String>>,
     ) -> Result<CallToolResult, McpError> {
-        match resolve_package_dependencies(&ecosystem, &package, &version, platform.as_deref()) {
+        let flags = OpamFlags {
+            with_test: with_test.unwrap_or(false),
+            with_doc: with_doc.unwrap_or(false),
+            with_dev_setup: with_dev_setup.unwrap_or(false),
+            dev: dev.unwrap_or_default().into_iter().collect(),
+            ..OpamFlags::default()
+        };
+        // Solving is CPU bound, so run it off the async runtime. The loaded
+        // repository is shared, so concurrent requests solve in parallel.
+        let resolved = tokio::task::spawn_blocking(move || {
+            resolve_package_dependencies(
+                &ecosystem,
+                &package,
+                &version,
+                platform.as_deref(),
+                flags,
+            )
+        })
+        .await
+        .unwrap_or_else(|e| Err(format!("Resolution task failed: {}", e)));
+        match resolved {
             Ok(result) => Ok(CallToolResult::success(vec![Content::text(result)])),
             Err(e) => {
                 tracing::warn!("Failed to resolve dependencies: {}", e);
@@ -95,12 +141,88 @@ impl ServerHandler for BabelHandler {
     }
 }
 
+// let create_filter = |_name: &str| true;
+// let version_filter = |version: &pubgrub_cargo::index_data::Version| !version.yanked;
+// let data = pubgrub_cargo::read_index::read_index(&crates_index, create_filter, version_filter);
+static CARGO_DATA: LazyLock<IndexMapLookup> = LazyLock::new(IndexMapLookup::default);
+
+static REPOSITORY: OnceLock<BabelRepository<'static>> = OnceLock::new();
+
+// Held while the repositories are being loaded, so concurrent first requests load them once
+static REPOSITORY_INIT: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+// Load the repositories on first use and share them between all requests
+fn repository() -> Result<&'static BabelRepository<'static>, String> {
+    if let Some(repository) = REPOSITORY.get() {
+        return Ok(repository);
+    }
+    let _init = REPOSITORY_INIT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(repository) = REPOSITORY.get() {
+        return Ok(repository);
+    }
+    let opam_repository = pubgrub_opam::index::OpamRepository::new(
+        "pubgrub_opam/opam-repository/packages".to_string(),
+    );
+    let debian_index = match pubgrub_debian::parse::create_index(
+        "pubgrub_debian/repositories/buster/Packages".to_string(),
+    ) {
+        Ok(idx) => idx,
+        Err(e) => return Err(format!("Failed to create Debian index: {}", e)),
+    };
+    let alpine_index = match pubgrub_alpine::parse::create_index(
+        "pubgrub_alpine/repositories/3.20/APKINDEX".to_string(),
+    ) {
+        Ok(idx) => idx,
+        Err(e) => return Err(format!("Failed to create Alpine index: {}", e)),
+    };
+
+    // Cargo index setup
+    if let Err(e) = crates_index::GitIndex::with_path(
+        "pubgrub_cargo/index",
+        "https://github.com/rust-lang/crates.io-index",
+    ) {
+        return Err(format!("Failed to create Cargo index: {}", e));
+    }
+
+    Ok(REPOSITORY.get_or_init(|| {
+        BabelRepository::new(opam_repository, debian_index, alpine_index, &CARGO_DATA)
+    }))
+}
+
+// Search the opam repository's index, built once and shared between requests
+fn search_packages(query: &str, limit: usize) -> Result<String, String> {
+    let index = repository()?.opam.search_index();
+    let packages: Vec<_> = index
+        .search(query)
+        .into_iter()
+        .take(limit)
+        .map(|package| {
+            json!({
+                "ecosystem": "opam",
+                "name": package.name,
+                "version": package.version.to_string(),
+                "synopsis": package.synopsis,
+                "tags": package.tags,
+                "dependents": index.reverse_dependencies(&package.name).len()
+            })
+        })
+        .collect();
+    Ok(json!({
+        "query": query,
+        "packages": packages
+    })
+    .to_string())
+}
+
 // Attempt to resolve dependencies using the actual Babel code
 fn resolve_package_dependencies(
     ecosystem: &str,
     package: &str,
     version: &str,
     platform: Option<&str>,
+    flags: OpamFlags,
 ) -> Result<String, String> {
     use pubgrub::Range;
     use enki_solver::version::BabelVersionSet;
@@ -211,39 +333,10 @@ fn resolve_package_dependencies(
         }
     }
 
-    // Set up the repositories
-    let opam_index =
-        pubgrub_opam::index::OpamIndex::new("pubgrub_opam/opam-repository/packages".to_string());
-    let debian_index = match pubgrub_debian::parse::create_index(
-        "pubgrub_debian/repositories/buster/Packages".to_string(),
-    ) {
-        Ok(idx) => idx,
-        Err(e) => return Err(format!("Failed to create Debian index: {}", e)),
-    };
-    let alpine_index = match pubgrub_alpine::parse::create_index(
-        "pubgrub_alpine/repositories/3.20/APKINDEX".to_string(),
-    ) {
-        Ok(idx) => idx,
-        Err(e) => return Err(format!("Failed to create Alpine index: {}", e)),
-    };
-
-    // Cargo index setup
-    let _crates_index = match crates_index::GitIndex::with_path(
-        "pubgrub_cargo/index",
-        "https://github.com/rust-lang/crates.io-index",
-    ) {
-        Ok(idx) => idx,
-        Err(e) => return Err(format!("Failed to create Cargo index: {}", e)),
-    };
-
-    // let create_filter = |_name: &str| true;
-    // let version_filter = |version: &pubgrub_cargo::index_data::Version| !version.yanked;
-    // let data = pubgrub_cargo::read_index::read_index(&crates_index, create_filter, version_filter);
-    let data = Map::default();
-    let cargo_index = pubgrub_cargo::Index::new(&data);
-
-    // Create the Babel index
-    let index = BabelIndex::new(opam_index, debian_index, alpine_index, cargo_index);
+    let index = repository()?.index().with_opam_flags(OpamFlags {
+        roots: babel_package.opam_roots().into_iter().collect(),
+        ..flags
+    });
 
     // Resolve dependencies
     let sol = match pubgrub::resolve(&index, babel_package.clone(), babel_version.clone()) {
@@ -264,7 +357,13 @@ fn resolve_package_dependencies(
                 deps.push(json!({
                     "ecosystem": "opam",
                     "name": name,
-                    "version": ver.to_string()
+                    "version": ver.to_string(),
+                    "overlay": index.is_overlay(pkg, ver),
+                    "avoided": index
+                        .opam
+                        .repository
+                        .avoided(name, &ver.to_string())
+                        .map(|avoidance| avoidance.to_string())
                 }));
             }
             // BabelPackage::Opam(OpamPackage::Var(name)) => {
@@ -278,7 +377,8 @@ fn resolve_package_dependencies(
                 deps.push(json!({
                     "ecosystem": "debian",
                     "name": name,
-                    "version": ver.to_string()
+                    "version": ver.to_string(),
+                    "overlay": index.is_overlay(pkg, ver)
                 }));
             }
             BabelPackage::Alpine(pubgrub_alpine::deps::AlpinePackage::Base(name)) => {
@@ -287,7 +387,8 @@ fn resolve_package_dependencies(
                     deps.push(json!({
                         "ecosystem": "alpine",
                         "name": name,
-                        "version": ver.to_string()
+                        "version": ver.to_string(),
+                        "overlay": index.is_overlay(pkg, ver)
                     }));
                 }
             }
@@ -295,7 +396,8 @@ fn resolve_package_dependencies(
                 deps.push(json!({
                     "ecosystem": "cargo",
                     "name": name.to_string(),
-                    "version": ver.to_string()
+                    "version": ver.to_string(),
+                    "overlay": index.is_overlay(pkg, ver)
                 }));
             }
             // BabelPackage::Platform(PlatformPackage::OS) => {
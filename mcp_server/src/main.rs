@@ -22,6 +22,7 @@ async fn main() -> Result<()> {
                 // If RUST_LOG is not set, use these defaults
                 "babel_mcp_server=debug,rmcp=trace,info".parse().unwrap()
             }))
+        .with_writer(std::io::stderr)
         .with_target(true)
         .with_thread_ids(true)
         .with_file(true)
@@ -32,11 +33,11 @@ async fn main() -> Result<()> {
     let file_appender = RollingFileAppender::new(Rotation::DAILY, "logs", "babel-mcp-server.log");
     let _ = std::fs::create_dir_all("logs");
     
-    // Print startup banner
-    println!("\n===========================================================");
-    println!("🚀 Starting Babel MCP Server");
-    println!("📝 Log file: logs/babel-mcp-server.log");
-    println!("===========================================================\n");
+    // Print startup banner to stderr, stdout is the MCP transport in CLI mode
+    eprintln!("\n===========================================================");
+    eprintln!("🚀 Starting Babel MCP Server");
+    eprintln!("📝 Log file: logs/babel-mcp-server.log");
+    eprintln!("===========================================================\n");
 
     // Parse command line arguments
     let args: Vec<String> = env::args().collect();
@@ -40,21 +40,11 @@ impl AlpineIndex {
             AlpinePackage::Root(_) => vec![AlpineVersion("".to_string())],
             AlpinePackage::Base(pkg) => self.available_versions(pkg),
         };
-        if self.version_debug.get() {
-            print!("versions of {}", package);
-            if versions.len() > 0 {
-                print!(": ")
-            }
-            let mut first = true;
-            for version in versions.clone() {
-                if !first {
-                    print!(", ");
-                }
-                print!("{}", version);
-                first = false;
-            }
-            println!();
-        };
+        tracing::trace!(
+            package = %package,
+            versions = ?versions,
+            "list_versions"
+        );
         versions.into_iter()
     }
 }
@@ -74,10 +64,18 @@ impl DependencyProvider for AlpineIndex {
 
     fn prioritize(
         &self,
-        _package: &Self::P,
-        _range: &Self::VS,
-        _package_conflicts_counts: &pubgrub::PackageResolutionStatistics,
+        package: &Self::P,
+        range: &Self::VS,
+        package_conflicts_counts: &pubgrub::PackageResolutionStatistics,
     ) -> Self::Priority {
+        if package_conflicts_counts.conflict_count() > 0 {
+            tracing::debug!(
+                package = %package,
+                range = %range,
+                conflicts = package_conflicts_counts.conflict_count(),
+                "prioritize"
+            );
+        }
         1
     }
 
@@ -86,10 +84,17 @@ impl DependencyProvider for AlpineIndex {
         package: &Self::P,
         range: &Self::VS,
     ) -> Result<Option<Self::V>, Self::Err> {
-        Ok(self
+        let version = self
             .list_versions(package)
             .filter(|v| range.contains(v))
-            .next())
+            .next();
+        tracing::debug!(
+            package = %package,
+            range = %range,
+            version = version.as_ref().map(tracing::field::display),
+            "choose_version"
+        );
+        Ok(version)
     }
 
     fn get_dependencies(
@@ -97,6 +102,9 @@ impl DependencyProvider for AlpineIndex {
         package: &AlpinePackage,
         version: &AlpineVersion,
     ) -> Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err> {
+        let _span =
+            tracing::debug_span!("get_dependencies", package = %package, version = %version)
+                .entered();
         match package {
             AlpinePackage::Root(deps) => {
                 Ok(Dependencies::Available(deps.into_iter().cloned().collect()))
@@ -111,21 +119,7 @@ impl DependencyProvider for AlpineIndex {
                     Some(d) => d,
                 };
                 let deps = from_dependencies(dependencies);
-                if self.debug.get() {
-                    print!("({}, {})", package, version);
-                    if deps.len() > 0 {
-                        print!(" -> ")
-                    }
-                    let mut first = true;
-                    for (package, range) in deps.clone() {
-                        if !first {
-                            print!(", ");
-                        }
-                        print!("({}, {})", package, range);
-                        first = false;
-                    }
-                    println!();
-                }
+                tracing::debug!(dependencies = ?deps, "dependencies");
                 Ok(Dependencies::Available(deps))
             }
         }
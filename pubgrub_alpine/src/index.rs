@@ -1,6 +1,5 @@
 use core::fmt::Display;
 use pubgrub::{Map, Range};
-use std::cell::Cell;
 use std::collections::BTreeMap;
 use std::hash::{Hash, Hasher};
 
@@ -10,8 +9,6 @@ pub type PackageName = String;
 
 pub struct AlpineIndex {
     pub packages: Map<PackageName, BTreeMap<AlpineVersion, Vec<Dependency>>>,
-    pub debug: Cell<bool>,
-    pub version_debug: Cell<bool>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -49,8 +46,6 @@ impl AlpineIndex {
     pub fn new() -> Self {
         Self {
             packages: Map::default(),
-            debug: false.into(),
-            version_debug: false.into(),
         }
     }
 
@@ -71,14 +66,6 @@ impl AlpineIndex {
             .insert(version, dependencies);
     }
 
-    pub fn set_debug(&self, flag: bool) {
-        self.debug.set(flag);
-    }
-
-    pub fn set_version_debug(&self, flag: bool) {
-        self.version_debug.set(flag);
-    }
-
     pub fn package_count(&self) -> usize {
         self.packages.len()
     }
@@ -16,7 +16,6 @@ fn solve_repo(
     repo: &str,
 ) -> Result<SelectedDependencies<AlpineIndex>, Box<dyn Error>> {
     let index = create_index(repo.to_string())?;
-    index.set_debug(true);
 
     let sol: SelectedDependencies<AlpineIndex> = match pubgrub::resolve(&index, pkg, version) {
         Ok(sol) => Ok(sol),
@@ -28,8 +27,6 @@ fn solve_repo(
         Err(err) => panic!("{:?}", err),
     }?;
 
-    index.set_debug(false);
-
     fn get_resolved_deps<'a>(
         index: &'a AlpineIndex,
         sol: &'a SelectedDependencies<AlpineIndex>,
@@ -111,6 +108,10 @@ fn solve_repo(
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_writer(std::io::stderr)
+        .init();
     let _ = solve_repo(
         AlpinePackage::from_str("openssh-server").unwrap(),
         "9.9_p2-r0".parse::<AlpineVersion>().unwrap(),
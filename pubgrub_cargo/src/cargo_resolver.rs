@@ -31,7 +31,8 @@ impl<'a> Registry for crate::Index<'a> {
                     if let Some((_, summary)) = by_name.get(past_ver) {
                         if dep.matches(&summary) {
                             self.dependencies
-                                .borrow_mut()
+                                .lock()
+                                .unwrap()
                                 .insert((dep.package_name(), summary.version().clone()));
                             f(IndexSummary::Candidate(summary.clone()));
                         }
@@ -47,7 +48,8 @@ impl<'a> Registry for crate::Index<'a> {
                     };
                     if matched {
                         self.dependencies
-                            .borrow_mut()
+                            .lock()
+                            .unwrap()
                             .insert((dep.package_name(), summary.version().clone()));
                         f(IndexSummary::Candidate(summary.clone()));
                     }
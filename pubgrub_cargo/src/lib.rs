@@ -1,5 +1,4 @@
 use std::{
-    cell::{Cell, RefCell},
     cmp::Reverse,
     collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     error::Error,
@@ -7,6 +6,10 @@ use std::{
     hash::{Hash, Hasher},
     io::{BufWriter, Write},
     ops::Bound,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
     time::Instant,
 };
 
@@ -47,21 +50,20 @@ static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 const TIME_MAKE_FILE: f32 = 40.0;
 const TIME_CUT_OFF: f32 = TIME_MAKE_FILE * 4.0;
 
-type IndexMapLookup = HashMap<
+pub type IndexMapLookup = HashMap<
     InternedString,
     BTreeMap<semver::Version, (index_data::Version, Summary)>,
     rustc_hash::FxBuildHasher,
 >;
 
-#[derive(Clone)]
 pub struct Index<'c> {
     crates: &'c IndexMapLookup,
     past_result:
         Option<HashMap<InternedString, BTreeSet<semver::Version>, rustc_hash::FxBuildHasher>>,
-    dependencies: RefCell<HashSet<(InternedString, semver::Version), rustc_hash::FxBuildHasher>>,
-    pubgrub_dependencies: RefCell<HashSet<(Names<'c>, semver::Version), rustc_hash::FxBuildHasher>>,
-    start: Cell<Instant>,
-    should_cancel_call_count: Cell<u64>,
+    dependencies: Mutex<HashSet<(InternedString, semver::Version), rustc_hash::FxBuildHasher>>,
+    pubgrub_dependencies: Mutex<HashSet<(Names<'c>, semver::Version), rustc_hash::FxBuildHasher>>,
+    start: Instant,
+    should_cancel_call_count: AtomicU64,
 }
 
 impl<'c> Index<'c> {
@@ -71,29 +73,29 @@ impl<'c> Index<'c> {
             past_result: None,
             pubgrub_dependencies: Default::default(),
             dependencies: Default::default(),
-            start: Cell::new(Instant::now()),
-            should_cancel_call_count: Cell::new(0),
+            start: Instant::now(),
+            should_cancel_call_count: AtomicU64::new(0),
         }
     }
 
     fn reset(&mut self) {
         self.past_result = None;
-        self.dependencies.get_mut().clear();
-        self.pubgrub_dependencies.get_mut().clear();
+        self.dependencies.get_mut().unwrap().clear();
+        self.pubgrub_dependencies.get_mut().unwrap().clear();
         self.reset_time();
     }
 
     fn reset_time(&mut self) {
         *self.should_cancel_call_count.get_mut() = 0;
-        *self.start.get_mut() = Instant::now();
+        self.start = Instant::now();
     }
 
     fn duration(&self) -> f32 {
-        self.start.get().elapsed().as_secs_f32()
+        self.start.elapsed().as_secs_f32()
     }
 
     fn should_cancel_call_count(&self) -> u64 {
-        self.should_cancel_call_count.get()
+        self.should_cancel_call_count.load(Ordering::Relaxed)
     }
 
     #[cfg(test)]
@@ -101,7 +103,8 @@ impl<'c> Index<'c> {
         let mut dependency_provider: BTreeMap<_, BTreeMap<_, Result<_, _>>> = BTreeMap::new();
         let deps = self
             .pubgrub_dependencies
-            .borrow()
+            .lock()
+            .unwrap()
             .iter()
             .cloned()
             .collect_vec();
@@ -143,7 +146,7 @@ impl<'c> Index<'c> {
     }
 
     fn make_index_ron_data(&self) -> Vec<index_data::Version> {
-        let deps = self.dependencies.borrow();
+        let deps = self.dependencies.lock().unwrap();
 
         let name_vers: BTreeSet<_> = deps.iter().map(|(n, v)| (n.as_str(), v)).collect();
 
@@ -154,7 +157,7 @@ impl<'c> Index<'c> {
     }
 
     fn make_index_ron_file(&self) {
-        let grub_deps = self.pubgrub_dependencies.borrow();
+        let grub_deps = self.pubgrub_dependencies.lock().unwrap();
 
         let name = grub_deps
             .iter()
@@ -654,7 +657,8 @@ impl<'c> DependencyProvider for Index<'c> {
         version: &semver::Version,
     ) -> Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err> {
         self.pubgrub_dependencies
-            .borrow_mut()
+            .lock()
+            .unwrap()
             .insert((package.clone(), version.clone()));
         Ok(match package {
             &Names::Bucket(name, _major, all_features) => {
@@ -662,7 +666,8 @@ impl<'c> DependencyProvider for Index<'c> {
                     return Err(SomeError);
                 };
                 self.dependencies
-                    .borrow_mut()
+                    .lock()
+                    .unwrap()
                     .insert((index_ver.name, version.clone()));
                 if index_ver.yanked {
                     return Ok(Dependencies::Unavailable("yanked: Bucket".into()));
@@ -725,7 +730,8 @@ impl<'c> DependencyProvider for Index<'c> {
                     return Err(SomeError);
                 };
                 self.dependencies
-                    .borrow_mut()
+                    .lock()
+                    .unwrap()
                     .insert((index_ver.name, version.clone()));
                 if index_ver.yanked {
                     return Ok(Dependencies::Unavailable(
@@ -793,7 +799,8 @@ impl<'c> DependencyProvider for Index<'c> {
                     return Err(SomeError);
                 };
                 self.dependencies
-                    .borrow_mut()
+                    .lock()
+                    .unwrap()
                     .insert((index_ver.name, version.clone()));
                 if index_ver.yanked {
                     return Ok(Dependencies::Unavailable(
@@ -914,9 +921,8 @@ impl<'c> DependencyProvider for Index<'c> {
     }
 
     fn should_cancel(&self) -> Result<(), Self::Err> {
-        let calls = self.should_cancel_call_count.get();
-        self.should_cancel_call_count.set(calls + 1);
-        if calls % 64 == 0 && TIME_CUT_OFF < self.start.get().elapsed().as_secs_f32() {
+        let calls = self.should_cancel_call_count.fetch_add(1, Ordering::Relaxed);
+        if calls % 64 == 0 && TIME_CUT_OFF < self.start.elapsed().as_secs_f32() {
             return Err(SomeError);
         }
         Ok(())
@@ -998,7 +1004,7 @@ pub fn process_crate_version(
         pub_cyclic_package_dependency = Some(cyclic_package_dependency);
         pub_time = dp.duration();
         should_cancel_call_count = dp.should_cancel_call_count();
-        get_dependencies_call_count = dp.pubgrub_dependencies.borrow().len();
+        get_dependencies_call_count = dp.pubgrub_dependencies.lock().unwrap().len();
         match res.as_ref().unwrap().as_ref() {
             Ok(map) => {
                 if !dp.check(root.clone(), &map) {
@@ -1,4 +1,4 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, sync::Arc};
 
 use pubgrub::VersionSet;
 use semver_pubgrub::SemverPubgrub;
@@ -6,13 +6,13 @@ use semver_pubgrub::SemverPubgrub;
 #[derive(Debug, PartialEq, Eq, Clone, Hash, serde::Deserialize, serde::Serialize)]
 #[serde(transparent)]
 pub struct RcSemverPubgrub {
-    pub(crate) inner: Rc<SemverPubgrub<semver::Version>>,
+    pub(crate) inner: Arc<SemverPubgrub<semver::Version>>,
 }
 
 impl RcSemverPubgrub {
     pub fn new(inner: SemverPubgrub<semver::Version>) -> Self {
         Self {
-            inner: Rc::new(inner),
+            inner: Arc::new(inner),
         }
     }
 }
@@ -25,7 +25,7 @@ impl std::fmt::Display for RcSemverPubgrub {
 
 thread_local! {
     static ARC_SEMVER_PUBGRUB_EMPTY: RefCell<RcSemverPubgrub> = RefCell::new(RcSemverPubgrub {
-        inner: Rc::new(SemverPubgrub::<semver::Version>::empty()),
+        inner: Arc::new(SemverPubgrub::<semver::Version>::empty()),
     });
 
     static ARC_SEMVER_PUBGRUB_SINGLETON: RefCell<HashMap<semver::Version, RcSemverPubgrub>> = RefCell::new(HashMap::default());
@@ -51,7 +51,7 @@ impl VersionSet for RcSemverPubgrub {
     }
 
     fn intersection(&self, other: &Self) -> Self {
-        if Rc::ptr_eq(&self.inner, &other.inner) {
+        if Arc::ptr_eq(&self.inner, &other.inner) {
             return self.clone();
         }
         RcSemverPubgrub::new(self.inner.intersection(&other.inner))
@@ -66,21 +66,21 @@ impl VersionSet for RcSemverPubgrub {
     }
 
     fn union(&self, other: &Self) -> Self {
-        if Rc::ptr_eq(&self.inner, &other.inner) {
+        if Arc::ptr_eq(&self.inner, &other.inner) {
             return self.clone();
         }
         RcSemverPubgrub::new(self.inner.union(&other.inner))
     }
 
     fn is_disjoint(&self, other: &Self) -> bool {
-        if Rc::ptr_eq(&self.inner, &other.inner) {
+        if Arc::ptr_eq(&self.inner, &other.inner) {
             return false;
         }
         self.inner.is_disjoint(&other.inner)
     }
 
     fn subset_of(&self, other: &Self) -> bool {
-        if Rc::ptr_eq(&self.inner, &other.inner) {
+        if Arc::ptr_eq(&self.inner, &other.inner) {
             return true;
         }
         self.inner.subset_of(&other.inner)
@@ -1,3 +1,6 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
 use std::time::Instant;
 
 use cargo::{core::Summary, util::interning::InternedString};
@@ -40,6 +43,49 @@ pub fn read_index(
     crates
 }
 
+/// Read crates from files in the registry index format, one JSON object per
+/// version and line, as found in a checkout of a registry index. `path` is
+/// either one such file or a directory searched recursively.
+pub fn read_index_files(
+    path: &Path,
+    version_filter: impl Fn(&index_data::Version) -> bool,
+) -> Result<IndexMapLookup, Box<dyn Error>> {
+    let mut crates = IndexMapLookup::default();
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+        // skip .git and the registry's config.json
+        if matches!(file_name.as_deref(), Some(n) if n.starts_with('.') || n == "config.json") {
+            continue;
+        }
+        if path.is_dir() {
+            for entry in fs::read_dir(&path)? {
+                pending.push(entry?.path());
+            }
+            continue;
+        }
+        let crt = crates_index::Crate::from_slice(&fs::read(&path)?)
+            .map_err(|e| format!("Error parsing {}: {}", path.display(), e))?;
+        let name: InternedString = crt.name().into();
+        for v in crt.versions() {
+            let v: index_data::Version = v
+                .try_into()
+                .map_err(|e| format!("Error parsing {} {}: {}", name, v.version(), e))?;
+            if !version_filter(&v) {
+                continue;
+            }
+            let s: Summary = (&v)
+                .try_into()
+                .map_err(|e| format!("Error parsing {} {}: {}", name, v.vers, e))?;
+            crates
+                .entry(name)
+                .or_default()
+                .insert((*v.vers).clone(), (v, s));
+        }
+    }
+    Ok(crates)
+}
+
 #[cfg(test)]
 pub fn read_test_file(iter: impl IntoIterator<Item = index_data::Version>) -> IndexMapLookup {
     let mut deps = IndexMapLookup::default();
@@ -48,21 +48,11 @@ impl DebianIndex {
                 .map(|dep| DebianVersion(dep.name))
                 .collect(),
         };
-        if self.version_debug.get() {
-            print!("versions of {}", package);
-            if versions.len() > 0 {
-                print!(": ")
-            }
-            let mut first = true;
-            for version in versions.clone() {
-                if !first {
-                    print!(", ");
-                }
-                print!("{}", version);
-                first = false;
-            }
-            println!();
-        };
+        tracing::trace!(
+            package = %package,
+            versions = ?versions,
+            "list_versions"
+        );
         versions.into_iter()
     }
 }
@@ -82,10 +72,18 @@ impl DependencyProvider for DebianIndex {
 
     fn prioritize(
         &self,
-        _package: &Self::P,
-        _range: &Self::VS,
-        _package_conflicts_counts: &pubgrub::PackageResolutionStatistics,
+        package: &Self::P,
+        range: &Self::VS,
+        package_conflicts_counts: &pubgrub::PackageResolutionStatistics,
     ) -> Self::Priority {
+        if package_conflicts_counts.conflict_count() > 0 {
+            tracing::debug!(
+                package = %package,
+                range = %range,
+                conflicts = package_conflicts_counts.conflict_count(),
+                "prioritize"
+            );
+        }
         1
     }
 
@@ -94,10 +92,17 @@ impl DependencyProvider for DebianIndex {
         package: &Self::P,
         range: &Self::VS,
     ) -> Result<Option<Self::V>, Self::Err> {
-        Ok(self
+        let version = self
             .list_versions(package)
             .filter(|v| range.contains(v))
-            .next())
+            .next();
+        tracing::debug!(
+            package = %package,
+            range = %range,
+            version = version.as_ref().map(tracing::field::display),
+            "choose_version"
+        );
+        Ok(version)
     }
 
     fn get_dependencies(
@@ -105,6 +110,9 @@ impl DependencyProvider for DebianIndex {
         package: &DebianPackage,
         version: &DebianVersion,
     ) -> Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err> {
+        let _span =
+            tracing::debug_span!("get_dependencies", package = %package, version = %version)
+                .entered();
         match package {
             DebianPackage::Root(deps) => {
                 Ok(Dependencies::Available(deps.into_iter().cloned().collect()))
@@ -119,40 +127,12 @@ impl DependencyProvider for DebianIndex {
                     Some(d) => d,
                 };
                 let deps = from_dependencies(dependencies);
-                if self.debug.get() {
-                    print!("({}, {})", package, version);
-                    if deps.len() > 0 {
-                        print!(" -> ")
-                    }
-                    let mut first = true;
-                    for (package, range) in deps.clone() {
-                        if !first {
-                            print!(", ");
-                        }
-                        print!("({}, {})", package, range);
-                        first = false;
-                    }
-                    println!();
-                }
+                tracing::debug!(dependencies = ?deps, "dependencies");
                 Ok(Dependencies::Available(deps))
             }
             DebianPackage::Proxy(dependency) => {
                 let deps = from_proxy(dependency, version);
-                if self.debug.get() {
-                    print!("({}, {})", package, version);
-                    if deps.len() > 0 {
-                        print!(" -> ")
-                    }
-                    let mut first = true;
-                    for (package, range) in deps.clone() {
-                        if !first {
-                            print!(", ");
-                        }
-                        print!("({}, {})", package, range);
-                        first = false;
-                    }
-                    println!();
-                }
+                tracing::debug!(dependencies = ?deps, "dependencies");
                 Ok(Dependencies::Available(deps))
             }
         }
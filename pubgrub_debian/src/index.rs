@@ -1,6 +1,5 @@
 use core::fmt::Display;
 use pubgrub::{Map, Range};
-use std::cell::Cell;
 use std::collections::BTreeMap;
 use std::hash::{Hash, Hasher};
 
@@ -10,8 +9,6 @@ pub type PackageName = String;
 
 pub struct DebianIndex {
     pub packages: Map<PackageName, BTreeMap<DebianVersion, Vec<Dependency>>>,
-    pub debug: Cell<bool>,
-    pub version_debug: Cell<bool>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -59,8 +56,6 @@ impl DebianIndex {
     pub fn new() -> Self {
         Self {
             packages: Map::default(),
-            debug: false.into(),
-            version_debug: false.into(),
         }
     }
 
@@ -80,12 +75,4 @@ impl DebianIndex {
             .or_default()
             .insert(version, dependencies);
     }
-
-    pub fn set_debug(&self, flag: bool) {
-        self.debug.set(flag);
-    }
-
-    pub fn set_version_debug(&self, flag: bool) {
-        self.version_debug.set(flag);
-    }
 }
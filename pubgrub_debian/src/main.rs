@@ -16,7 +16,6 @@ fn solve_repo(
     repo: &str,
 ) -> Result<SelectedDependencies<DebianIndex>, Box<dyn Error>> {
     let index = create_index(repo.to_string())?;
-    index.set_debug(true);
 
     let sol: SelectedDependencies<DebianIndex> = match pubgrub::resolve(&index, pkg, version) {
         Ok(sol) => Ok(sol),
@@ -28,8 +27,6 @@ fn solve_repo(
         Err(err) => panic!("{:?}", err),
     }?;
 
-    index.set_debug(false);
-
     fn get_resolved_deps<'a>(
         index: &'a DebianIndex,
         sol: &'a SelectedDependencies<DebianIndex>,
@@ -119,6 +116,10 @@ fn solve_repo(
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_writer(std::io::stderr)
+        .init();
     let _ = solve_repo(
         DebianPackage::from_str("openssh-server").unwrap(),
         "1:7.9p1-10+deb10u2".parse::<DebianVersion>().unwrap(),
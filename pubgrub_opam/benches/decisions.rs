@@ -0,0 +1,131 @@
+//! Compares solves with and without normalising dependency formulas: the
+//! decisions the solver makes, the proxy packages left in the solution, and
+//! how long each solve takes.
+//!
+//!     cargo bench --bench decisions -- [REPOSITORY] [NAME.VERSION...]
+
+use pubgrub::{Dependencies, DependencyProvider, PackageResolutionStatistics, Range};
+use pubgrub_opam::deps::OpamPackage;
+use pubgrub_opam::index::{OpamFlags, OpamIndex, OpamRepository};
+use pubgrub_opam::version::OpamVersion;
+use std::cell::Cell;
+use std::convert::Infallible;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Instant;
+
+const REPOSITORY: &str = "./opam-repository/packages";
+const PACKAGES: &[&str] = &["dune.3.17.2", "conf-gmp.4"];
+
+// counts every version the solver picks
+struct Counting<'a> {
+    index: &'a OpamIndex,
+    decisions: Cell<usize>,
+}
+
+impl DependencyProvider for Counting<'_> {
+    type P = OpamPackage;
+
+    type V = OpamVersion;
+
+    type VS = Range<OpamVersion>;
+
+    type M = String;
+
+    type Err = Infallible;
+
+    type Priority = u8;
+
+    fn prioritize(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+        package_conflicts_counts: &PackageResolutionStatistics,
+    ) -> Self::Priority {
+        self.index
+            .prioritize(package, range, package_conflicts_counts)
+    }
+
+    fn choose_version(
+        &self,
+        package: &Self::P,
+        range: &Self::VS,
+    ) -> Result<Option<Self::V>, Self::Err> {
+        self.decisions.set(self.decisions.get() + 1);
+        self.index.choose_version(package, range)
+    }
+
+    fn get_dependencies(
+        &self,
+        package: &Self::P,
+        version: &Self::V,
+    ) -> Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err> {
+        self.index.get_dependencies(package, version)
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // cargo passes `--bench` along to benchmarks without a harness
+    let args: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    let repository = args.first().map_or(REPOSITORY, |repository| repository);
+    let packages: Vec<&str> = match args.get(1..) {
+        Some(packages) if !packages.is_empty() => packages.iter().map(|p| p.as_str()).collect(),
+        _ => PACKAGES.to_vec(),
+    };
+    let repository = Arc::new(OpamRepository::new(repository.to_string()));
+    // scan the repository up front, so that the first solve isn't charged for it
+    repository.domains();
+
+    println!(
+        "{:<24} {:>10} {:>10} {:>8} {:>10}",
+        "package", "normalize", "decisions", "proxies", "millis"
+    );
+    for package in packages {
+        let (name, version) = package
+            .split_once('.')
+            .ok_or_else(|| format!("Expected NAME.VERSION, got {}", package))?;
+        for normalize in [false, true] {
+            let index = OpamIndex::from_repository(repository.clone())
+                .with_flags(OpamFlags::new([name.to_string()]))
+                .with_normalize(normalize);
+            let provider = Counting {
+                index: &index,
+                decisions: Cell::new(0),
+            };
+            let start = Instant::now();
+            let sol = pubgrub::resolve(
+                &provider,
+                OpamPackage::Base(name.to_string()),
+                OpamVersion(version.to_string()),
+            );
+            let millis = start.elapsed().as_millis();
+            let proxies = match sol {
+                Ok(sol) => sol
+                    .keys()
+                    .filter(|package| {
+                        matches!(
+                            package,
+                            OpamPackage::Lor(_)
+                                | OpamPackage::Formula { .. }
+                                | OpamPackage::Proxy { .. }
+                        )
+                    })
+                    .count()
+                    .to_string(),
+                Err(_) => "failed".to_string(),
+            };
+            println!(
+                "{:<24} {:>10} {:>10} {:>8} {:>10}",
+                package,
+                normalize,
+                provider.decisions.get(),
+                proxies,
+                millis
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use flate2::read::GzDecoder;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use crate::index::PackageName;
+use crate::version::OpamVersion;
+
+/// A repository read from the `index.tar.gz` an opam repository publishes.
+/// Only the `opam` files are kept, in memory, so the archive is never
+/// unpacked to disk.
+#[derive(Debug, Default)]
+pub struct OpamArchive {
+    packages: HashMap<PackageName, HashMap<String, String>>,
+}
+
+impl OpamArchive {
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let mut archive = tar::Archive::new(GzDecoder::new(file));
+        let mut packages: HashMap<PackageName, HashMap<String, String>> = HashMap::new();
+        let entries = archive
+            .entries()
+            .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        for entry in entries {
+            let mut entry = entry.map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            let entry_path = entry.path()?.into_owned();
+            let Some((package, version)) = opam_file_version(&entry_path) else {
+                continue;
+            };
+            let mut content = String::new();
+            entry.read_to_string(&mut content).map_err(|e| {
+                format!("Failed to read {} in {}: {}", entry_path.display(), path, e)
+            })?;
+            packages
+                .entry(package)
+                .or_default()
+                .insert(version, content);
+        }
+        Ok(Self { packages })
+    }
+
+    pub fn has_package(&self, package: &str) -> bool {
+        self.packages.contains_key(package)
+    }
+
+    pub fn has_version(&self, package: &str, version: &str) -> bool {
+        self.packages
+            .get(package)
+            .is_some_and(|versions| versions.contains_key(version))
+    }
+
+    pub fn packages(&self) -> impl Iterator<Item = &PackageName> {
+        self.packages.keys()
+    }
+
+    /// The versions of a package in descending order.
+    pub fn versions(&self, package: &str) -> Result<Vec<OpamVersion>, Box<dyn Error>> {
+        let mut versions = match self.packages.get(package) {
+            Some(versions) => versions
+                .keys()
+                .map(|version| OpamVersion::from_str(version))
+                .collect::<Result<Vec<_>, _>>()?,
+            None => vec![],
+        };
+        versions.sort();
+        versions.reverse();
+        Ok(versions)
+    }
+
+    /// The contents of a package version's `opam` file.
+    pub fn opam_file(&self, package: &str, version: &str) -> Option<&str> {
+        self.packages
+            .get(package)
+            .and_then(|versions| versions.get(version))
+            .map(String::as_str)
+    }
+}
+
+// the package and version of a `packages/<name>/<name>.<version>/opam` entry
+fn opam_file_version(path: &Path) -> Option<(PackageName, String)> {
+    let components: Vec<&str> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect();
+    match components[..] {
+        [.., package, dir, "opam"] => {
+            let version = dir.strip_prefix(package)?.strip_prefix('.')?;
+            Some((package.to_string(), version.to_string()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opam_file_version() {
+        assert_eq!(
+            opam_file_version(Path::new("packages/dune/dune.3.17.2/opam")),
+            Some(("dune".to_string(), "3.17.2".to_string()))
+        );
+        assert_eq!(
+            opam_file_version(Path::new("./packages/B/B.3.0.0/opam")),
+            Some(("B".to_string(), "3.0.0".to_string()))
+        );
+        assert_eq!(opam_file_version(Path::new("packages/dune/dune.3.17.2/url")), None);
+        assert_eq!(opam_file_version(Path::new("repo")), None);
+    }
+}
@@ -1,12 +1,15 @@
-use crate::index::{Binary, OpamIndex, PackageFormula, VersionFormula};
-use crate::parse::{negate_relop, parse_dependencies_for_package_version, relop_to_range, RelOp};
+use crate::index::{Binary, HashedRange, OpamIndex, PackageFormula, VersionFormula};
+use crate::normalize::{disjuncts_of, filter_part, package_disjuncts, Normalizer};
+use crate::parse::{
+    as_bool, flip_relop, formula_bool, normalize_negation, relop_to_range, RelOp, PACKAGE_FLAGS,
+};
+use crate::platform::{platform_implications, platform_values};
 use crate::version::OpamVersion;
 use core::fmt::Display;
 use pubgrub::{Dependencies, DependencyConstraints, DependencyProvider, Map, Range};
-use std::collections::{HashMap, HashSet};
 use std::convert::Infallible;
 use std::str::FromStr;
-use std::sync::{LazyLock, Mutex};
+use std::sync::LazyLock;
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum OpamPackage {
@@ -17,10 +20,24 @@ pub enum OpamPackage {
         formula: VersionFormula,
     },
     ConflictClass(String),
-    Lor {
-        lhs: Box<PackageFormula>,
-        rhs: Box<PackageFormula>,
+    /// A `conflicts:` declaration, kept as its own package so conflict
+    /// reports name it.
+    Conflict(Box<PackageFormula>),
+    /// A `depopts:` entry, constraining the package only if it's installed.
+    Depopt(Box<PackageFormula>),
+    /// Which version of a package is installed, if any. Every selected
+    /// version pins this, so that conflicts can refer to a package without
+    /// requiring it.
+    Installed(String),
+    /// Whether a package's `available:` filter holds. The false version is
+    /// unavailable, so that reports say why the package can't be selected.
+    Available {
+        package: String,
+        formula: Box<VersionFormula>,
     },
+    /// A choice between the alternatives of a disjunction, with versions
+    /// `1` to `n` picking each in turn.
+    Lor(Vec<PackageFormula>),
     Formula {
         base: Box<OpamPackage>,
         formula: Box<VersionFormula>,
@@ -32,12 +49,6 @@ pub enum OpamPackage {
     Var(String),
 }
 
-static VARIABLE_CACHE: LazyLock<Mutex<HashMap<String, HashSet<OpamVersion>>>> =
-    LazyLock::new(|| Mutex::new(HashMap::new()));
-
-static CONFLICT_CLASS_CACHE: LazyLock<Mutex<HashMap<String, HashSet<OpamVersion>>>> =
-    LazyLock::new(|| Mutex::new(HashMap::new()));
-
 impl FromStr for OpamPackage {
     type Err = String;
     fn from_str(pkg: &str) -> Result<Self, Self::Err> {
@@ -56,7 +67,19 @@ impl Display for OpamPackage {
             OpamPackage::Base(pkg) => write!(f, "{}", pkg),
             OpamPackage::Depext { names, formula } => write!(f, "{:?} {{{}}}", names, formula),
             OpamPackage::ConflictClass(pkg) => write!(f, "Conflict class {}", pkg),
-            OpamPackage::Lor { lhs, rhs } => write!(f, "{} | {}", lhs, rhs),
+            OpamPackage::Conflict(conflict) => write!(f, "{}", conflict),
+            OpamPackage::Depopt(depopt) => write!(f, "{}", depopt),
+            OpamPackage::Installed(pkg) => write!(f, "{} (installed)", pkg),
+            OpamPackage::Available { package, formula } => {
+                write!(f, "{} {{available: {}}}", package, formula)
+            }
+            OpamPackage::Lor(alternatives) => {
+                let alternatives: Vec<String> = alternatives
+                    .iter()
+                    .map(|alternative| alternative.to_string())
+                    .collect();
+                write!(f, "{}", alternatives.join(" | "))
+            }
             OpamPackage::Formula { base, formula } => write!(f, "{} {{{}}}", base, formula),
             OpamPackage::Proxy { base, formula } => match *base.clone() {
                 Some(base) => write!(f, "{} {{{}}}", base, formula),
@@ -67,12 +90,14 @@ impl Display for OpamPackage {
     }
 }
 
-static LHS_VERSION: LazyLock<OpamVersion> = LazyLock::new(|| OpamVersion("lhs".to_string()));
-static RHS_VERSION: LazyLock<OpamVersion> = LazyLock::new(|| OpamVersion("rhs".to_string()));
-
 pub static TRUE_VERSION: LazyLock<OpamVersion> = LazyLock::new(|| OpamVersion("true".to_string()));
 pub static FALSE_VERSION: LazyLock<OpamVersion> =
     LazyLock::new(|| OpamVersion("false".to_string()));
+const NOT_AVAILABLE: &str = "not available on this platform";
+static DEV_VERSION: LazyLock<OpamVersion> = LazyLock::new(|| OpamVersion("dev".to_string()));
+
+/// The version of an `Installed` package when the package isn't installed.
+pub static ABSENT_VERSION: LazyLock<OpamVersion> = LazyLock::new(|| OpamVersion("".to_string()));
 
 impl OpamIndex {
     pub fn list_versions(&self, package: &OpamPackage) -> impl Iterator<Item = OpamVersion> + '_ {
@@ -80,83 +105,96 @@ impl OpamIndex {
             OpamPackage::Root(_) => vec![OpamVersion("".to_string())],
             OpamPackage::Depext { .. } => vec![OpamVersion("".to_string())],
             OpamPackage::Base(pkg) => self.available_versions(pkg),
-            OpamPackage::ConflictClass(pkg) => CONFLICT_CLASS_CACHE
-                .lock()
-                .unwrap()
-                .get(pkg)
-                .unwrap()
-                .iter()
-                .cloned()
+            OpamPackage::ConflictClass(pkg) => {
+                self.repository.domains().conflict_class_members(pkg)
+            }
+            OpamPackage::Conflict(_) => vec![OpamVersion("".to_string())],
+            OpamPackage::Depopt(_) => vec![OpamVersion("".to_string())],
+            OpamPackage::Installed(pkg) => std::iter::once(ABSENT_VERSION.clone())
+                .chain(self.installable_versions(pkg))
                 .collect(),
-            OpamPackage::Lor { lhs: _, rhs: _ } => vec![LHS_VERSION.clone(), RHS_VERSION.clone()],
-            OpamPackage::Var(var) => match var.as_str() {
-                "os" => vec![
-                    OpamVersion("linux".to_string()),
-                    OpamVersion("macos".to_string()),
-                    OpamVersion("win32".to_string()),
-                    OpamVersion("cygwin".to_string()),
-                    OpamVersion("freebsd".to_string()),
-                    OpamVersion("openbsd".to_string()),
-                    OpamVersion("netbsd".to_string()),
-                    OpamVersion("dragonfly".to_string()),
-                ],
-                "os-distribution" => vec![
-                    // OpamVersion("homebrew".to_string()),
-                    // OpamVersion("macports".to_string()),
-                    // OpamVersion("macos".to_string()),
-                    // OpamVersion("android".to_string()),
-                    OpamVersion("debian".to_string()),
-                    // OpamVersion("ubuntu".to_string()),
-                    OpamVersion("alpine".to_string()),
-                    // TODO ...
-                ],
-                "os-family" => vec![
-                    OpamVersion("debian".to_string()),
-                    OpamVersion("alpine".to_string()),
-                    // OpamVersion("windows".to_string()),
-                    // OpamVersion("bsd".to_string()),
-                    // TODO ...
-                ],
-                "arch" => vec![
-                    OpamVersion("arm64".to_string()),
-                    OpamVersion("x86_32".to_string()),
-                    OpamVersion("x86_64".to_string()),
-                    OpamVersion("ppc32".to_string()),
-                    OpamVersion("ppc64".to_string()),
-                    OpamVersion("arm32".to_string()),
-                    OpamVersion("arm64".to_string()),
-                ],
-                _ => match VARIABLE_CACHE.lock().unwrap().get(var) {
-                    Some(m) => m.iter().cloned().collect(),
-                    None => vec![FALSE_VERSION.clone(), TRUE_VERSION.clone()],
-                },
-            },
+            OpamPackage::Available { .. } => vec![TRUE_VERSION.clone(), FALSE_VERSION.clone()],
+            OpamPackage::Lor(alternatives) => choice_versions(alternatives.len()),
+            OpamPackage::Var(var) if self.declared_values(var).is_some() => {
+                self.declared_values(var).unwrap().clone()
+            }
+            OpamPackage::Var(var) if self.flag_value(var).is_some() => {
+                vec![bool_version(self.flag_value(var).unwrap())]
+            }
+            OpamPackage::Var(var) => {
+                let values = self.repository.domains().variable_values(var);
+                match platform_values(var) {
+                    // values the repository compares against that opam doesn't
+                    // report, such as an `os-version` the table lacks, come last
+                    Some(known) => {
+                        let mut known: Vec<OpamVersion> = known
+                            .into_iter()
+                            .map(|value| OpamVersion(value.to_string()))
+                            .collect();
+                        for value in values.unwrap_or_default() {
+                            if !known.contains(&value) {
+                                known.push(value);
+                            }
+                        }
+                        known
+                    }
+                    // a variable that's never compared against a value is a boolean
+                    None => {
+                        values.unwrap_or_else(|| vec![FALSE_VERSION.clone(), TRUE_VERSION.clone()])
+                    }
+                }
+            }
             OpamPackage::Formula {
                 base: _,
                 formula: _,
             } => vec![FALSE_VERSION.clone(), TRUE_VERSION.clone()],
-            OpamPackage::Proxy {
-                base: _,
-                formula: _,
-            } => vec![LHS_VERSION.clone(), RHS_VERSION.clone()],
+            OpamPackage::Proxy { formula, .. } => choice_versions(self.choices(formula).len()),
         };
-        if self.version_debug.get() {
-            print!("versions of {}", package);
-            if versions.len() > 0 {
-                print!(": ")
-            }
-            let mut first = true;
-            for version in versions.clone() {
-                if !first {
-                    print!(", ");
-                }
-                print!("{}", version);
-                first = false;
-            }
-            println!();
-        }
+        tracing::trace!(
+            package = %package,
+            versions = ?versions,
+            "list_versions"
+        );
         versions.into_iter()
     }
+
+    // The value opam gives a flag, if this solve sets flags. `dev` is only
+    // fixed for pinned packages.
+    fn flag_value(&self, var: &str) -> Option<bool> {
+        let flags = self.flags.as_ref()?;
+        let (package, flag) = match package_variable(var) {
+            Some((package, flag)) => (Some(package), flag),
+            None => (None, var),
+        };
+        let root = package.is_some_and(|package| flags.roots.contains(package));
+        match flag {
+            "build" if package.is_none() => Some(flags.build),
+            "post" if package.is_none() => Some(flags.post),
+            "with-test" => Some(flags.with_test && root),
+            "with-doc" => Some(flags.with_doc && root),
+            "with-dev-setup" => Some(flags.with_dev_setup && root),
+            "dev" if package.is_some_and(|package| flags.dev.contains(package)) => Some(true),
+            _ => None,
+        }
+    }
+
+    // The values declared for a variable. A package's flag, like
+    // `dune:with-test`, takes those declared for the flag itself, which
+    // apply to every package.
+    fn declared_values(&self, var: &str) -> Option<&Vec<OpamVersion>> {
+        self.variables
+            .get(var)
+            .or_else(|| package_flag(var).and_then(|flag| self.variables.get(flag)))
+    }
+
+    // the value a variable has throughout this solve, if it only has one
+    fn known_value(&self, var: &str) -> Option<OpamVersion> {
+        match self.declared_values(var) {
+            Some(values) if values.len() == 1 => values.first().cloned(),
+            Some(_) => None,
+            None => self.flag_value(var).map(bool_version),
+        }
+    }
 }
 
 impl DependencyProvider for OpamIndex {
@@ -174,10 +212,18 @@ impl DependencyProvider for OpamIndex {
 
     fn prioritize(
         &self,
-        _package: &Self::P,
-        _range: &Self::VS,
-        _package_conflicts_counts: &pubgrub::PackageResolutionStatistics,
+        package: &Self::P,
+        range: &Self::VS,
+        package_conflicts_counts: &pubgrub::PackageResolutionStatistics,
     ) -> Self::Priority {
+        if package_conflicts_counts.conflict_count() > 0 {
+            tracing::debug!(
+                package = %package,
+                range = %range,
+                conflicts = package_conflicts_counts.conflict_count(),
+                "prioritize"
+            );
+        }
         1
     }
 
@@ -186,10 +232,17 @@ impl DependencyProvider for OpamIndex {
         package: &Self::P,
         range: &Self::VS,
     ) -> Result<Option<Self::V>, Self::Err> {
-        Ok(self
+        let version = self
             .list_versions(package)
             .filter(|v| range.contains(v))
-            .next())
+            .next();
+        tracing::debug!(
+            package = %package,
+            range = %range,
+            version = version.as_ref().map(tracing::field::display),
+            "choose_version"
+        );
+        Ok(version)
     }
 
     fn get_dependencies(
@@ -197,412 +250,617 @@ impl DependencyProvider for OpamIndex {
         package: &OpamPackage,
         version: &OpamVersion,
     ) -> Result<Dependencies<Self::P, Self::VS, Self::M>, Self::Err> {
-        match package {
+        let _span =
+            tracing::debug_span!("get_dependencies", package = %package, version = %version)
+                .entered();
+        let deps = match package {
             OpamPackage::Root(deps) => {
-                for (package, range) in deps {
-                    match package {
-                        OpamPackage::Var(var) => {
-                            // TODO support enumerating versions with OR's
-                            if let Some(ver) = range.as_singleton() {
-                                VARIABLE_CACHE
-                                    .lock()
-                                    .unwrap()
-                                    .entry(var.to_string())
-                                    .or_insert_with(HashSet::new)
-                                    .insert(ver.clone());
-                            }
-                        }
-                        _ => {}
-                    }
-                }
                 Ok(Dependencies::Available(deps.into_iter().cloned().collect()))
             }
             OpamPackage::Base(pkg) => {
-                let formulas = parse_dependencies_for_package_version(
-                    self.repo.as_str(),
-                    pkg,
-                    version.to_string().as_str(),
-                )
-                .unwrap();
-                let deps = from_formulas(&formulas);
-                if self.debug.get() {
-                    print!("({}, {})", package, version);
-                    if deps.len() > 0 {
-                        print!(" -> ")
-                    }
-                    let mut first = true;
-                    for (package, range) in deps.clone() {
-                        if !first {
-                            print!(", ");
-                        }
-                        print!("({}, {})", package, range);
-                        first = false;
-                    }
-                    println!();
+                let version = version.to_string();
+                let formulas = match self.repository.dependencies(pkg, &version) {
+                    Ok(formulas) => formulas,
+                    // like a pin to a source whose opam file can't be read
+                    Err(e) => return Ok(Dependencies::Unavailable(e.to_string())),
+                };
+                let formulas = if self.with_depopts {
+                    formulas
+                        .into_iter()
+                        .map(|formula| match formula {
+                            PackageFormula::Depopt { name, formula, .. } => {
+                                PackageFormula::Base { name, formula }
+                            }
+                            formula => formula,
+                        })
+                        .collect()
+                } else {
+                    formulas
+                };
+                let mut normalizer = Normalizer::new(|var: &str| self.known_value(var));
+                let formulas: Vec<PackageFormula> = if self.normalize {
+                    formulas
+                        .iter()
+                        .filter_map(|formula| normalizer.package_formula(formula))
+                        .collect()
+                } else {
+                    formulas
+                };
+                let mut installed = Map::default();
+                installed.insert(
+                    OpamPackage::Installed(pkg.to_string()),
+                    Range::singleton(OpamVersion(version)),
+                );
+                // the variables folded away still take their values
+                for (var, value) in normalizer.folded {
+                    installed.insert(OpamPackage::Var(var), Range::singleton(value));
                 }
-                Ok(Dependencies::Available(deps))
+                let deps = self
+                    .from_formulas(&formulas)
+                    .map(|deps| merge_constraints(deps, installed));
+                Ok(dependencies(deps))
             }
             OpamPackage::ConflictClass(_) => Ok(Dependencies::Available(Map::default())),
-            OpamPackage::Lor { lhs, rhs } => {
-                let deps = match version {
-                    OpamVersion(ver) => match ver.as_str() {
-                        "lhs" => from_formula(*&lhs),
-                        "rhs" => from_formula(*&rhs),
-                        _ => panic!("Unknown OR version {}", version),
-                    },
-                };
-                if self.debug.get() {
-                    print!("({}, {})", package, version);
-                    if deps.len() > 0 {
-                        print!(" -> ")
-                    }
-                    let mut first = true;
-                    for (package, range) in deps.clone() {
-                        if !first {
-                            print!(", ");
-                        }
-                        print!("({}, {})", package, range);
-                        first = false;
-                    }
-                    println!();
+            OpamPackage::Conflict(conflict) => match conflict.as_ref() {
+                PackageFormula::Conflict { name, formula, .. } => {
+                    Ok(dependencies(self.from_conflict(name, formula)))
+                }
+                _ => Ok(Dependencies::Unavailable(format!(
+                    "Not a conflict: {}",
+                    conflict
+                ))),
+            },
+            OpamPackage::Depopt(depopt) => match depopt.as_ref() {
+                PackageFormula::Depopt { name, formula, .. } => {
+                    Ok(Dependencies::Available(from_depopt(name, formula)))
+                }
+                _ => Ok(Dependencies::Unavailable(format!(
+                    "Not a depopt: {}",
+                    depopt
+                ))),
+            },
+            OpamPackage::Installed(pkg) => {
+                let mut deps = Map::default();
+                // a package variable can require a package be installed
+                if *version != *ABSENT_VERSION {
+                    deps.insert(
+                        OpamPackage::Base(pkg.to_string()),
+                        Range::singleton(version.clone()),
+                    );
                 }
                 Ok(Dependencies::Available(deps))
             }
-            OpamPackage::Formula { base, formula } => {
-                let deps = match version {
-                    OpamVersion(ver) => match ver.as_str() {
-                        "true" => from_version_formula(Some(&base), formula),
-                        "false" => {
-                            from_version_formula(None, &Box::new(negate_formula(*formula.clone())))
-                        }
-                        _ => panic!("Unknown Formula version {}", version),
-                    },
-                };
-                if self.debug.get() {
-                    print!("({}, {})", package, version);
-                    if deps.len() > 0 {
-                        print!(" -> ")
+            OpamPackage::Available { formula, .. } => match version.0.as_str() {
+                "true" => match formula.as_ref() {
+                    VersionFormula::Lit(available) if *available == *TRUE_VERSION => {
+                        Ok(Dependencies::Available(Map::default()))
                     }
-                    let mut first = true;
-                    for (package, range) in deps.clone() {
-                        if !first {
-                            print!(", ");
-                        }
-                        print!("({}, {})", package, range);
-                        first = false;
+                    VersionFormula::Lit(_) => {
+                        Ok(Dependencies::Unavailable(NOT_AVAILABLE.to_string()))
                     }
-                    println!();
+                    formula => Ok(dependencies(self.from_version_formula(None, formula))),
+                },
+                "false" => Ok(Dependencies::Unavailable(NOT_AVAILABLE.to_string())),
+                _ => Ok(Dependencies::Unavailable(format!(
+                    "Unknown Available version {}",
+                    version
+                ))),
+            },
+            OpamPackage::Lor(alternatives) => match choice(version, alternatives.len()) {
+                Some(alternative) => {
+                    Ok(dependencies(self.from_formula(&alternatives[alternative])))
                 }
-                Ok(Dependencies::Available(deps))
-            }
+                None => Ok(Dependencies::Unavailable(format!(
+                    "Unknown alternative {} of {}",
+                    version, package
+                ))),
+            },
+            OpamPackage::Formula { base, formula } => match version.0.as_str() {
+                "true" => Ok(dependencies(self.from_version_formula(Some(base), formula))),
+                // the filter doesn't hold, whatever the package's version
+                "false" => {
+                    let filter = negate(filter_part(formula));
+                    Ok(dependencies(self.from_version_formula(None, &filter)))
+                }
+                _ => Ok(Dependencies::Unavailable(format!(
+                    "Unknown Formula version {}",
+                    version
+                ))),
+            },
             OpamPackage::Proxy { base, formula } => {
-                let deps = from_proxy_formula(base.as_ref().as_ref(), version, formula);
-                if self.debug.get() {
-                    print!("({}, {})", package, version);
-                    if deps.len() > 0 {
-                        print!(" -> ")
-                    }
-                    let mut first = true;
-                    for (package, range) in deps.clone() {
-                        if !first {
-                            print!(", ");
-                        }
-                        print!("({}, {})", package, range);
-                        first = false;
-                    }
-                    println!();
+                let choices = self.choices(formula);
+                match choice(version, choices.len()) {
+                    Some(i) => Ok(dependencies(
+                        self.from_version_formula(base.as_ref().as_ref(), &choices[i]),
+                    )),
+                    None => Ok(Dependencies::Unavailable(format!(
+                        "Unknown choice {} of {}",
+                        version, package
+                    ))),
                 }
-                Ok(Dependencies::Available(deps))
             }
-            OpamPackage::Var(_) => {
-                if self.debug.get() {
-                    println!("({}, {})", package, version);
+            OpamPackage::Var(var) => {
+                let mut deps: DependencyConstraints<OpamPackage, Range<OpamVersion>> =
+                    platform_implications(var, version)
+                        .into_iter()
+                        .map(|(implied, values)| {
+                            let range = values.into_iter().fold(Range::empty(), |range, value| {
+                                range.union(&Range::singleton(OpamVersion(value.to_string())))
+                            });
+                            (OpamPackage::Var(implied.to_string()), range)
+                        })
+                        .collect();
+                // without flags, a package's flag is the flag every package
+                // shares, which the caller may set
+                if let Some(flag) = package_flag(var) {
+                    if self.flags.is_none() && self.declared_values(var).is_none() {
+                        deps.insert(
+                            OpamPackage::Var(flag.to_string()),
+                            Range::singleton(version.clone()),
+                        );
+                    }
                 }
-                Ok(Dependencies::Available(Map::default()))
-            }
-            OpamPackage::Depext { .. } => {
-                if self.debug.get() {
-                    println!("({}, {})", package, version);
+                // an unpinned package's `dev` follows whether it's at its
+                // development version
+                if let Some((package, "dev")) = package_variable(var) {
+                    if self.flags.is_some() && self.flag_value(var).is_none() {
+                        let dev = Range::singleton(DEV_VERSION.clone());
+                        let range = match as_bool(version) {
+                            Some(true) => dev,
+                            _ => dev.complement(),
+                        };
+                        deps.insert(OpamPackage::Installed(package.to_string()), range);
+                    }
                 }
-                Ok(Dependencies::Available(Map::default()))
+                Ok(Dependencies::Available(deps))
             }
+            OpamPackage::Depext { .. } => Ok(Dependencies::Available(Map::default())),
+        };
+        if let Ok(Dependencies::Available(deps)) = &deps {
+            tracing::debug!(dependencies = ?deps, "dependencies");
         }
+        deps
     }
 }
 
-pub fn from_formulas(
-    formulas: &Vec<PackageFormula>,
-) -> DependencyConstraints<OpamPackage, Range<OpamVersion>> {
-    formulas
-        .iter()
-        .map(|formula| from_formula(formula))
-        .fold(Map::default(), |acc, cons| merge_constraints(acc, cons))
+type Constraints = DependencyConstraints<OpamPackage, Range<OpamVersion>>;
+
+// constraints that can't be encoded leave the version unavailable, with the reason
+fn dependencies(
+    constraints: Result<Constraints, String>,
+) -> Dependencies<OpamPackage, Range<OpamVersion>, String> {
+    match constraints {
+        Ok(constraints) => Dependencies::Available(constraints),
+        Err(reason) => Dependencies::Unavailable(reason),
+    }
 }
 
-fn from_formula(
-    formula: &PackageFormula,
-) -> DependencyConstraints<OpamPackage, Range<OpamVersion>> {
-    match formula {
-        PackageFormula::Base { name, formula } => {
-            let mut map = Map::default();
-            match formula {
-                // in parse.rs we collapse non-filtered formula to a single version dependency
-                VersionFormula::Version(range) => {
-                    map.insert(OpamPackage::Base(name.to_string()), range.0.clone())
-                }
-                // otherwise, we need to introduce a formula packge to select variable values
-                _ => map.insert(
-                    OpamPackage::Formula {
-                        base: Box::new(OpamPackage::Base(name.to_string())),
+// the versions of a choice between `n` alternatives
+fn choice_versions(n: usize) -> Vec<OpamVersion> {
+    (1..=n).map(|i| OpamVersion(i.to_string())).collect()
+}
+
+/// The alternative, counting from 0, that a version of a choice between `n`
+/// alternatives like `Lor` picks.
+pub fn choice(version: &OpamVersion, n: usize) -> Option<usize> {
+    version
+        .0
+        .parse::<usize>()
+        .ok()
+        .filter(|i| (1..=n).contains(i))
+        .map(|i| i - 1)
+}
+
+impl OpamIndex {
+    pub fn from_formulas(&self, formulas: &[PackageFormula]) -> Result<Constraints, String> {
+        formulas.iter().try_fold(Map::default(), |acc, formula| {
+            Ok(merge_constraints(acc, self.from_formula(formula)?))
+        })
+    }
+
+    fn from_formula(&self, formula: &PackageFormula) -> Result<Constraints, String> {
+        let mut map = Map::default();
+        match formula {
+            PackageFormula::Base { name, formula } => {
+                let base = OpamPackage::Base(name.to_string());
+                match formula {
+                    // in parse.rs we collapse non-filtered formula to a single version dependency
+                    VersionFormula::Version(range) => map.insert(base, range.0.clone()),
+                    // a constant filter either always or never applies
+                    VersionFormula::Lit(lit) => match as_bool(lit) {
+                        Some(true) => map.insert(base, Range::full()),
+                        Some(false) => None,
+                        None => return Err(format!("Invalid filter for {}: {}", name, lit)),
+                    },
+                    // with its filter known to hold, only the version constraints are left
+                    _ if self.normalize && formula_bool(&filter_part(formula)) == Some(true) => {
+                        return self.from_version_formula(Some(&base), formula);
+                    }
+                    // otherwise, we need to introduce a formula package to select variable values
+                    _ => map.insert(
+                        OpamPackage::Formula {
+                            base: Box::new(base),
+                            formula: Box::new(formula.clone()),
+                        },
+                        Range::full(),
+                    ),
+                };
+            }
+            PackageFormula::Depext { names, formula } => {
+                map.insert(
+                    OpamPackage::Depext {
+                        names: names.to_vec(),
+                        formula: formula.clone(),
+                    },
+                    Range::full(),
+                );
+            }
+            PackageFormula::ConflictClass { name, package } => {
+                map.insert(
+                    OpamPackage::ConflictClass(name.to_string()),
+                    Range::<OpamVersion>::singleton(OpamVersion(package.to_string())),
+                );
+            }
+            PackageFormula::Available { package, formula } => {
+                map.insert(
+                    OpamPackage::Available {
+                        package: package.to_string(),
                         formula: Box::new(formula.clone()),
                     },
                     Range::full(),
-                ),
-            };
-            map
-        }
-        PackageFormula::Depext { names, formula } => {
-            let mut map = Map::default();
-            map.insert(
-                OpamPackage::Depext {
-                    names: names.to_vec(),
-                    formula: formula.clone(),
-                },
-                Range::full(),
-            );
-            map
-        }
-        PackageFormula::ConflictClass { name, package } => {
-            let mut map = Map::default();
-            map.insert(
-                OpamPackage::ConflictClass(name.to_string()),
-                Range::<OpamVersion>::singleton(OpamVersion(package.to_string())),
-            );
-            CONFLICT_CLASS_CACHE
-                .lock()
-                .unwrap()
-                .entry(name.to_string())
-                .or_insert_with(HashSet::new)
-                .insert(OpamVersion(package.to_string()));
-            map
-        }
-        PackageFormula::Or(Binary { lhs, rhs }) => {
-            let mut map = Map::default();
-            map.insert(
-                OpamPackage::Lor {
-                    lhs: lhs.clone(),
-                    rhs: rhs.clone(),
-                },
-                Range::full(),
-            );
-            map
-        }
-        PackageFormula::And(Binary { lhs, rhs }) => {
-            let left = from_formula(lhs);
-            let right = from_formula(rhs);
-            merge_constraints(left, right)
+                );
+            }
+            PackageFormula::Conflict { .. } => {
+                map.insert(
+                    OpamPackage::Conflict(Box::new(formula.clone())),
+                    Range::full(),
+                );
+            }
+            PackageFormula::Depopt { .. } => {
+                map.insert(
+                    OpamPackage::Depopt(Box::new(formula.clone())),
+                    Range::full(),
+                );
+            }
+            PackageFormula::Or(Binary { lhs, rhs }) => {
+                // one choice between every alternative, or pairs of them
+                // when formulas aren't normalised
+                let alternatives = if self.normalize {
+                    package_disjuncts(formula).into_iter().cloned().collect()
+                } else {
+                    vec![*lhs.clone(), *rhs.clone()]
+                };
+                map.insert(OpamPackage::Lor(alternatives), Range::full());
+            }
+            PackageFormula::And(Binary { lhs, rhs }) => {
+                return Ok(merge_constraints(
+                    self.from_formula(lhs)?,
+                    self.from_formula(rhs)?,
+                ));
+            }
         }
+        Ok(map)
     }
-}
 
-fn merge_constraints(
-    mut left: DependencyConstraints<OpamPackage, Range<OpamVersion>>,
-    right: DependencyConstraints<OpamPackage, Range<OpamVersion>>,
-) -> DependencyConstraints<OpamPackage, Range<OpamVersion>> {
-    for (pkg, range) in right {
-        left.entry(pkg.clone())
-            .and_modify(|existing| {
-                match pkg {
-                    _ => *existing = existing.intersection(&range),
-                };
-            })
-            .or_insert(range);
+    // the conflicting package must be absent, or installed at a version (and
+    // with variable values) the conflict doesn't match
+    fn from_conflict(&self, name: &str, formula: &VersionFormula) -> Result<Constraints, String> {
+        let installed = OpamPackage::Installed(name.to_string());
+        let absent = Range::singleton(ABSENT_VERSION.clone());
+        let mut map = Map::default();
+        match negate(formula.clone()) {
+            VersionFormula::Version(range) => {
+                map.insert(installed, range.0.union(&absent));
+            }
+            VersionFormula::Lit(lit) if as_bool(&lit) == Some(true) => (),
+            VersionFormula::Lit(lit) if as_bool(&lit) == Some(false) => {
+                map.insert(installed, absent);
+            }
+            allowed => {
+                return self.from_version_formula(
+                    Some(&installed),
+                    &VersionFormula::Or(Binary {
+                        lhs: Box::new(VersionFormula::Version(HashedRange(absent))),
+                        rhs: Box::new(allowed),
+                    }),
+                )
+            }
+        }
+        Ok(map)
     }
-    left
-}
 
-// we depend on this if we don't select a formula
-fn negate_formula(expr: VersionFormula) -> VersionFormula {
-    match expr {
-        // we strip out all versions, and only select variable values
-        VersionFormula::Version(_) => panic!("we should never get here"),
-        VersionFormula::Variable(variable) => VersionFormula::Not(variable),
-        VersionFormula::Not(variable) => VersionFormula::Variable(variable),
-        VersionFormula::And(Binary { lhs, rhs }) => match (*lhs.clone(), *rhs.clone()) {
-            // strip out versions
-            // if there's two versions, we propigate one and it will be stripped above
-            (VersionFormula::Version(_), _) => negate_formula(*rhs),
-            (_, VersionFormula::Version(_)) => negate_formula(*lhs),
-            // De Morgan’s laws
-            _ => VersionFormula::Or(Binary {
-                lhs: Box::new(negate_formula(*lhs)),
-                rhs: Box::new(negate_formula(*rhs)),
-            }),
-        },
-        VersionFormula::Or(Binary { lhs, rhs }) => match (*lhs.clone(), *rhs.clone()) {
-            // strip out versions
-            // if there's two versions, we propigate one and it will be stripped above
-            (VersionFormula::Version(_), _) => negate_formula(*rhs),
-            (_, VersionFormula::Version(_)) => negate_formula(*lhs),
-            // De Morgan’s laws
-            _ => VersionFormula::And(Binary {
-                lhs: Box::new(negate_formula(*lhs)),
-                rhs: Box::new(negate_formula(*rhs)),
-            }),
-        },
-        VersionFormula::Comparator { relop, binary } => VersionFormula::Comparator {
-            relop: negate_relop(relop),
-            binary,
-        },
-        VersionFormula::Lit(lit) => VersionFormula::Lit(lit),
+    // the ways a proxy's formula can hold: each alternative of a
+    // disjunction, or both sides of a comparison holding or not
+    fn choices(&self, formula: &VersionFormula) -> Vec<VersionFormula> {
+        let both = |lhs: VersionFormula, rhs: VersionFormula| {
+            VersionFormula::And(Binary {
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            })
+        };
+        match formula {
+            VersionFormula::Or(_) if self.normalize => {
+                disjuncts_of(formula).into_iter().cloned().collect()
+            }
+            VersionFormula::Or(Binary { lhs, rhs }) => vec![*lhs.clone(), *rhs.clone()],
+            VersionFormula::Comparator { relop, binary } => {
+                let (lhs, rhs) = (*binary.lhs.clone(), *binary.rhs.clone());
+                let (not_lhs, not_rhs) = (negate(lhs.clone()), negate(rhs.clone()));
+                match relop {
+                    RelOp::Eq => vec![both(lhs, rhs), both(not_lhs, not_rhs)],
+                    RelOp::Neq => vec![both(lhs, not_rhs), both(not_lhs, rhs)],
+                    _ => vec![],
+                }
+            }
+            _ => vec![],
+        }
     }
-}
 
-fn from_proxy_formula(
-    base: Option<&OpamPackage>,
-    version: &OpamVersion,
-    formula: &VersionFormula,
-) -> DependencyConstraints<OpamPackage, Range<OpamVersion>> {
-    // let mut map = Map::default();
-    match formula {
-        VersionFormula::Or(Binary { lhs, rhs }) => match version {
-            OpamVersion(ver) => match ver.as_str() {
-                "lhs" => from_version_formula(base, lhs),
-                "rhs" => from_version_formula(base, rhs),
-                _ => panic!("Unknown Formula version {}", version),
-            },
-        },
-        VersionFormula::Comparator { relop, binary } => match relop {
-            RelOp::Eq => match version {
-                OpamVersion(ver) => match ver.as_str() {
-                    "lhs" => {
-                        let lhs = from_version_formula(base, &*binary.lhs);
-                        let rhs = from_version_formula(base, &*binary.rhs);
-                        merge_constraints(lhs, rhs)
+    fn from_version_formula(
+        &self,
+        base: Option<&OpamPackage>,
+        formula: &VersionFormula,
+    ) -> Result<Constraints, String> {
+        let mut map = Map::default();
+        let proxy = || OpamPackage::Proxy {
+            base: Box::new(base.cloned()),
+            formula: Box::new(formula.clone()),
+        };
+        match formula {
+            VersionFormula::Version(range) => {
+                if let Some(base) = base {
+                    map.insert(base.clone(), range.0.clone());
+                };
+                Ok(map)
+            }
+            VersionFormula::Variable(variable) => {
+                if let Some(base) = base {
+                    map.insert(base.clone(), Range::full());
+                };
+                Ok(merge_constraints(map, from_variable(variable, true)))
+            }
+            VersionFormula::Not(variable) => {
+                if let Some(base) = base {
+                    map.insert(base.clone(), Range::full());
+                };
+                Ok(merge_constraints(map, from_variable(variable, false)))
+            }
+            VersionFormula::Or(_) => {
+                map.insert(proxy(), Range::full());
+                Ok(map)
+            }
+            VersionFormula::And(Binary { lhs, rhs }) => Ok(merge_constraints(
+                self.from_version_formula(base, lhs)?,
+                self.from_version_formula(base, rhs)?,
+            )),
+            VersionFormula::Comparator { relop, binary } => {
+                if let Some(base) = base {
+                    map.insert(base.clone(), Range::full());
+                };
+                let (var, range) = match (binary.lhs.as_ref(), binary.rhs.as_ref()) {
+                    (VersionFormula::Variable(var), VersionFormula::Lit(ver)) => {
+                        (var, compared_range(var, relop, ver))
                     }
-                    "rhs" => {
-                        let lhs = from_version_formula(base, &negate_formula(*binary.lhs.clone()));
-                        let rhs = from_version_formula(base, &negate_formula(*binary.rhs.clone()));
-                        merge_constraints(lhs, rhs)
+                    (VersionFormula::Lit(ver), VersionFormula::Variable(var)) => {
+                        (var, compared_range(var, &flip_relop(relop), ver))
                     }
-                    _ => panic!("Unknown Formula version {}", version),
-                },
-            },
-            RelOp::Neq => match version {
-                OpamVersion(ver) => match ver.as_str() {
-                    "lhs" => {
-                        let lhs = from_version_formula(base, &*binary.lhs);
-                        let rhs = from_version_formula(base, &negate_formula(*binary.rhs.clone()));
-                        merge_constraints(lhs, rhs)
+                    // `?var`, whether the variable is defined
+                    (VersionFormula::Variable(var), VersionFormula::Version(defined))
+                        if *relop == RelOp::Eq =>
+                    {
+                        (var, defined.0.clone())
                     }
-                    "rhs" => {
-                        let lhs = from_version_formula(base, &negate_formula(*binary.lhs.clone()));
-                        let rhs = from_version_formula(base, &*binary.rhs);
-                        merge_constraints(lhs, rhs)
+                    (VersionFormula::Variable(var), VersionFormula::Version(defined))
+                        if *relop == RelOp::Neq =>
+                    {
+                        (var, defined.0.complement())
                     }
-                    _ => panic!("Unknown Formula version {}", version),
-                },
-            },
-            _ => match base {
-                Some(base) => panic!("invalid operator for {}: {}", base, formula),
-                None => panic!("invalid operator for {}", formula),
+                    // a proxy selects which sides of a boolean comparison hold
+                    _ if !self.choices(formula).is_empty() => {
+                        map.insert(proxy(), Range::full());
+                        return Ok(map);
+                    }
+                    _ => {
+                        return Err(match base {
+                            Some(base) => format!("invalid operator for {}: {}", base, formula),
+                            None => format!("invalid operator for {}", formula),
+                        })
+                    }
+                };
+                match package_variable(var) {
+                    // the version selected for the package, which is absent
+                    // (and lower than any version) if it isn't installed
+                    Some((package, "version")) => {
+                        map.insert(OpamPackage::Installed(package.to_string()), range)
+                    }
+                    _ => map.insert(OpamPackage::Var(var.to_string()), range),
+                };
+                Ok(map)
+            }
+            // parsing folds boolean literals away unless they are the whole formula
+            VersionFormula::Lit(lit) => match (base, as_bool(lit)) {
+                (Some(base), Some(value)) => {
+                    let range = if value { Range::full() } else { Range::empty() };
+                    map.insert(base.clone(), range);
+                    Ok(map)
+                }
+                (None, Some(true)) => Ok(map),
+                (None, Some(false)) => Err(format!("{{{}}} never holds", formula)),
+                (Some(base), None) => Err(format!(
+                    "invalid literal for {} {{{}}}: {}",
+                    base, formula, lit
+                )),
+                (None, None) => Err(format!("invalid literal for {{{}}}: {}", formula, lit)),
             },
-        },
-        _ => panic!("This formula shouldn't be in a proxy: {}", formula),
+        }
     }
 }
 
-fn from_version_formula(
-    base: Option<&OpamPackage>,
+// the optional package may be absent, otherwise the formula applies to it
+// like a filtered dependency
+fn from_depopt(
+    name: &str,
     formula: &VersionFormula,
 ) -> DependencyConstraints<OpamPackage, Range<OpamVersion>> {
+    let installed = OpamPackage::Installed(name.to_string());
     let mut map = Map::default();
+    match or_absent(formula.clone()) {
+        VersionFormula::Version(range) => map.insert(installed, range.0),
+        // a constant filter either always holds or never applies
+        VersionFormula::Lit(lit) if as_bool(&lit).is_some() => None,
+        formula => map.insert(
+            OpamPackage::Formula {
+                base: Box::new(installed),
+                formula: Box::new(formula),
+            },
+            Range::full(),
+        ),
+    };
+    map
+}
+
+// allow an `Installed` package to be absent wherever a version is constrained
+fn or_absent(formula: VersionFormula) -> VersionFormula {
     match formula {
-        VersionFormula::Version(range) => {
-            if let Some(base) = base {
-                map.insert(base.clone(), range.0.clone());
-            };
-            map
+        VersionFormula::Version(range) => VersionFormula::Version(HashedRange(
+            range.0.union(&Range::singleton(ABSENT_VERSION.clone())),
+        )),
+        VersionFormula::And(Binary { lhs, rhs }) => VersionFormula::And(Binary {
+            lhs: Box::new(or_absent(*lhs)),
+            rhs: Box::new(or_absent(*rhs)),
+        }),
+        VersionFormula::Or(Binary { lhs, rhs }) => VersionFormula::Or(Binary {
+            lhs: Box::new(or_absent(*lhs)),
+            rhs: Box::new(or_absent(*rhs)),
+        }),
+        formula => formula,
+    }
+}
+
+fn bool_version(value: bool) -> OpamVersion {
+    if value {
+        TRUE_VERSION.clone()
+    } else {
+        FALSE_VERSION.clone()
+    }
+}
+
+// the flag a package's flag variable like `dune:with-test` scopes
+fn package_flag(variable: &str) -> Option<&str> {
+    package_variable(variable)
+        .map(|(_, flag)| flag)
+        .filter(|flag| PACKAGE_FLAGS.contains(flag))
+}
+
+// the values of a variable for which comparing it with a literal holds: the
+// comparison doesn't hold when a package's version is undefined, so a
+// package that isn't installed never satisfies it
+fn compared_range(var: &str, relop: &RelOp, literal: &OpamVersion) -> Range<OpamVersion> {
+    let range = relop_to_range(relop, literal.clone());
+    match package_variable(var) {
+        Some((_, "version")) => {
+            range.intersection(&Range::singleton(ABSENT_VERSION.clone()).complement())
         }
-        VersionFormula::Variable(variable) => {
-            if let Some(base) = base {
-                map.insert(base.clone(), Range::full());
+        _ => range,
+    }
+}
+
+// the negation of a filter, under which a package that isn't installed
+// fails every comparison of its version, so satisfies every negated one
+fn negate(formula: VersionFormula) -> VersionFormula {
+    normalize_negation(defined_versions(formula))
+}
+
+// compare package versions with ranges rather than literals, so negating
+// a comparison keeps the package's absence
+fn defined_versions(formula: VersionFormula) -> VersionFormula {
+    match formula {
+        VersionFormula::And(Binary { lhs, rhs }) => VersionFormula::And(Binary {
+            lhs: Box::new(defined_versions(*lhs)),
+            rhs: Box::new(defined_versions(*rhs)),
+        }),
+        VersionFormula::Or(Binary { lhs, rhs }) => VersionFormula::Or(Binary {
+            lhs: Box::new(defined_versions(*lhs)),
+            rhs: Box::new(defined_versions(*rhs)),
+        }),
+        VersionFormula::Comparator { relop, binary } => {
+            let compared = match (binary.lhs.as_ref(), binary.rhs.as_ref()) {
+                (VersionFormula::Variable(var), VersionFormula::Lit(ver)) => {
+                    Some((var, relop.clone(), ver))
+                }
+                (VersionFormula::Lit(ver), VersionFormula::Variable(var)) => {
+                    Some((var, flip_relop(&relop), ver))
+                }
+                _ => None,
             };
-            map.insert(
-                OpamPackage::Var(variable.to_string()),
-                Range::singleton(TRUE_VERSION.clone()),
-            );
-            map
+            match compared {
+                Some((var, relop, ver))
+                    if matches!(package_variable(var), Some((_, "version"))) =>
+                {
+                    VersionFormula::Comparator {
+                        relop: RelOp::Eq,
+                        binary: Binary {
+                            lhs: Box::new(VersionFormula::Variable(var.clone())),
+                            rhs: Box::new(VersionFormula::Version(HashedRange(compared_range(
+                                var, &relop, ver,
+                            )))),
+                        },
+                    }
+                }
+                _ => VersionFormula::Comparator { relop, binary },
+            }
+        }
+        formula => formula,
+    }
+}
+
+// split a package variable like `ocaml:version` into the package and variable
+fn package_variable(variable: &str) -> Option<(&str, &str)> {
+    variable
+        .split_once(':')
+        .filter(|(package, name)| !package.is_empty() && !name.is_empty())
+}
+
+// the constraints for a boolean variable to have the given value
+fn from_variable(
+    variable: &str,
+    value: bool,
+) -> DependencyConstraints<OpamPackage, Range<OpamVersion>> {
+    let mut map = Map::default();
+    match package_variable(variable) {
+        // whether the package is in the solution
+        Some((package, "installed")) => {
+            let absent = Range::singleton(ABSENT_VERSION.clone());
+            let range = if value { absent.complement() } else { absent };
+            map.insert(OpamPackage::Installed(package.to_string()), range);
         }
-        VersionFormula::Not(variable) => {
-            if let Some(base) = base {
-                map.insert(base.clone(), Range::full());
+        _ => {
+            let value = if value {
+                TRUE_VERSION.clone()
+            } else {
+                FALSE_VERSION.clone()
             };
             map.insert(
                 OpamPackage::Var(variable.to_string()),
-                Range::singleton(FALSE_VERSION.clone()),
-            );
-            map
-        }
-        VersionFormula::Or(_) => {
-            map.insert(
-                OpamPackage::Proxy {
-                    base: Box::new(base.cloned()),
-                    formula: Box::new(formula.clone()),
-                },
-                Range::full(),
+                Range::singleton(value),
             );
-            map
         }
-        VersionFormula::And(Binary { lhs, rhs }) => {
-            let left = from_version_formula(base, lhs);
-            let right = from_version_formula(base, rhs);
-            merge_constraints(left, right)
-        }
-        VersionFormula::Comparator { relop, binary } => {
-            if let Some(base) = base {
-                map.insert(base.clone(), Range::full());
-            };
-            match (*binary.lhs.clone(), *binary.rhs.clone()) {
-                (VersionFormula::Lit(ver), VersionFormula::Variable(var)) => {
-                    VARIABLE_CACHE
-                        .lock()
-                        .unwrap()
-                        .entry(var.to_string())
-                        .or_insert_with(HashSet::new)
-                        .insert(ver.clone());
-                    let range = relop_to_range(relop, ver);
-                    map.insert(OpamPackage::Var(var.to_string()), range)
-                }
-                (VersionFormula::Variable(var), VersionFormula::Lit(ver)) => {
-                    VARIABLE_CACHE
-                        .lock()
-                        .unwrap()
-                        .entry(var.to_string())
-                        .or_insert_with(HashSet::new)
-                        .insert(ver.clone());
-                    let range = relop_to_range(relop, ver);
-                    map.insert(OpamPackage::Var(var.to_string()), range)
-                }
-                _ => match relop {
-                    RelOp::Eq | RelOp::Neq => map.insert(
-                        OpamPackage::Proxy {
-                            base: Box::new(base.cloned()),
-                            formula: Box::new(formula.clone()),
-                        },
-                        Range::full(),
-                    ),
-                    _ => match base {
-                        Some(base) => panic!("invalid operator for {}: {}", base, formula),
-                        None => panic!("invalid operator for {}", formula),
-                    },
-                },
-            };
-            map
-        }
-        VersionFormula::Lit(lit) => match base {
-            Some(base) => panic!("invalid literal for {} {{{}}}: {}", base, formula, lit),
-            None => panic!("invalid literal for {{{}}}: {}", formula, lit),
-        },
     }
+    map
+}
+
+fn merge_constraints(
+    mut left: DependencyConstraints<OpamPackage, Range<OpamVersion>>,
+    right: DependencyConstraints<OpamPackage, Range<OpamVersion>>,
+) -> DependencyConstraints<OpamPackage, Range<OpamVersion>> {
+    for (pkg, range) in right {
+        left.entry(pkg.clone())
+            .and_modify(|existing| {
+                match pkg {
+                    _ => *existing = existing.intersection(&range),
+                };
+            })
+            .or_insert(range);
+    }
+    left
 }
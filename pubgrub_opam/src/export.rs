@@ -0,0 +1,171 @@
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+
+use crate::deps::OpamPackage;
+use crate::index::{OpamRepository, PackageName};
+use crate::parse::as_bool;
+use crate::version::OpamVersion;
+
+/// The opam packages and variable values of a solution, in the forms stock
+/// opam reads back.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpamSolution {
+    pub packages: BTreeMap<PackageName, OpamVersion>,
+    pub variables: BTreeMap<String, OpamVersion>,
+}
+
+impl OpamSolution {
+    /// Collect the `Base` and `Var` entries of a solution. The solver's other
+    /// packages only exist to encode formulas.
+    pub fn new<'a>(selected: impl IntoIterator<Item = (&'a OpamPackage, &'a OpamVersion)>) -> Self {
+        let mut solution = OpamSolution::default();
+        for (package, version) in selected {
+            match package {
+                OpamPackage::Base(name) => {
+                    solution.packages.insert(name.clone(), version.clone());
+                }
+                OpamPackage::Var(name) => {
+                    solution.variables.insert(name.clone(), version.clone());
+                }
+                _ => {}
+            }
+        }
+        solution
+    }
+
+    /// A `<project>.opam.locked` file pinning every package but the project
+    /// itself to its selected version, for `opam install --locked`.
+    pub fn lock_file(&self, project: &str) -> String {
+        let mut out = String::new();
+        writeln!(out, "opam-version: \"2.0\"").unwrap();
+        writeln!(out, "name: \"{}\"", project).unwrap();
+        if let Some(version) = self.packages.get(project) {
+            writeln!(out, "version: \"{}\"", version).unwrap();
+        }
+        writeln!(out, "depends: [").unwrap();
+        for (name, version) in &self.packages {
+            if name != project {
+                writeln!(out, "  \"{}\" {{= \"{}\"}}", name, version).unwrap();
+            }
+        }
+        writeln!(out, "]").unwrap();
+        if !self.variables.is_empty() {
+            writeln!(out, "# Solved with the variables:").unwrap();
+            for (name, value) in &self.variables {
+                writeln!(out, "#   {} = {}", name, variable_value(value)).unwrap();
+            }
+        }
+        out
+    }
+
+    /// A file for `opam switch import`, with the given packages as the
+    /// switch's roots and every package installed.
+    pub fn switch_export(&self, roots: &[PackageName]) -> String {
+        let atom =
+            |(name, version): (&PackageName, &OpamVersion)| format!("\"{}.{}\"", name, version);
+        let root_atoms: Vec<String> = self
+            .packages
+            .iter()
+            .filter(|(name, _)| roots.contains(name))
+            .map(atom)
+            .collect();
+        let installed: Vec<String> = self.packages.iter().map(atom).collect();
+        let mut out = String::new();
+        writeln!(out, "opam-version: \"2.0\"").unwrap();
+        writeln!(out, "roots: [{}]", root_atoms.join(" ")).unwrap();
+        writeln!(out, "installed: [{}]", installed.join(" ")).unwrap();
+        out
+    }
+
+    /// A JSON array of where to fetch each package's source from and the
+    /// checksums to verify it against. Packages without a `url` section,
+    /// like `conf-` packages, have nothing to fetch and are left out.
+    pub fn fetch_manifest(&self, repository: &OpamRepository) -> Result<String, Box<dyn Error>> {
+        let mut manifest = vec![];
+        for (name, version) in &self.packages {
+            if let Some(source) = repository.source(name, &version.0)? {
+                manifest.push(json!({
+                    "name": name,
+                    "version": version.0,
+                    "src": source.src,
+                    "checksum": source.checksums,
+                }));
+            }
+        }
+        Ok(serde_json::to_string_pretty(&manifest)?)
+    }
+}
+
+// booleans are bare in opam files, other values are strings
+fn variable_value(value: &OpamVersion) -> String {
+    match as_bool(value) {
+        Some(value) => value.to_string(),
+        None => format!("\"{}\"", value),
+    }
+}
+
+/// The project a lock file is for, from its `<project>.opam.locked` name.
+pub fn lock_file_project(path: &str) -> Option<&str> {
+    std::path::Path::new(path)
+        .file_name()?
+        .to_str()?
+        .strip_suffix(".opam.locked")
+        .filter(|project| !project.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solution() -> OpamSolution {
+        let selected = [
+            (OpamPackage::Base("dune".to_string()), "3.17.2"),
+            (OpamPackage::Base("ocaml".to_string()), "5.3.0"),
+            (OpamPackage::Base("project".to_string()), "1.0.0"),
+            (OpamPackage::Var("os".to_string()), "linux"),
+            (OpamPackage::Var("with-test".to_string()), "false"),
+            (OpamPackage::Installed("ocaml".to_string()), "5.3.0"),
+        ]
+        .map(|(package, version)| (package, OpamVersion(version.to_string())));
+        OpamSolution::new(selected.iter().map(|(package, version)| (package, version)))
+    }
+
+    #[test]
+    fn test_lock_file() {
+        assert_eq!(
+            solution().lock_file("project"),
+            "opam-version: \"2.0\"\n\
+             name: \"project\"\n\
+             version: \"1.0.0\"\n\
+             depends: [\n  \
+             \"dune\" {= \"3.17.2\"}\n  \
+             \"ocaml\" {= \"5.3.0\"}\n\
+             ]\n\
+             # Solved with the variables:\n\
+             #   os = \"linux\"\n\
+             #   with-test = false\n"
+        );
+    }
+
+    #[test]
+    fn test_switch_export() {
+        assert_eq!(
+            solution().switch_export(&["project".to_string()]),
+            "opam-version: \"2.0\"\n\
+             roots: [\"project.1.0.0\"]\n\
+             installed: [\"dune.3.17.2\" \"ocaml.5.3.0\" \"project.1.0.0\"]\n"
+        );
+    }
+
+    #[test]
+    fn test_lock_file_project() {
+        assert_eq!(
+            lock_file_project("out/project.opam.locked"),
+            Some("project")
+        );
+        assert_eq!(lock_file_project("project.opam"), None);
+        assert_eq!(lock_file_project(".opam.locked"), None);
+    }
+}
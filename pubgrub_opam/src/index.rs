@@ -1,17 +1,182 @@
 use core::fmt::Display;
 use pubgrub::Range;
-use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
 use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock, RwLock};
 
-use crate::parse::{available_versions_from_repo, RelOp};
+use crate::archive::OpamArchive;
+use crate::metadata::{PackageInfo, SearchIndex};
+use crate::opam_file::parse_opam_file;
+use crate::parse::{
+    available_versions_from_repo, package_version_dir, parse_dependencies, read_opam_file,
+    read_opam_metadata, OpamJson, RelOp,
+};
+use crate::switch::SwitchState;
 use crate::version::OpamVersion;
 
 pub type PackageName = String;
 
+/// The name of the layer `OpamRepository::new` creates.
+pub const DEFAULT_LAYER: &str = "default";
+/// The name of the layer `OpamRepository::with_overlay` adds.
+pub const OVERLAY_LAYER: &str = "overlay";
+
+/// The package data a solve reads from. It is never mutated, so one
+/// repository can be shared between any number of concurrent solves.
+pub struct OpamRepository {
+    /// Looked through in order, so the first layer with a version of a
+    /// package defines it.
+    pub layers: Vec<OpamLayer>,
+    /// Packages whose only version is the one they're pinned to.
+    pub pins: HashMap<PackageName, Pin>,
+    domains: OnceLock<Domains>,
+    cache: RepositoryCache,
+}
+
+/// What's been read from a repository's layers, kept so that each package
+/// is listed and each opam file parsed at most once.
+#[derive(Default)]
+struct RepositoryCache {
+    versions: RwLock<HashMap<PackageName, Vec<OpamVersion>>>,
+    parsed: RwLock<HashMap<(PackageName, String), Arc<ParsedVersion>>>,
+    avoided: RwLock<HashMap<PackageName, HashMap<String, Avoidance>>>,
+    search: OnceLock<SearchIndex>,
+}
+
+// what a solve needs of a package version's opam file, read once
+struct ParsedVersion {
+    formulas: Vec<PackageFormula>,
+    flags: Vec<String>,
+    maintenance_intent: Vec<String>,
+}
+
+/// One of the repositories an `OpamRepository` is made of, like each
+/// repository configured in an opam switch.
+pub struct OpamLayer {
+    pub name: String,
+    pub source: RepositorySource,
+    /// Hide every version later layers have of a package this layer defines.
+    pub shadow: bool,
+}
+
+/// Where a layer's packages are read from.
+pub enum RepositorySource {
+    /// A directory laid out like an opam repository's `packages` directory.
+    Directory(String),
+    /// The `index.tar.gz` an opam repository publishes.
+    Archive(OpamArchive),
+}
+
+/// How an overlay is combined with the packages it is layered over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverlayPrecedence {
+    /// A package defined in the overlay hides every base version of it.
+    Shadow,
+    /// Versions from both are offered, the overlay's wins where both have one.
+    #[default]
+    Overlay,
+    /// Versions from both are offered, the base's wins where both have one.
+    Base,
+}
+
+/// A package pinned to a source, as `pin-depends:` pins it. The pinned
+/// version replaces every version the repository has.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pin {
+    pub version: OpamVersion,
+    /// A URL like `git+https://example.com/foo.git#main`, or a local path.
+    pub url: String,
+}
+
+/// Where a package version's source is fetched from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpamSource {
+    pub src: String,
+    /// The hashes the source is verified against, like `sha256=...`.
+    pub checksums: Vec<String>,
+}
+
+/// Why opam's solver would only pick a package version if nothing else works.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Avoidance {
+    /// The version is flagged `avoid-version`.
+    AvoidVersion,
+    /// The version is flagged `deprecated`.
+    Deprecated,
+    /// The package's `x-maintenance-intent` doesn't cover the version.
+    Unmaintained,
+}
+
+/// The values variables are compared against and the members of conflict
+/// classes, which are the versions of `Var` and `ConflictClass` packages.
+/// Scanned from every package in a repository up front, so the versions
+/// offered don't depend on which formulas a solve happens to reach first.
+#[derive(Debug, Clone, Default)]
+pub struct Domains {
+    variables: HashMap<String, BTreeSet<OpamVersion>>,
+    conflict_classes: HashMap<String, BTreeSet<OpamVersion>>,
+}
+
 pub struct OpamIndex {
-    pub repo: String,
-    pub debug: Cell<bool>,
-    pub version_debug: Cell<bool>,
+    pub repository: Arc<OpamRepository>,
+    /// Values declared for variables, used instead of the repository's.
+    pub variables: HashMap<String, Vec<OpamVersion>>,
+    /// Require every `depopts:` package, as `opam install --with-depopts` does.
+    pub with_depopts: bool,
+    /// The flags opam would set for this solve. Without them every flag is
+    /// left for the solver to choose.
+    pub flags: Option<OpamFlags>,
+    /// Fold known variables into dependency formulas and flatten them before
+    /// encoding, so that a solve makes fewer decisions.
+    pub normalize: bool,
+    /// The versions already installed in a switch, which are tried before
+    /// any other version of their package.
+    pub installed: HashMap<PackageName, OpamVersion>,
+}
+
+/// The flags `opam install` sets, which filters read as variables.
+/// `with-test`, `with-doc` and `with-dev-setup` only hold for the packages
+/// asked for, as with `opam install --with-test`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpamFlags {
+    /// The packages asked for.
+    pub roots: HashSet<PackageName>,
+    pub with_test: bool,
+    pub with_doc: bool,
+    pub with_dev_setup: bool,
+    /// Build dependencies are part of every solve.
+    pub build: bool,
+    /// `post` dependencies are installed along with the package.
+    pub post: bool,
+    /// Packages pinned to a development source, for which `dev` holds. It
+    /// also holds for any other package at its `dev` version.
+    pub dev: HashSet<PackageName>,
+}
+
+impl Default for OpamFlags {
+    fn default() -> Self {
+        Self {
+            roots: HashSet::new(),
+            with_test: false,
+            with_doc: false,
+            with_dev_setup: false,
+            build: true,
+            post: true,
+            dev: HashSet::new(),
+        }
+    }
+}
+
+impl OpamFlags {
+    pub fn new(roots: impl IntoIterator<Item = PackageName>) -> Self {
+        Self {
+            roots: roots.into_iter().collect(),
+            ..Self::default()
+        }
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -66,6 +231,25 @@ pub enum PackageFormula {
         name: PackageName,
         package: PackageName,
     },
+    /// `package` can't be installed alongside the versions of `name` that
+    /// `formula` matches.
+    Conflict {
+        name: PackageName,
+        formula: VersionFormula,
+        package: PackageName,
+    },
+    /// `package` optionally depends on `name`: when `name` is installed,
+    /// `formula` must hold.
+    Depopt {
+        name: PackageName,
+        formula: VersionFormula,
+        package: PackageName,
+    },
+    /// The `available:` filter of `package`.
+    Available {
+        package: PackageName,
+        formula: VersionFormula,
+    },
 }
 
 impl Display for RelOp {
@@ -122,6 +306,25 @@ impl Display for PackageFormula {
             PackageFormula::ConflictClass { name, package } => {
                 write!(f, "(Conflict class ({}, {}) )", name, package)
             }
+            PackageFormula::Conflict {
+                name,
+                formula,
+                package,
+            } => {
+                write!(f, "{} conflicts with ", package)?;
+                write_atom(f, name, formula)
+            }
+            PackageFormula::Depopt {
+                name,
+                formula,
+                package,
+            } => {
+                write!(f, "{} optionally depends on ", package)?;
+                write_atom(f, name, formula)
+            }
+            PackageFormula::Available { package, formula } => {
+                write!(f, "({} {{available: {}}})", package, formula)
+            }
             PackageFormula::And(binary) => {
                 write!(f, "({} & {})", binary.lhs, binary.rhs)
             }
@@ -132,24 +335,661 @@ impl Display for PackageFormula {
     }
 }
 
-impl OpamIndex {
+// a package and its constraints as they'd be written in an opam file
+fn write_atom(
+    f: &mut std::fmt::Formatter<'_>,
+    name: &str,
+    formula: &VersionFormula,
+) -> std::fmt::Result {
+    match formula {
+        VersionFormula::Version(range) if range.0 == Range::full() => write!(f, "{}", name),
+        VersionFormula::Version(range) => write!(f, "{} {{{}}}", name, range),
+        _ => write!(f, "{} {{{}}}", name, formula),
+    }
+}
+
+impl FromStr for OverlayPrecedence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shadow" => Ok(OverlayPrecedence::Shadow),
+            "overlay" => Ok(OverlayPrecedence::Overlay),
+            "base" => Ok(OverlayPrecedence::Base),
+            _ => Err(format!(
+                "Unknown overlay precedence {}, expected shadow, overlay or base",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for OverlayPrecedence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OverlayPrecedence::Shadow => write!(f, "shadow"),
+            OverlayPrecedence::Overlay => write!(f, "overlay"),
+            OverlayPrecedence::Base => write!(f, "base"),
+        }
+    }
+}
+
+impl Display for Avoidance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Avoidance::AvoidVersion => write!(f, "avoid-version"),
+            Avoidance::Deprecated => write!(f, "deprecated"),
+            Avoidance::Unmaintained => write!(f, "unmaintained"),
+        }
+    }
+}
+
+// Whether an `x-maintenance-intent` covers the version at this position of
+// the package's versions in descending order. `(latest-N)` covers the N
+// versions before the latest too, `1.x` every `1.` version.
+fn is_maintained(intent: &[String], versions: &[OpamVersion], position: usize) -> bool {
+    let version = versions[position].0.as_str();
+    intent.iter().any(|pattern| match pattern.as_str() {
+        "(any)" => true,
+        "(none)" => false,
+        "(latest)" => position == 0,
+        pattern => match pattern
+            .strip_prefix("(latest-")
+            .and_then(|n| n.strip_suffix(')'))
+        {
+            Some(n) => n.parse::<usize>().is_ok_and(|n| position <= n),
+            None => match pattern.strip_suffix(".x") {
+                Some(prefix) => version
+                    .strip_prefix(prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('.')),
+                None => pattern == version,
+            },
+        },
+    })
+}
+
+impl Pin {
+    /// The directory of a pin to a local source. A URL without a scheme is
+    /// a path, as in opam.
+    pub fn local_path(&self) -> Option<PathBuf> {
+        let url = self.url.split('#').next().unwrap_or_default();
+        let url = ["git+", "hg+", "darcs+"]
+            .iter()
+            .find_map(|vcs| url.strip_prefix(vcs))
+            .unwrap_or(url);
+        match url.strip_prefix("file://") {
+            Some(path) => Some(PathBuf::from(path)),
+            None if !url.contains("://") => Some(PathBuf::from(url)),
+            None => None,
+        }
+    }
+
+    /// The opam file a local source has for a package, if any.
+    pub fn opam_file(&self, package: &str) -> Option<PathBuf> {
+        let dir = self.local_path()?;
+        [dir.join(format!("{}.opam", package)), dir.join("opam")]
+            .into_iter()
+            .find(|path| path.is_file())
+    }
+}
+
+impl RepositorySource {
+    /// Read a repository from a `packages` directory, or from an archive if
+    /// the path ends in `.tar.gz` or `.tgz`.
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Ok(RepositorySource::Archive(OpamArchive::open(path)?))
+        } else if Path::new(path).is_dir() {
+            Ok(RepositorySource::Directory(path.to_string()))
+        } else {
+            Err(format!("Opam repository {} is not a directory or archive", path).into())
+        }
+    }
+
+    pub fn has_package(&self, package: &str) -> bool {
+        match self {
+            RepositorySource::Directory(repo) => Path::new(repo).join(package).is_dir(),
+            RepositorySource::Archive(archive) => archive.has_package(package),
+        }
+    }
+
+    pub fn has_version(&self, package: &str, version: &str) -> bool {
+        match self {
+            RepositorySource::Directory(repo) => {
+                package_version_dir(repo, package, version).exists()
+            }
+            RepositorySource::Archive(archive) => archive.has_version(package, version),
+        }
+    }
+
+    pub fn packages(&self) -> Vec<PackageName> {
+        match self {
+            RepositorySource::Directory(repo) => std::fs::read_dir(repo)
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect(),
+            RepositorySource::Archive(archive) => archive.packages().cloned().collect(),
+        }
+    }
+
+    pub fn versions(&self, package: &str) -> Result<Vec<OpamVersion>, Box<dyn Error>> {
+        match self {
+            RepositorySource::Directory(repo) => available_versions_from_repo(repo, package),
+            RepositorySource::Archive(archive) => archive.versions(package),
+        }
+    }
+
+    pub fn read_metadata(&self, package: &str, version: &str) -> Result<OpamJson, Box<dyn Error>> {
+        match self {
+            RepositorySource::Directory(repo) => read_opam_metadata(repo, package, version),
+            RepositorySource::Archive(archive) => {
+                let content = archive
+                    .opam_file(package, version)
+                    .ok_or_else(|| format!("No opam file for {}.{}", package, version))?;
+                parse_opam_file(content).map_err(|e| {
+                    format!("Error parsing opam file of {}.{}:{}", package, version, e).into()
+                })
+            }
+        }
+    }
+}
+
+impl OpamRepository {
     pub fn new(repo: String) -> Self {
         Self {
-            repo,
-            debug: false.into(),
-            version_debug: false.into(),
+            layers: vec![OpamLayer {
+                name: DEFAULT_LAYER.to_string(),
+                source: RepositorySource::Directory(repo),
+                shadow: false,
+            }],
+            pins: HashMap::new(),
+            domains: OnceLock::new(),
+            cache: RepositoryCache::default(),
         }
     }
 
+    /// Add a repository at a rank, as `opam repository add --rank` does:
+    /// rank 1 is looked at first, and a rank past the end adds it last.
+    pub fn with_repository(mut self, name: String, source: RepositorySource, rank: usize) -> Self {
+        let position = rank.saturating_sub(1).min(self.layers.len());
+        self.layers.insert(
+            position,
+            OpamLayer {
+                name,
+                source,
+                shadow: false,
+            },
+        );
+        self.domains = OnceLock::new();
+        self.cache = RepositoryCache::default();
+        self
+    }
+
+    pub fn with_overlay(mut self, repo: String, precedence: OverlayPrecedence) -> Self {
+        let layer = OpamLayer {
+            name: OVERLAY_LAYER.to_string(),
+            source: RepositorySource::Directory(repo),
+            shadow: precedence == OverlayPrecedence::Shadow,
+        };
+        match precedence {
+            OverlayPrecedence::Base => self.layers.push(layer),
+            _ => self.layers.insert(0, layer),
+        }
+        self.domains = OnceLock::new();
+        self.cache = RepositoryCache::default();
+        self
+    }
+
+    /// Pin a package to a version and source, as `opam pin` does.
+    pub fn with_pin(mut self, package: PackageName, pin: Pin) -> Self {
+        self.pins.insert(package, pin);
+        self.domains = OnceLock::new();
+        self.cache = RepositoryCache::default();
+        self
+    }
+
+    /// Pin the `pin-depends:` of a project's opam file. A relative path is
+    /// relative to the project's directory.
+    pub fn with_pin_depends(mut self, project: &Path) -> Result<Self, Box<dyn Error>> {
+        for (package, mut pin) in read_opam_file(project)?.pin_depends()? {
+            if !pin.url.contains("://") && Path::new(&pin.url).is_relative() {
+                let dir = project.parent().unwrap_or(Path::new(""));
+                pin.url = dir.join(&pin.url).to_string_lossy().to_string();
+            }
+            self = self.with_pin(package, pin);
+        }
+        Ok(self)
+    }
+
+    /// Every package in any layer, and every pinned package.
+    pub fn packages(&self) -> Vec<PackageName> {
+        let mut packages: Vec<PackageName> = self
+            .layers
+            .iter()
+            .flat_map(|layer| layer.source.packages())
+            .chain(self.pins.keys().cloned())
+            .collect();
+        packages.sort();
+        packages.dedup();
+        packages
+    }
+
+    /// The variable values and conflict classes of the repository, scanned on
+    /// first use.
+    pub fn domains(&self) -> &Domains {
+        self.domains.get_or_init(|| Domains::scan(self))
+    }
+
+    // the layers that offer versions of a package
+    fn package_layers(&self, package: &str) -> Vec<&OpamLayer> {
+        let mut layers = vec![];
+        for layer in &self.layers {
+            if layer.source.has_package(package) {
+                layers.push(layer);
+                if layer.shadow {
+                    break;
+                }
+            }
+        }
+        layers
+    }
+
+    /// The layer a package version is read from.
+    pub fn layer(&self, package: &str, version: &str) -> Option<&OpamLayer> {
+        self.package_layers(package)
+            .into_iter()
+            .find(|layer| layer.source.has_version(package, version))
+    }
+
+    /// Read every version of every package, spread over the available cores,
+    /// so that a solve doesn't wait on the filesystem.
+    pub fn preload(&self) {
+        let packages = self.packages();
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = packages.len().div_ceil(threads).max(1);
+        std::thread::scope(|scope| {
+            for chunk in packages.chunks(chunk_size) {
+                scope.spawn(move || {
+                    for package in chunk {
+                        for version in self.available_versions(package) {
+                            // unreadable versions are reported when they're used
+                            let _ = self.dependencies(package, &version.to_string());
+                        }
+                    }
+                });
+            }
+        });
+    }
+
     pub fn available_versions(&self, package: &PackageName) -> Vec<OpamVersion> {
-        available_versions_from_repo(self.repo.as_str(), package).unwrap()
+        if let Some(versions) = self.cache.versions.read().unwrap().get(package) {
+            return versions.clone();
+        }
+        let versions = self.read_versions(package);
+        self.cache
+            .versions
+            .write()
+            .unwrap()
+            .insert(package.clone(), versions.clone());
+        versions
+    }
+
+    fn read_versions(&self, package: &PackageName) -> Vec<OpamVersion> {
+        if let Some(pin) = self.pins.get(package) {
+            return vec![pin.version.clone()];
+        }
+        self.repository_versions(package)
+    }
+
+    // the versions the layers have of a package, latest first, pinned or not
+    fn repository_versions(&self, package: &str) -> Vec<OpamVersion> {
+        let mut versions = vec![];
+        for layer in self.package_layers(package) {
+            match layer.source.versions(package) {
+                Ok(layer_versions) => versions.extend(layer_versions),
+                Err(e) => tracing::warn!(
+                    package = %package,
+                    repository = %layer.name,
+                    error = %e,
+                    "skipping unreadable versions"
+                ),
+            }
+        }
+        versions.sort();
+        versions.dedup();
+        versions.reverse();
+        versions
+    }
+
+    /// Whether any layer has versions of a package, or it's pinned.
+    pub fn has_package(&self, package: &str) -> bool {
+        self.pins.contains_key(package)
+            || self
+                .layers
+                .iter()
+                .any(|layer| layer.source.has_package(package))
+    }
+
+    /// Whether the opam file for this package version is read from a layer
+    /// other than the default one.
+    pub fn is_overlay(&self, package: &str, version: &str) -> bool {
+        self.layer(package, version)
+            .is_some_and(|layer| layer.name != DEFAULT_LAYER)
+    }
+
+    /// The metadata of a package version, from the layer that defines it. A
+    /// package pinned to a local source with an opam file is read from there,
+    /// and one pinned to a version no layer has is read from its latest
+    /// version, as opam does.
+    pub fn read_metadata(&self, package: &str, version: &str) -> Result<OpamJson, Box<dyn Error>> {
+        let pin = self.pins.get(package);
+        if let Some(opam_file) = pin.and_then(|pin| pin.opam_file(package)) {
+            return read_opam_file(&opam_file);
+        }
+        match (self.layer(package, version), pin) {
+            (Some(layer), _) => layer.source.read_metadata(package, version),
+            (None, Some(pin)) => match self.repository_versions(package).first() {
+                Some(latest) => self.read_metadata(package, &latest.0),
+                None => Err(format!(
+                    "{} is pinned to {}, which has no opam file here, and no repository has {}",
+                    package, pin.url, package
+                )
+                .into()),
+            },
+            (None, None) => Err(format!("No repository has {}.{}", package, version).into()),
+        }
+    }
+
+    /// The descriptive metadata of a package version.
+    pub fn package_info(
+        &self,
+        package: &str,
+        version: &str,
+    ) -> Result<PackageInfo, Box<dyn Error>> {
+        let metadata = self.read_metadata(package, version)?;
+        PackageInfo::new(package, &OpamVersion(version.to_string()), &metadata)
+    }
+
+    /// The search index and reverse dependencies of the repository, built
+    /// on first use.
+    pub fn search_index(&self) -> &SearchIndex {
+        self.cache.search.get_or_init(|| SearchIndex::build(self))
+    }
+
+    /// Where a package version's source is fetched from, if it has a
+    /// source. A pinned package is fetched from its pin, unverified.
+    pub fn source(
+        &self,
+        package: &str,
+        version: &str,
+    ) -> Result<Option<OpamSource>, Box<dyn Error>> {
+        if let Some(pin) = self.pins.get(package) {
+            return Ok(Some(OpamSource {
+                src: pin.url.clone(),
+                checksums: vec![],
+            }));
+        }
+        Ok(self.read_metadata(package, version)?.source())
+    }
+
+    /// Why a package version should only be picked if nothing else works,
+    /// if it should.
+    pub fn avoided(&self, package: &str, version: &str) -> Option<Avoidance> {
+        if let Some(avoided) = self.cache.avoided.read().unwrap().get(package) {
+            return avoided.get(version).copied();
+        }
+        let avoided = self.read_avoided(package);
+        let avoidance = avoided.get(version).copied();
+        self.cache
+            .avoided
+            .write()
+            .unwrap()
+            .insert(package.to_string(), avoided);
+        avoidance
+    }
+
+    fn read_avoided(&self, package: &str) -> HashMap<String, Avoidance> {
+        let versions = self.available_versions(&package.to_string());
+        let parsed = |version: &OpamVersion| match self.parsed(package, &version.0) {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                tracing::warn!(
+                    package = %package,
+                    version = %version,
+                    error = %e,
+                    "skipping unreadable flags"
+                );
+                None
+            }
+        };
+        // the latest version states the intent for the whole package
+        let intent = versions
+            .first()
+            .and_then(parsed)
+            .map(|parsed| parsed.maintenance_intent.clone())
+            .unwrap_or_default();
+        let mut avoided = HashMap::new();
+        for (position, version) in versions.iter().enumerate() {
+            let flags = parsed(version)
+                .map(|parsed| parsed.flags.clone())
+                .unwrap_or_default();
+            let avoidance = if flags.iter().any(|flag| flag == "avoid-version") {
+                Avoidance::AvoidVersion
+            } else if flags.iter().any(|flag| flag == "deprecated") {
+                Avoidance::Deprecated
+            } else if !intent.is_empty() && !is_maintained(&intent, &versions, position) {
+                Avoidance::Unmaintained
+            } else {
+                continue;
+            };
+            avoided.insert(version.0.clone(), avoidance);
+        }
+        avoided
     }
 
-    pub fn set_debug(&self, flag: bool) {
-        self.debug.set(flag);
+    /// The dependency formulas of a package version.
+    pub fn dependencies(
+        &self,
+        package: &str,
+        version: &str,
+    ) -> Result<Vec<PackageFormula>, Box<dyn Error>> {
+        Ok(self.parsed(package, version)?.formulas.clone())
     }
 
-    pub fn set_version_debug(&self, flag: bool) {
-        self.version_debug.set(flag);
+    // the dependencies, flags and maintenance intent of a package version,
+    // cached together so its opam file is only read once
+    fn parsed(&self, package: &str, version: &str) -> Result<Arc<ParsedVersion>, Box<dyn Error>> {
+        let key = (package.to_string(), version.to_string());
+        if let Some(parsed) = self.cache.parsed.read().unwrap().get(&key) {
+            return Ok(parsed.clone());
+        }
+        let metadata = self.read_metadata(package, version)?;
+        let flags = metadata.flags();
+        let maintenance_intent = metadata.maintenance_intent();
+        let parsed = Arc::new(ParsedVersion {
+            formulas: parse_dependencies(metadata, package)?,
+            flags,
+            maintenance_intent,
+        });
+        self.cache
+            .parsed
+            .write()
+            .unwrap()
+            .insert(key, parsed.clone());
+        Ok(parsed)
+    }
+}
+
+impl Domains {
+    /// Read the dependencies of every version of every package. Versions
+    /// that fail to parse are skipped, as a solve would fail on them anyway.
+    pub fn scan(repository: &OpamRepository) -> Self {
+        repository.preload();
+        let mut domains = Domains::default();
+        for package in repository.packages() {
+            for version in repository.available_versions(&package) {
+                let version = version.to_string();
+                match repository.dependencies(&package, &version) {
+                    Ok(formulas) => formulas
+                        .iter()
+                        .for_each(|formula| domains.add_package_formula(formula)),
+                    Err(e) => tracing::warn!(
+                        package = %package,
+                        version = %version,
+                        error = %e,
+                        "skipping unreadable package"
+                    ),
+                }
+            }
+        }
+        domains
+    }
+
+    pub fn add_variable(&mut self, var: &str, value: &OpamVersion) {
+        self.variables
+            .entry(var.to_string())
+            .or_default()
+            .insert(value.clone());
+    }
+
+    pub fn add_conflict_class(&mut self, name: &str, package: &str) {
+        self.conflict_classes
+            .entry(name.to_string())
+            .or_default()
+            .insert(OpamVersion(package.to_string()));
+    }
+
+    /// The values a variable is compared against, if it ever is.
+    pub fn variable_values(&self, var: &str) -> Option<Vec<OpamVersion>> {
+        self.variables
+            .get(var)
+            .map(|values| values.iter().cloned().collect())
+    }
+
+    /// The packages in a conflict class, none for an unknown class.
+    pub fn conflict_class_members(&self, name: &str) -> Vec<OpamVersion> {
+        self.conflict_classes
+            .get(name)
+            .map(|members| members.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn add_package_formula(&mut self, formula: &PackageFormula) {
+        match formula {
+            PackageFormula::Or(binary) | PackageFormula::And(binary) => {
+                self.add_package_formula(&binary.lhs);
+                self.add_package_formula(&binary.rhs);
+            }
+            PackageFormula::ConflictClass { name, package } => {
+                self.add_conflict_class(name, package)
+            }
+            PackageFormula::Base { formula, .. }
+            | PackageFormula::Depext { formula, .. }
+            | PackageFormula::Conflict { formula, .. }
+            | PackageFormula::Depopt { formula, .. }
+            | PackageFormula::Available { formula, .. } => self.add_version_formula(formula),
+        }
+    }
+
+    fn add_version_formula(&mut self, formula: &VersionFormula) {
+        match formula {
+            VersionFormula::And(binary) | VersionFormula::Or(binary) => {
+                self.add_version_formula(&binary.lhs);
+                self.add_version_formula(&binary.rhs);
+            }
+            VersionFormula::Comparator { binary, .. } => {
+                match (binary.lhs.as_ref(), binary.rhs.as_ref()) {
+                    (VersionFormula::Lit(value), VersionFormula::Variable(var))
+                    | (VersionFormula::Variable(var), VersionFormula::Lit(value)) => {
+                        self.add_variable(var, value)
+                    }
+                    (lhs, rhs) => {
+                        self.add_version_formula(lhs);
+                        self.add_version_formula(rhs);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl OpamIndex {
+    pub fn new(repo: String) -> Self {
+        Self::from_repository(Arc::new(OpamRepository::new(repo)))
+    }
+
+    /// A fresh solve over a repository that may be shared with other solves.
+    pub fn from_repository(repository: Arc<OpamRepository>) -> Self {
+        Self {
+            repository,
+            variables: HashMap::new(),
+            with_depopts: false,
+            flags: None,
+            normalize: true,
+            installed: HashMap::new(),
+        }
+    }
+
+    /// Declare the values a variable can take, instead of the ones the
+    /// repository compares it against.
+    pub fn with_variable(mut self, var: &str, values: Vec<OpamVersion>) -> Self {
+        self.variables.insert(var.to_string(), values);
+        self
+    }
+
+    pub fn with_depopts(mut self, with_depopts: bool) -> Self {
+        self.with_depopts = with_depopts;
+        self
+    }
+
+    pub fn with_flags(mut self, flags: OpamFlags) -> Self {
+        self.flags = Some(flags);
+        self
+    }
+
+    pub fn with_normalize(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// Start from a switch: keep its installed versions where possible, and
+    /// give its variables the values the switch has.
+    pub fn with_switch(mut self, switch: &SwitchState) -> Self {
+        self.installed.extend(switch.installed.clone());
+        for (var, value) in &switch.variables {
+            self = self.with_variable(var, vec![value.clone()]);
+        }
+        self
+    }
+
+    /// The versions of a package in the order they're tried: the installed
+    /// version, then descending, except that versions opam avoids come
+    /// after every other one.
+    pub fn available_versions(&self, package: &PackageName) -> Vec<OpamVersion> {
+        let installed = self.installed.get(package);
+        let (avoided, preferred): (Vec<_>, Vec<_>) = self
+            .repository
+            .available_versions(package)
+            .into_iter()
+            .partition(|version| self.repository.avoided(package, &version.0).is_some());
+        let (installed, others): (Vec<_>, Vec<_>) = preferred
+            .into_iter()
+            .chain(avoided)
+            .partition(|version| Some(version) == installed);
+        installed.into_iter().chain(others).collect()
+    }
+
+    /// The versions of a package that could be installed, which is none for
+    /// a package the repository doesn't have.
+    pub fn installable_versions(&self, package: &PackageName) -> Vec<OpamVersion> {
+        if self.repository.has_package(package) {
+            self.available_versions(package)
+        } else {
+            vec![]
+        }
     }
 }
@@ -1,4 +1,11 @@
+pub mod archive;
 pub mod deps;
+pub mod export;
 pub mod index;
+pub mod metadata;
+pub mod normalize;
+pub mod opam_file;
 pub mod parse;
+pub mod platform;
+pub mod switch;
 pub mod version;
@@ -14,8 +14,6 @@ fn solve_repo(
     repo: &str,
 ) -> Result<SelectedDependencies<OpamIndex>, Box<dyn Error>> {
     let index = OpamIndex::new(repo.to_string());
-    index.set_debug(true);
-    index.set_version_debug(true);
 
     let sol: SelectedDependencies<OpamIndex> = match pubgrub::resolve(&index, pkg, version) {
         Ok(sol) => Ok(sol),
@@ -27,8 +25,6 @@ fn solve_repo(
         Err(err) => panic!("{:?}", err),
     }?;
 
-    index.set_debug(false);
-
     fn get_resolved_deps<'a>(
         index: &'a OpamIndex,
         sol: &'a SelectedDependencies<OpamIndex>,
@@ -45,7 +41,7 @@ fn solve_repo(
                         OpamPackage::Base(name) => {
                             dependents.insert((name, solved_version));
                         }
-                        OpamPackage::Lor { lhs: _, rhs: _ } => {
+                        OpamPackage::Lor(_) => {
                             dependents.extend(get_resolved_deps(
                                 &index,
                                 sol,
@@ -96,6 +92,31 @@ fn solve_repo(
                                 solved_version,
                             ));
                         }
+                        OpamPackage::Conflict(_) => {
+                            dependents.extend(get_resolved_deps(
+                                &index,
+                                sol,
+                                &dep_package,
+                                solved_version,
+                            ));
+                        }
+                        OpamPackage::Depopt(_) => {
+                            dependents.extend(get_resolved_deps(
+                                &index,
+                                sol,
+                                &dep_package,
+                                solved_version,
+                            ));
+                        }
+                        OpamPackage::Installed(_) => {}
+                        OpamPackage::Available { .. } => {
+                            dependents.extend(get_resolved_deps(
+                                &index,
+                                sol,
+                                &dep_package,
+                                solved_version,
+                            ));
+                        }
                     };
                 }
                 dependents
@@ -110,9 +131,10 @@ fn solve_repo(
     println!("\nSolution Set:");
     for (package, version) in &sol {
         match package {
-            OpamPackage::Base(name) => {
-                println!("\t({}, {})", name, version);
-            }
+            OpamPackage::Base(name) => match index.repository.avoided(name, &version.0) {
+                Some(avoidance) => println!("\t({}, {})\t[{}]", name, version, avoidance),
+                None => println!("\t({}, {})", name, version),
+            },
             OpamPackage::Var(name) => {
                 println!("\t{} = {}", name, version);
             }
@@ -156,6 +178,10 @@ fn solve_repo(
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_writer(std::io::stderr)
+        .init();
     let _ = solve_repo(
         OpamPackage::from_str("A").unwrap(),
         "1.0.0".parse::<OpamVersion>().unwrap(),
@@ -168,7 +194,15 @@ fn main() -> Result<(), Box<dyn Error>> {
 mod tests {
 
     use pubgrub::Range;
-    use pubgrub_opam::deps::{FALSE_VERSION, TRUE_VERSION};
+    use pubgrub_opam::deps::{ABSENT_VERSION, FALSE_VERSION, TRUE_VERSION};
+    use pubgrub_opam::export::OpamSolution;
+    use pubgrub_opam::index::{
+        Avoidance, OpamFlags, OpamRepository, OverlayPrecedence, RepositorySource, DEFAULT_LAYER,
+    };
+    use pubgrub_opam::switch::SwitchState;
+    use serde_json::json;
+    use std::path::Path;
+    use std::sync::Arc;
 
     use super::*;
 
@@ -193,6 +227,121 @@ mod tests {
         Ok(())
     }
 
+    fn solve_with_overlay(
+        precedence: OverlayPrecedence,
+    ) -> Result<SelectedDependencies<OpamIndex>, PubGrubError<OpamIndex>> {
+        let repository = OpamRepository::new("./example-repo/packages".to_string())
+            .with_overlay("./example-overlay/packages".to_string(), precedence);
+        let index = OpamIndex::from_repository(Arc::new(repository));
+        pubgrub::resolve(
+            &index,
+            OpamPackage::from_str("E").unwrap(),
+            "1.0.0".parse::<OpamVersion>().unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_overlay() -> Result<(), Box<dyn Error>> {
+        // the overlay's B 2.0.0 fixes the conflict that makes A 2.0.0 unsolvable
+        let sol = solve_with_overlay(OverlayPrecedence::Overlay)?;
+        assert_eq!(
+            sol.get(&OpamPackage::from_str("D").unwrap()),
+            Some(&"3.0.0".parse::<OpamVersion>().unwrap())
+        );
+        let sol = solve_with_overlay(OverlayPrecedence::Shadow)?;
+        assert_eq!(
+            sol.get(&OpamPackage::from_str("B").unwrap()),
+            Some(&"2.0.0".parse::<OpamVersion>().unwrap())
+        );
+        assert!(solve_with_overlay(OverlayPrecedence::Base).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlay_source() {
+        let repository = OpamRepository::new("./example-repo/packages".to_string())
+            .with_overlay(
+                "./example-overlay/packages".to_string(),
+                OverlayPrecedence::Overlay,
+            );
+        assert!(repository.is_overlay("B", "2.0.0"));
+        assert!(repository.is_overlay("E", "1.0.0"));
+        assert!(!repository.is_overlay("B", "1.0.0"));
+        assert_eq!(
+            repository.available_versions(&"B".to_string()),
+            vec![
+                "2.0.0".parse::<OpamVersion>().unwrap(),
+                "1.0.0".parse::<OpamVersion>().unwrap()
+            ]
+        );
+    }
+
+    fn archive_repository(rank: usize) -> Result<OpamRepository, Box<dyn Error>> {
+        Ok(
+            OpamRepository::new("./example-repo/packages".to_string()).with_repository(
+                "archive".to_string(),
+                RepositorySource::open("./example-archive/index.tar.gz")?,
+                rank,
+            ),
+        )
+    }
+
+    #[test]
+    fn test_archive_repository() -> Result<(), Box<dyn Error>> {
+        let repository = archive_repository(2)?;
+        assert_eq!(
+            repository.available_versions(&"B".to_string()),
+            vec![
+                "3.0.0".parse::<OpamVersion>().unwrap(),
+                "2.0.0".parse::<OpamVersion>().unwrap(),
+                "1.0.0".parse::<OpamVersion>().unwrap()
+            ]
+        );
+        assert!(repository.is_overlay("B", "3.0.0"));
+        let index = OpamIndex::from_repository(Arc::new(repository));
+        let sol = pubgrub::resolve(
+            &index,
+            OpamPackage::from_str("B").unwrap(),
+            "3.0.0".parse::<OpamVersion>().unwrap(),
+        )?;
+        assert_eq!(
+            sol.get(&OpamPackage::from_str("D").unwrap()),
+            Some(&"3.0.0".parse::<OpamVersion>().unwrap())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_repository_rank() -> Result<(), Box<dyn Error>> {
+        // both define D 1.0.0, and the lower rank wins
+        let repository = archive_repository(1)?;
+        assert_eq!(repository.layer("D", "1.0.0").unwrap().name, "archive");
+        assert_eq!(repository.dependencies("D", "1.0.0")?.len(), 1);
+        let repository = archive_repository(2)?;
+        assert_eq!(repository.layer("D", "1.0.0").unwrap().name, DEFAULT_LAYER);
+        assert!(repository.dependencies("D", "1.0.0")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_preload() -> Result<(), Box<dyn Error>> {
+        let preloaded = archive_repository(1)?;
+        preloaded.preload();
+        let repository = archive_repository(1)?;
+        for package in repository.packages() {
+            let versions = repository.available_versions(&package);
+            assert_eq!(preloaded.available_versions(&package), versions);
+            for version in versions {
+                let version = version.to_string();
+                assert_eq!(
+                    preloaded.dependencies(&package, &version).ok(),
+                    repository.dependencies(&package, &version).ok()
+                );
+            }
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_package_formula() -> Result<(), Box<dyn Error>> {
         solve_repo(
@@ -460,6 +609,582 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_filtered_package_formula_literal() -> Result<(), Box<dyn Error>> {
+        let sol = solve_repo(
+            OpamPackage::from_str("filtered-package-formula-literal").unwrap(),
+            "1.0.0".parse::<OpamVersion>().unwrap(),
+            "./example-repo/packages",
+        )?;
+        assert_eq!(sol.get(&OpamPackage::Base("C".to_string())), None);
+        assert!(sol.contains_key(&OpamPackage::Base("B".to_string())));
+        assert_eq!(
+            sol.get(&OpamPackage::Var("jobs".to_string())),
+            Some("2".parse::<OpamVersion>().as_ref().unwrap())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_domains() {
+        let repository = OpamRepository::new("./example-repo/packages".to_string());
+        let domains = repository.domains();
+        assert_eq!(
+            domains.variable_values("jobs"),
+            Some(vec![OpamVersion("2".to_string())])
+        );
+        assert!(domains
+            .variable_values("os")
+            .is_some_and(|values| values.contains(&OpamVersion("win32".to_string()))));
+        assert_eq!(domains.variable_values("with-test"), None);
+        assert_eq!(
+            domains.conflict_class_members("compiler"),
+            vec![
+                OpamVersion("conflict-class-a".to_string()),
+                OpamVersion("conflict-class-b".to_string()),
+            ]
+        );
+        assert!(domains.conflict_class_members("not-a-class").is_empty());
+    }
+
+    #[test]
+    fn test_declared_variable() -> Result<(), Box<dyn Error>> {
+        let index = OpamIndex::new("./example-repo/packages".to_string())
+            .with_variable("jobs", vec![OpamVersion("1".to_string())]);
+        let sol = pubgrub::resolve(
+            &index,
+            OpamPackage::from_str("filtered-package-formula-literal").unwrap(),
+            "1.0.0".parse::<OpamVersion>().unwrap(),
+        )?;
+        assert_eq!(sol.get(&OpamPackage::Base("D".to_string())), None);
+        assert_eq!(
+            sol.get(&OpamPackage::Var("jobs".to_string())),
+            Some(&OpamVersion("1".to_string()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_platform_variables() -> Result<(), Box<dyn Error>> {
+        // the family follows from the declared os rather than defaulting to debian
+        let index = OpamIndex::new("./example-repo/packages".to_string())
+            .with_variable("os", vec![OpamVersion("freebsd".to_string())]);
+        let sol = pubgrub::resolve(
+            &index,
+            OpamPackage::from_str("filtered-package-formula-variable-string").unwrap(),
+            "1.0.0".parse::<OpamVersion>().unwrap(),
+        )?;
+        assert_eq!(
+            sol.get(&OpamPackage::Var("os-family".to_string())),
+            Some(&OpamVersion("bsd".to_string()))
+        );
+        assert_eq!(sol.get(&OpamPackage::Base("A".to_string())), None);
+        Ok(())
+    }
+
+    fn solve_flags(flags: OpamFlags) -> Result<SelectedDependencies<OpamIndex>, Box<dyn Error>> {
+        let index = OpamIndex::new("./example-repo/packages".to_string()).with_flags(flags);
+        Ok(pubgrub::resolve(
+            &index,
+            OpamPackage::from_str("flags").unwrap(),
+            "1.0.0".parse::<OpamVersion>().unwrap(),
+        )?)
+    }
+
+    #[test]
+    fn test_flags() -> Result<(), Box<dyn Error>> {
+        let base = |name: &str| OpamPackage::Base(name.to_string());
+        let sol = solve_flags(OpamFlags::new(["flags".to_string()]))?;
+        assert!(!sol.contains_key(&base("D")));
+        assert!(sol.contains_key(&base("C")));
+        assert!(!sol.contains_key(&base("A")));
+        // `with-test` holds for the root but not for its dependencies
+        let sol = solve_flags(OpamFlags {
+            with_test: true,
+            ..OpamFlags::new(["flags".to_string()])
+        })?;
+        assert!(sol.contains_key(&base("D")));
+        assert!(!sol.contains_key(&base("B")));
+        // `dev` holds for a package pinned to a development source
+        let sol = solve_flags(OpamFlags {
+            dev: ["flags".to_string()].into(),
+            ..OpamFlags::new(["flags".to_string()])
+        })?;
+        assert!(sol.contains_key(&base("A")));
+        // a value declared for `with-test` applies to every package
+        let index = OpamIndex::new("./example-repo/packages".to_string())
+            .with_flags(OpamFlags::new(["flags".to_string()]))
+            .with_variable("with-test", vec![TRUE_VERSION.clone()]);
+        let sol = pubgrub::resolve(
+            &index,
+            OpamPackage::from_str("flags").unwrap(),
+            "1.0.0".parse::<OpamVersion>().unwrap(),
+        )?;
+        assert!(sol.contains_key(&base("D")));
+        // without flags, every package's `with-test` is the one variable
+        let index = OpamIndex::new("./example-repo/packages".to_string());
+        let root = OpamPackage::Root(vec![
+            (base("flags"), Range::full()),
+            (
+                OpamPackage::Var("with-test".to_string()),
+                Range::singleton(TRUE_VERSION.clone()),
+            ),
+        ]);
+        let sol = pubgrub::resolve(&index, root, OpamVersion("".to_string()))?;
+        assert!(sol.contains_key(&base("D")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalized_formulas() -> Result<(), Box<dyn Error>> {
+        let solve = |normalize: bool| -> Result<SelectedDependencies<OpamIndex>, Box<dyn Error>> {
+            let index = OpamIndex::new("./example-repo/packages".to_string())
+                .with_flags(OpamFlags::new(["normalized".to_string()]))
+                .with_normalize(normalize);
+            Ok(pubgrub::resolve(
+                &index,
+                OpamPackage::from_str("normalized").unwrap(),
+                "1.0.0".parse::<OpamVersion>().unwrap(),
+            )?)
+        };
+        let proxies = |sol: &SelectedDependencies<OpamIndex>| {
+            sol.keys()
+                .filter(|package| {
+                    matches!(
+                        package,
+                        OpamPackage::Lor(_)
+                            | OpamPackage::Formula { .. }
+                            | OpamPackage::Proxy { .. }
+                    )
+                })
+                .count()
+        };
+        // the flags are folded away, and the three alternatives are one choice
+        let sol = solve(true)?;
+        assert_eq!(proxies(&sol), 1);
+        assert!(sol.contains_key(&OpamPackage::Base("B".to_string())));
+        assert!(!sol.contains_key(&OpamPackage::Base("C".to_string())));
+        assert_eq!(
+            sol.get(&OpamPackage::Var("build".to_string())),
+            Some(&TRUE_VERSION.clone())
+        );
+        let raw = solve(false)?;
+        assert!(proxies(&raw) > proxies(&sol));
+        assert!(raw.contains_key(&OpamPackage::Base("D".to_string())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_avoided_versions() -> Result<(), Box<dyn Error>> {
+        let index = OpamIndex::new("./example-repo/packages".to_string());
+        let versions = |package: &str| -> Vec<String> {
+            index
+                .available_versions(&package.to_string())
+                .into_iter()
+                .map(|version| version.to_string())
+                .collect()
+        };
+        assert_eq!(versions("flagged"), ["1.0.0", "3.0.0", "2.0.0"]);
+        assert_eq!(
+            index.repository.avoided("flagged", "3.0.0"),
+            Some(Avoidance::AvoidVersion)
+        );
+        assert_eq!(
+            index.repository.avoided("flagged", "2.0.0"),
+            Some(Avoidance::Deprecated)
+        );
+        // only the latest version is maintained
+        assert_eq!(versions("maintenance"), ["3.0.0", "2.0.0", "1.0.0"]);
+        assert_eq!(index.repository.avoided("maintenance", "3.0.0"), None);
+        assert_eq!(
+            index.repository.avoided("maintenance", "1.0.0"),
+            Some(Avoidance::Unmaintained)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pin_depends() -> Result<(), Box<dyn Error>> {
+        let repository = OpamRepository::new("./example-repo/packages".to_string())
+            .with_pin_depends(Path::new("./example-pins/pinned.opam"))?;
+        let index = OpamIndex::from_repository(Arc::new(repository));
+        // the pin replaces every version the repository has
+        assert_eq!(
+            index.available_versions(&"fetched".to_string()),
+            [OpamVersion("2.0.0".to_string())]
+        );
+        let root = OpamPackage::Root(vec![(
+            OpamPackage::Base("local-pin".to_string()),
+            Range::full(),
+        )]);
+        let sol = pubgrub::resolve(&index, root, OpamVersion("".to_string()))?;
+        assert_eq!(
+            sol.get(&OpamPackage::Base("local-pin".to_string())),
+            Some(&OpamVersion("dev".to_string()))
+        );
+        let manifest: serde_json::Value =
+            serde_json::from_str(&OpamSolution::new(&sol).fetch_manifest(&index.repository)?)?;
+        assert_eq!(
+            manifest,
+            json!([
+                {
+                    "name": "fetched",
+                    "version": "2.0.0",
+                    "src": "git+https://example.com/fetched.git#main",
+                    "checksum": [],
+                },
+                {
+                    "name": "local-pin",
+                    "version": "dev",
+                    "src": "./example-pins/./local-pin",
+                    "checksum": [],
+                },
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pin_depends_unreleased() -> Result<(), Box<dyn Error>> {
+        // no repository has fetched.3.0.0, so it's read from fetched.2.0.0
+        let repository = OpamRepository::new("./example-repo/packages".to_string())
+            .with_pin_depends(Path::new("./example-pins/unreleased.opam"))?;
+        let index = OpamIndex::from_repository(Arc::new(repository));
+        let root = OpamPackage::Root(vec![(
+            OpamPackage::Base("fetched".to_string()),
+            Range::full(),
+        )]);
+        let sol = pubgrub::resolve(&index, root, OpamVersion("".to_string()))?;
+        assert_eq!(
+            sol.get(&OpamPackage::Base("fetched".to_string())),
+            Some(&OpamVersion("3.0.0".to_string()))
+        );
+        assert!(sol.contains_key(&OpamPackage::Base("conf-fetched".to_string())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_manifest() -> Result<(), Box<dyn Error>> {
+        let index = OpamIndex::new("./example-repo/packages".to_string());
+        let sol = pubgrub::resolve(
+            &index,
+            OpamPackage::from_str("fetched").unwrap(),
+            "1.0.0".parse::<OpamVersion>().unwrap(),
+        )?;
+        let manifest: serde_json::Value =
+            serde_json::from_str(&OpamSolution::new(&sol).fetch_manifest(&index.repository)?)?;
+        // `conf-fetched` has no source to fetch
+        assert_eq!(
+            manifest,
+            json!([{
+                "name": "fetched",
+                "version": "1.0.0",
+                "src": "https://example.com/fetched-1.0.0.tar.gz",
+                "checksum": [
+                    "sha256=2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae",
+                    "md5=acbd18db4cc2f85cedef654fccc4a4d8",
+                ],
+            }])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_switch_state() -> Result<(), Box<dyn Error>> {
+        let switch = SwitchState::read(Path::new("./example-switch"))?;
+        let repository = switch.pin(OpamRepository::new("./example-repo/packages".to_string()));
+        let index = OpamIndex::from_repository(Arc::new(repository)).with_switch(&switch);
+        assert_eq!(
+            index.variables["os-family"],
+            [OpamVersion("debian".to_string())]
+        );
+        let solve = |requested: &[&str]| -> Result<Vec<String>, Box<dyn Error>> {
+            let requested = requested
+                .iter()
+                .map(|name| (OpamPackage::Base(name.to_string()), Range::full()));
+            let root =
+                OpamPackage::Root(switch.requirements().into_iter().chain(requested).collect());
+            let sol = pubgrub::resolve(&index, root, OpamVersion("".to_string()))?;
+            Ok(switch
+                .actions(&OpamSolution::new(&sol))
+                .iter()
+                .map(|action| action.to_string())
+                .collect())
+        };
+        // installed versions are kept, and the pinned package is read from the switch
+        assert_eq!(
+            solve(&[])?,
+            ["remove switch-dep 2.0.0", "remove switch-old 1.0.0"]
+        );
+        assert_eq!(
+            solve(&["switch-new"])?,
+            [
+                "downgrade switch-dep 2.0.0 to 1.0.0",
+                "upgrade switch-lib 1.0.0 to 2.0.0",
+                "install switch-new 1.0.0",
+                "remove switch-old 1.0.0",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata() -> Result<(), Box<dyn Error>> {
+        let repository = OpamRepository::new("./example-repo/packages".to_string());
+        let info = repository.package_info("described", "1.0.0")?;
+        assert_eq!(info.licenses, ["MIT", "ISC"]);
+        assert_eq!(
+            info.dev_repo.as_deref(),
+            Some("git+https://example.com/described.git")
+        );
+        assert_eq!(info.depends[1].packages, ["switch-dep"]);
+        assert_eq!(info.depends[1].variables, ["with-test"]);
+
+        let index = repository.search_index();
+        let found: Vec<&str> = index
+            .search("metadata")
+            .iter()
+            .map(|package| package.name.as_str())
+            .collect();
+        assert_eq!(found, ["described"]);
+        assert_eq!(
+            index.search("descr")[0].version,
+            OpamVersion("1.0.0".to_string())
+        );
+        // test dependencies count too
+        let reverse: Vec<String> = index
+            .reverse_dependencies("switch-dep")
+            .into_iter()
+            .map(|(package, _)| package)
+            .collect();
+        assert_eq!(reverse, ["described", "switch-new"]);
+        let reverse = index.reverse_dependencies("switch-lib");
+        assert!(reverse.contains(&(
+            "switch-app".to_string(),
+            vec![OpamVersion("1.0.0".to_string())]
+        )));
+        assert!(index.reverse_dependencies("switch-new").is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflict_class() -> Result<(), Box<dyn Error>> {
+        // members of the class the solve never reaches are still known
+        solve_repo(
+            OpamPackage::from_str("conflict-class-a").unwrap(),
+            "1.0.0".parse::<OpamVersion>().unwrap(),
+            "./example-repo/packages",
+        )?;
+        assert!(solve_repo(
+            OpamPackage::from_str("conflict-class").unwrap(),
+            "1.0.0".parse::<OpamVersion>().unwrap(),
+            "./example-repo/packages",
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflicts() -> Result<(), Box<dyn Error>> {
+        let sol = solve_repo(
+            OpamPackage::from_str("conflicts").unwrap(),
+            "1.0.0".parse::<OpamVersion>().unwrap(),
+            "./example-repo/packages",
+        )?;
+        assert_eq!(
+            sol.get(&OpamPackage::Base("D".to_string())),
+            Some("2.0.0".parse::<OpamVersion>().as_ref().unwrap())
+        );
+        assert_eq!(
+            sol.get(&OpamPackage::Installed("not-in-repository".to_string())),
+            Some(&*ABSENT_VERSION)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflicts_error() -> Result<(), Box<dyn Error>> {
+        let index = OpamIndex::new("./example-repo/packages".to_string());
+        let result = pubgrub::resolve(
+            &index,
+            OpamPackage::from_str("conflicts-error").unwrap(),
+            "1.0.0".parse::<OpamVersion>().unwrap(),
+        );
+        let Err(PubGrubError::NoSolution(derivation_tree)) = result else {
+            return Err("expected no solution".into());
+        };
+        let report = DefaultStringReporter::report(&derivation_tree);
+        assert!(
+            report.contains("conflicts-error conflicts with C"),
+            "{}",
+            report
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflicts_conjunction() -> Result<(), Box<dyn Error>> {
+        // the conjunction is left out, and the rest of the entry kept
+        let sol = solve_repo(
+            OpamPackage::from_str("conflicts-and").unwrap(),
+            "1.0.0".parse::<OpamVersion>().unwrap(),
+            "./example-repo/packages",
+        )?;
+        assert!(sol.contains_key(&OpamPackage::Base("C".to_string())));
+        assert!(sol.contains_key(&OpamPackage::Base("D".to_string())));
+        let index = OpamIndex::new("./example-repo/packages".to_string());
+        let root = OpamPackage::Root(vec![
+            (
+                OpamPackage::Base("conflicts-and".to_string()),
+                Range::full(),
+            ),
+            (OpamPackage::Base("A".to_string()), Range::full()),
+        ]);
+        assert!(pubgrub::resolve(&index, root, OpamVersion("".to_string())).is_err());
+        Ok(())
+    }
+
+    fn solve_conflicts_filtered(os: &str) -> Result<OpamVersion, Box<dyn Error>> {
+        let root = OpamPackage::Root(vec![
+            (
+                OpamPackage::Base("conflicts-filtered".to_string()),
+                Range::singleton(OpamVersion("1.0.0".to_string())),
+            ),
+            (
+                OpamPackage::Var("os".to_string()),
+                Range::singleton(OpamVersion(os.to_string())),
+            ),
+        ]);
+        let sol = solve_repo(root, OpamVersion("".to_string()), "./example-repo/packages")?;
+        Ok(sol[&OpamPackage::Base("D".to_string())].clone())
+    }
+
+    #[test]
+    fn test_conflicts_filtered() -> Result<(), Box<dyn Error>> {
+        assert_eq!(
+            solve_conflicts_filtered("linux")?,
+            "2.0.0".parse::<OpamVersion>()?
+        );
+        assert_eq!(
+            solve_conflicts_filtered("macos")?,
+            "3.0.0".parse::<OpamVersion>()?
+        );
+        Ok(())
+    }
+
+    fn solve_available(
+        range: Range<OpamVersion>,
+        os: &str,
+    ) -> Result<SelectedDependencies<OpamIndex>, PubGrubError<OpamIndex>> {
+        let root = OpamPackage::Root(vec![
+            (OpamPackage::Base("available".to_string()), range),
+            (
+                OpamPackage::Var("os".to_string()),
+                Range::singleton(OpamVersion(os.to_string())),
+            ),
+        ]);
+        let index = OpamIndex::new("./example-repo/packages".to_string());
+        pubgrub::resolve(&index, root, OpamVersion("".to_string()))
+    }
+
+    #[test]
+    fn test_available() -> Result<(), Box<dyn Error>> {
+        let available = OpamPackage::Base("available".to_string());
+        let sol = solve_available(Range::full(), "linux")?;
+        assert_eq!(sol[&available], "1.0.0".parse::<OpamVersion>()?);
+        let sol = solve_available(Range::full(), "macos")?;
+        assert_eq!(sol[&available], "2.0.0".parse::<OpamVersion>()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_available_error() -> Result<(), Box<dyn Error>> {
+        let range = Range::singleton(OpamVersion("2.0.0".to_string()));
+        let Err(PubGrubError::NoSolution(derivation_tree)) = solve_available(range, "linux") else {
+            return Err("expected no solution".into());
+        };
+        let report = DefaultStringReporter::report(&derivation_tree);
+        assert!(
+            report.contains("not available on this platform"),
+            "{}",
+            report
+        );
+        Ok(())
+    }
+
+    fn solve_depopts(
+        with_depopts: bool,
+        dependencies: Vec<(OpamPackage, Range<OpamVersion>)>,
+    ) -> Result<SelectedDependencies<OpamIndex>, PubGrubError<OpamIndex>> {
+        let mut root = vec![(
+            OpamPackage::Base("depopts".to_string()),
+            Range::singleton(OpamVersion("1.0.0".to_string())),
+        )];
+        root.extend(dependencies);
+        let index =
+            OpamIndex::new("./example-repo/packages".to_string()).with_depopts(with_depopts);
+        pubgrub::resolve(&index, OpamPackage::Root(root), OpamVersion("".to_string()))
+    }
+
+    #[test]
+    fn test_depopts() -> Result<(), Box<dyn Error>> {
+        let d = OpamPackage::Base("D".to_string());
+        // an optional dependency that nothing else needs isn't installed
+        let sol = solve_depopts(false, vec![])?;
+        assert_eq!(sol.get(&d), None);
+        // but its constraints hold once something else needs it
+        let sol = solve_depopts(false, vec![(d.clone(), Range::full())])?;
+        assert_eq!(sol[&d], "2.0.0".parse::<OpamVersion>()?);
+        let sol = solve_depopts(true, vec![])?;
+        assert_eq!(sol[&d], "2.0.0".parse::<OpamVersion>()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_depopts_error() -> Result<(), Box<dyn Error>> {
+        let d = (
+            OpamPackage::Base("D".to_string()),
+            Range::singleton(OpamVersion("3.0.0".to_string())),
+        );
+        assert!(solve_depopts(false, vec![d]).is_err());
+        Ok(())
+    }
+
+    fn solve_package_variables(
+        version: &str,
+    ) -> Result<SelectedDependencies<OpamIndex>, Box<dyn Error>> {
+        solve_repo(
+            OpamPackage::from_str("package-variables").unwrap(),
+            version.parse::<OpamVersion>().unwrap(),
+            "./example-repo/packages",
+        )
+    }
+
+    #[test]
+    fn test_package_variables() -> Result<(), Box<dyn Error>> {
+        let base = |name: &str| OpamPackage::Base(name.to_string());
+        // `D:version` is the version of D that's selected
+        let sol = solve_package_variables("1.0.0")?;
+        assert!(sol.contains_key(&base("C")));
+        assert!(!sol.contains_key(&OpamPackage::Var("D:version".to_string())));
+        let sol = solve_package_variables("2.0.0")?;
+        assert!(!sol.contains_key(&base("C")));
+        // `D:installed` holds when D is in the solution
+        let sol = solve_package_variables("3.0.0")?;
+        assert!(sol.contains_key(&base("B")));
+        assert!(!sol.contains_key(&base("A")));
+        // `_:version` is the version of the package itself
+        let sol = solve_package_variables("4.0.0")?;
+        assert!(sol.contains_key(&base("B")));
+        assert!(!sol.contains_key(&base("C")));
+        // comparing the version of a package that isn't installed doesn't
+        // hold, so neither the filtered dependency nor the conflict applies
+        let sol = solve_package_variables("5.0.0")?;
+        assert!(sol.contains_key(&base("B")));
+        assert!(!sol.contains_key(&base("C")));
+        Ok(())
+    }
+
     #[test]
     fn test_opam_repository_dune_simple() -> Result<(), Box<dyn Error>> {
         solve_repo(
@@ -0,0 +1,341 @@
+//! What opam packages are, rather than what they need: the descriptive
+//! fields of each version, a search index over a repository's packages, and
+//! which packages depend on which.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+
+use crate::index::{OpamRepository, PackageFormula, PackageName, VersionFormula};
+use crate::parse::OpamJson;
+use crate::version::OpamVersion;
+
+/// The metadata of a package version, as `opam show` gives it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackageInfo {
+    pub name: PackageName,
+    pub version: OpamVersion,
+    pub synopsis: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub maintainers: Vec<String>,
+    pub licenses: Vec<String>,
+    pub homepages: Vec<String>,
+    pub dev_repo: Option<String>,
+    pub flags: Vec<String>,
+    pub depends: Vec<Dependency>,
+    pub depopts: Vec<Dependency>,
+}
+
+/// One entry of a `depends:` or `depopts:` list.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Dependency {
+    /// Every package the entry names, more than one for alternatives.
+    pub packages: Vec<PackageName>,
+    /// The entry with its version constraints and filters.
+    pub formula: String,
+    /// The variables it's filtered on, like `build` or `with-test`.
+    pub variables: Vec<String>,
+}
+
+/// A package found by `SearchIndex::search`, described by its latest version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SearchResult {
+    pub name: PackageName,
+    pub version: OpamVersion,
+    pub synopsis: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// The names, synopses and tags of every package in a repository, and the
+/// packages each one is depended on by.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    packages: Vec<SearchResult>,
+    /// For each package, the versions of each package that depend on it.
+    reverse: HashMap<PackageName, BTreeMap<PackageName, BTreeSet<OpamVersion>>>,
+}
+
+impl PackageInfo {
+    pub fn new(
+        name: &str,
+        version: &OpamVersion,
+        metadata: &OpamJson,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(PackageInfo {
+            name: name.to_string(),
+            version: version.clone(),
+            synopsis: metadata.synopsis.clone(),
+            description: metadata.description.clone(),
+            tags: metadata.tags(),
+            maintainers: metadata.maintainers(),
+            licenses: metadata.licenses(),
+            homepages: metadata.homepages(),
+            dev_repo: metadata.dev_repo.clone(),
+            flags: metadata.flags(),
+            depends: metadata.depends()?.iter().map(Dependency::new).collect(),
+            depopts: metadata.depopts()?.iter().map(Dependency::new).collect(),
+        })
+    }
+}
+
+impl Dependency {
+    pub fn new(formula: &PackageFormula) -> Self {
+        let mut packages = BTreeSet::new();
+        let mut variables = BTreeSet::new();
+        add_names(formula, &mut packages, &mut variables);
+        Dependency {
+            packages: packages.into_iter().collect(),
+            formula: formula.to_string(),
+            variables: variables.into_iter().collect(),
+        }
+    }
+}
+
+// the packages a formula depends on, and the variables its constraints use
+fn add_names(
+    formula: &PackageFormula,
+    packages: &mut BTreeSet<PackageName>,
+    variables: &mut BTreeSet<String>,
+) {
+    match formula {
+        PackageFormula::Or(binary) | PackageFormula::And(binary) => {
+            add_names(&binary.lhs, packages, variables);
+            add_names(&binary.rhs, packages, variables);
+        }
+        PackageFormula::Base { name, formula } | PackageFormula::Depopt { name, formula, .. } => {
+            packages.insert(name.clone());
+            add_variables(formula, variables);
+        }
+        _ => {}
+    }
+}
+
+fn add_variables(formula: &VersionFormula, variables: &mut BTreeSet<String>) {
+    match formula {
+        VersionFormula::Variable(var) | VersionFormula::Not(var) => {
+            variables.insert(var.clone());
+        }
+        VersionFormula::And(binary)
+        | VersionFormula::Or(binary)
+        | VersionFormula::Comparator { binary, .. } => {
+            add_variables(&binary.lhs, variables);
+            add_variables(&binary.rhs, variables);
+        }
+        VersionFormula::Version(_) | VersionFormula::Lit(_) => {}
+    }
+}
+
+impl SearchIndex {
+    /// Read every version of every package. Versions that fail to parse are
+    /// skipped, as they are by `Domains::scan`.
+    pub fn build(repository: &OpamRepository) -> Self {
+        repository.preload();
+        let mut index = SearchIndex::default();
+        for package in repository.packages() {
+            let versions = repository.available_versions(&package);
+            for version in &versions {
+                let formulas = match repository.dependencies(&package, &version.to_string()) {
+                    Ok(formulas) => formulas,
+                    Err(e) => {
+                        tracing::warn!(
+                            package = %package,
+                            version = %version,
+                            error = %e,
+                            "skipping unreadable package"
+                        );
+                        continue;
+                    }
+                };
+                let mut names = BTreeSet::new();
+                for formula in &formulas {
+                    add_names(formula, &mut names, &mut BTreeSet::new());
+                }
+                for name in names {
+                    index
+                        .reverse
+                        .entry(name)
+                        .or_default()
+                        .entry(package.clone())
+                        .or_default()
+                        .insert(version.clone());
+                }
+            }
+            // the latest version that can be read describes the package
+            let latest = versions.iter().find_map(|version| {
+                let metadata = repository
+                    .read_metadata(&package, &version.to_string())
+                    .ok()?;
+                PackageInfo::new(&package, version, &metadata).ok()
+            });
+            if let Some(info) = latest {
+                index.packages.push(SearchResult {
+                    name: info.name,
+                    version: info.version,
+                    synopsis: info.synopsis,
+                    tags: info.tags,
+                });
+            }
+        }
+        index
+    }
+
+    /// The packages matching every word of a query, best first: those whose
+    /// name is a word, then whose name starts with or contains it, then
+    /// those tagged with it, then those whose synopsis mentions it. Matching
+    /// ignores case, and a query without words matches nothing.
+    pub fn search(&self, query: &str) -> Vec<&SearchResult> {
+        let words: Vec<String> = query
+            .split_whitespace()
+            .map(|word| word.to_lowercase())
+            .collect();
+        if words.is_empty() {
+            return vec![];
+        }
+        let mut results: Vec<(usize, &SearchResult)> = self
+            .packages
+            .iter()
+            .filter_map(|package| {
+                words
+                    .iter()
+                    .map(|word| rank(package, word))
+                    .sum::<Option<usize>>()
+                    .map(|rank| (rank, package))
+            })
+            .collect();
+        results.sort_by(|(rank1, package1), (rank2, package2)| {
+            rank1
+                .cmp(rank2)
+                .then_with(|| package1.name.cmp(&package2.name))
+        });
+        results.into_iter().map(|(_, package)| package).collect()
+    }
+
+    /// The packages with a version that depends on a package, even
+    /// optionally or only under a filter, with each such version, latest
+    /// first.
+    pub fn reverse_dependencies(&self, package: &str) -> Vec<(PackageName, Vec<OpamVersion>)> {
+        self.reverse
+            .get(package)
+            .into_iter()
+            .flatten()
+            .map(|(name, versions)| (name.clone(), versions.iter().rev().cloned().collect()))
+            .collect()
+    }
+}
+
+// how well a package matches a lowercase word, lower being better
+fn rank(package: &SearchResult, word: &str) -> Option<usize> {
+    let name = package.name.to_lowercase();
+    if name == *word {
+        Some(0)
+    } else if name.starts_with(word) {
+        Some(1)
+    } else if name.contains(word) {
+        Some(2)
+    } else if package.tags.iter().any(|tag| tag.to_lowercase() == *word) {
+        Some(3)
+    } else if package
+        .tags
+        .iter()
+        .any(|tag| tag.to_lowercase().contains(word))
+    {
+        Some(4)
+    } else if package
+        .synopsis
+        .as_ref()
+        .is_some_and(|synopsis| synopsis.to_lowercase().contains(word))
+    {
+        Some(5)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opam_file::parse_opam_file;
+
+    fn package(name: &str, synopsis: &str, tags: &[&str]) -> SearchResult {
+        SearchResult {
+            name: name.to_string(),
+            version: OpamVersion("1.0".to_string()),
+            synopsis: Some(synopsis.to_string()),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_package_info() -> Result<(), Box<dyn std::error::Error>> {
+        let metadata = parse_opam_file(
+            r#"
+            opam-version: "2.0"
+            synopsis: "A library"
+            description: """
+            It does things."""
+            maintainer: "someone@example.com"
+            license: ["MIT" "ISC"]
+            tags: ["org:example" "parsing"]
+            homepage: "https://example.com"
+            dev-repo: "git+https://example.com/lib.git"
+            depends: [
+              "ocaml" {>= "4.08"}
+              "dune" {build}
+              "alcotest" {with-test} | "ounit"
+            ]
+            depopts: ["lwt"]
+            "#,
+        )?;
+        let info = PackageInfo::new("lib", &OpamVersion("1.0".to_string()), &metadata)?;
+        assert_eq!(info.synopsis.as_deref(), Some("A library"));
+        assert!(info
+            .description
+            .is_some_and(|d| d.contains("It does things.")));
+        assert_eq!(info.maintainers, ["someone@example.com"]);
+        assert_eq!(info.licenses, ["MIT", "ISC"]);
+        assert_eq!(info.tags, ["org:example", "parsing"]);
+        assert_eq!(info.homepages, ["https://example.com"]);
+        assert_eq!(
+            info.dev_repo.as_deref(),
+            Some("git+https://example.com/lib.git")
+        );
+        assert_eq!(info.depends.len(), 3);
+        assert_eq!(info.depends[1].packages, ["dune"]);
+        assert_eq!(info.depends[1].variables, ["build"]);
+        assert_eq!(info.depends[2].packages, ["alcotest", "ounit"]);
+        assert_eq!(info.depends[2].variables, ["with-test"]);
+        assert_eq!(info.depopts[0].packages, ["lwt"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search() {
+        let index = SearchIndex {
+            packages: vec![
+                package("yaml", "Parse and print YAML", &["org:example"]),
+                package("ezjsonm", "Simple JSON", &["json"]),
+                package("json-data", "JSON values", &[]),
+                package("yojson", "JSON parsing and printing", &["parsing"]),
+            ],
+            ..SearchIndex::default()
+        };
+        let names = |query: &str| -> Vec<&str> {
+            index
+                .search(query)
+                .iter()
+                .map(|package| package.name.as_str())
+                .collect()
+        };
+        assert_eq!(
+            names("JSON"),
+            ["json-data", "ezjsonm", "yojson"],
+            "names starting with the word, then names containing it"
+        );
+        assert_eq!(names("json parsing"), ["yojson"]);
+        assert_eq!(names("example"), ["yaml"]);
+        assert!(names("toml").is_empty());
+        assert!(names("").is_empty());
+        assert!(names(" \t ").is_empty());
+    }
+}
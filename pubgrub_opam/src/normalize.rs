@@ -0,0 +1,482 @@
+//! Simplifying dependency formulas before they're encoded for the solver.
+//!
+//! Each filter or disjunction left in a formula becomes a package the solver
+//! has to decide on. So variables whose value is fixed for the solve are
+//! folded into constants, nested `&` and `|` are flattened so that a
+//! disjunction is one choice between all its alternatives, and the
+//! constraints a conjunction or disjunction puts on one package are merged
+//! into a single range.
+
+use crate::index::{Binary, HashedRange, PackageFormula, VersionFormula};
+use crate::parse::{
+    as_bool, bool_formula, flip_relop, formula_bool, normalize_negation, relop_to_range, RelOp,
+};
+use crate::version::OpamVersion;
+use pubgrub::Range;
+use std::collections::BTreeMap;
+
+type Merge = fn(&Range<OpamVersion>, &Range<OpamVersion>) -> Range<OpamVersion>;
+
+pub struct Normalizer<F> {
+    known: F,
+    /// The variables that were folded into constants and the values they
+    /// had, which a solution still has to select.
+    pub folded: BTreeMap<String, OpamVersion>,
+}
+
+impl<F: Fn(&str) -> Option<OpamVersion>> Normalizer<F> {
+    /// `known` gives the value of a variable, if it has only one.
+    pub fn new(known: F) -> Self {
+        Self {
+            known,
+            folded: BTreeMap::new(),
+        }
+    }
+
+    fn value(&mut self, var: &str) -> Option<OpamVersion> {
+        let value = (self.known)(var)?;
+        self.folded.insert(var.to_string(), value.clone());
+        Some(value)
+    }
+
+    /// The formula with as few choices left as possible, or `None` if it
+    /// requires nothing. As in opam, an alternative whose filter is false
+    /// drops out of a disjunction.
+    pub fn package_formula(&mut self, formula: &PackageFormula) -> Option<PackageFormula> {
+        match formula {
+            PackageFormula::Base { name, formula } => {
+                let formula = match self.filter(formula) {
+                    VersionFormula::Lit(lit) if as_bool(&lit) == Some(false) => return None,
+                    VersionFormula::Lit(lit) if as_bool(&lit) == Some(true) => {
+                        VersionFormula::Version(HashedRange(Range::full()))
+                    }
+                    formula => formula,
+                };
+                Some(PackageFormula::Base {
+                    name: name.clone(),
+                    formula,
+                })
+            }
+            PackageFormula::And(_) => {
+                let mut conjuncts = vec![];
+                for conjunct in package_conjuncts(formula) {
+                    if let Some(conjunct) = self.package_formula(conjunct) {
+                        for conjunct in package_conjuncts(&conjunct) {
+                            add_package(&mut conjuncts, conjunct.clone(), |l, r| l.intersection(r));
+                        }
+                    }
+                }
+                join(conjuncts, PackageFormula::And)
+            }
+            PackageFormula::Or(_) => {
+                let mut disjuncts = vec![];
+                for disjunct in package_disjuncts(formula) {
+                    if let Some(disjunct) = self.package_formula(disjunct) {
+                        for disjunct in package_disjuncts(&disjunct) {
+                            add_package(&mut disjuncts, disjunct.clone(), |l, r| l.union(r));
+                        }
+                    }
+                }
+                join(disjuncts, PackageFormula::Or)
+            }
+            // a conflict or optional dependency whose filter is false never applies
+            PackageFormula::Conflict {
+                name,
+                formula,
+                package,
+            } => match self.filter(formula) {
+                VersionFormula::Lit(lit) if as_bool(&lit) == Some(false) => None,
+                formula => Some(PackageFormula::Conflict {
+                    name: name.clone(),
+                    formula,
+                    package: package.clone(),
+                }),
+            },
+            PackageFormula::Depopt {
+                name,
+                formula,
+                package,
+            } => match self.filter(formula) {
+                VersionFormula::Lit(lit) if as_bool(&lit) == Some(false) => None,
+                formula => Some(PackageFormula::Depopt {
+                    name: name.clone(),
+                    formula,
+                    package: package.clone(),
+                }),
+            },
+            PackageFormula::Available { package, formula } => Some(PackageFormula::Available {
+                package: package.clone(),
+                formula: self.filter(formula),
+            }),
+            PackageFormula::Depext { .. } | PackageFormula::ConflictClass { .. } => {
+                Some(formula.clone())
+            }
+        }
+    }
+
+    /// The filter or version constraint with known variables folded, `&`
+    /// and `|` flattened, and version ranges merged.
+    pub fn filter(&mut self, formula: &VersionFormula) -> VersionFormula {
+        match formula {
+            VersionFormula::Variable(var) => match self.value(var).as_ref().and_then(as_bool) {
+                Some(value) => bool_formula(value),
+                None => formula.clone(),
+            },
+            VersionFormula::Not(var) => match self.value(var).as_ref().and_then(as_bool) {
+                Some(value) => bool_formula(!value),
+                None => formula.clone(),
+            },
+            VersionFormula::Comparator { relop, binary } => {
+                self.comparator(relop, &binary.lhs, &binary.rhs)
+            }
+            VersionFormula::And(_) => {
+                let mut conjuncts = vec![];
+                for conjunct in conjuncts_of(formula) {
+                    let conjunct = self.filter(conjunct);
+                    match formula_bool(&conjunct) {
+                        Some(true) => continue,
+                        Some(false) => return bool_formula(false),
+                        None => (),
+                    }
+                    for conjunct in conjuncts_of(&conjunct) {
+                        add_version(&mut conjuncts, conjunct.clone(), |l, r| l.intersection(r));
+                    }
+                }
+                join(conjuncts, VersionFormula::And).unwrap_or_else(|| bool_formula(true))
+            }
+            VersionFormula::Or(_) => {
+                let mut disjuncts = vec![];
+                for disjunct in disjuncts_of(formula) {
+                    let disjunct = self.filter(disjunct);
+                    match formula_bool(&disjunct) {
+                        Some(false) => continue,
+                        Some(true) => return bool_formula(true),
+                        None => (),
+                    }
+                    for disjunct in disjuncts_of(&disjunct) {
+                        add_version(&mut disjuncts, disjunct.clone(), |l, r| l.union(r));
+                    }
+                }
+                join(disjuncts, VersionFormula::Or).unwrap_or_else(|| bool_formula(false))
+            }
+            VersionFormula::Version(_) | VersionFormula::Lit(_) => formula.clone(),
+        }
+    }
+
+    fn comparator(
+        &mut self,
+        relop: &RelOp,
+        lhs: &VersionFormula,
+        rhs: &VersionFormula,
+    ) -> VersionFormula {
+        match (lhs, rhs) {
+            // keep the variable on the left
+            (VersionFormula::Lit(_), VersionFormula::Variable(_)) => {
+                self.comparator(&flip_relop(relop), rhs, lhs)
+            }
+            (VersionFormula::Variable(var), VersionFormula::Lit(lit)) => match self.value(var) {
+                Some(value) => bool_formula(relop_to_range(relop, lit.clone()).contains(&value)),
+                None => comparison(relop, lhs, rhs),
+            },
+            (VersionFormula::Lit(l), VersionFormula::Lit(r)) => {
+                bool_formula(relop_to_range(relop, r.clone()).contains(l))
+            }
+            // `?var`, which parses as the variable not being undefined
+            (VersionFormula::Variable(_), VersionFormula::Version(_)) => {
+                comparison(relop, lhs, rhs)
+            }
+            // otherwise this compares booleans
+            _ => {
+                let lhs = self.filter(lhs);
+                let rhs = self.filter(rhs);
+                let equal = match relop {
+                    RelOp::Eq => true,
+                    RelOp::Neq => false,
+                    _ => return comparison(relop, &lhs, &rhs),
+                };
+                match (formula_bool(&lhs), formula_bool(&rhs)) {
+                    (Some(l), Some(r)) => bool_formula((l == r) == equal),
+                    // `x = true` is `x`, and `x = false` is `!x`
+                    (None, Some(value)) if value == equal => lhs,
+                    (None, Some(_)) => self.filter(&normalize_negation(lhs)),
+                    (Some(value), None) if value == equal => rhs,
+                    (Some(_), None) => self.filter(&normalize_negation(rhs)),
+                    (None, None) => comparison(relop, &lhs, &rhs),
+                }
+            }
+        }
+    }
+}
+
+/// The filter part of a dependency's formula, taking its version constraints
+/// to hold: `with-test` for `{>= "1.0" & with-test}`.
+pub fn filter_part(formula: &VersionFormula) -> VersionFormula {
+    Normalizer::new(|_: &str| None).filter(&without_versions(formula))
+}
+
+fn without_versions(formula: &VersionFormula) -> VersionFormula {
+    match formula {
+        VersionFormula::Version(_) => bool_formula(true),
+        VersionFormula::And(Binary { lhs, rhs }) => VersionFormula::And(Binary {
+            lhs: Box::new(without_versions(lhs)),
+            rhs: Box::new(without_versions(rhs)),
+        }),
+        VersionFormula::Or(Binary { lhs, rhs }) => VersionFormula::Or(Binary {
+            lhs: Box::new(without_versions(lhs)),
+            rhs: Box::new(without_versions(rhs)),
+        }),
+        formula => formula.clone(),
+    }
+}
+
+fn comparison(relop: &RelOp, lhs: &VersionFormula, rhs: &VersionFormula) -> VersionFormula {
+    VersionFormula::Comparator {
+        relop: relop.clone(),
+        binary: Binary {
+            lhs: Box::new(lhs.clone()),
+            rhs: Box::new(rhs.clone()),
+        },
+    }
+}
+
+/// The conjuncts of a formula, with nested `&` flattened.
+pub fn package_conjuncts(formula: &PackageFormula) -> Vec<&PackageFormula> {
+    match formula {
+        PackageFormula::And(Binary { lhs, rhs }) => {
+            let mut conjuncts = package_conjuncts(lhs);
+            conjuncts.extend(package_conjuncts(rhs));
+            conjuncts
+        }
+        formula => vec![formula],
+    }
+}
+
+/// The alternatives of a formula, with nested `|` flattened.
+pub fn package_disjuncts(formula: &PackageFormula) -> Vec<&PackageFormula> {
+    match formula {
+        PackageFormula::Or(Binary { lhs, rhs }) => {
+            let mut disjuncts = package_disjuncts(lhs);
+            disjuncts.extend(package_disjuncts(rhs));
+            disjuncts
+        }
+        formula => vec![formula],
+    }
+}
+
+/// The conjuncts of a filter, with nested `&` flattened.
+pub fn conjuncts_of(formula: &VersionFormula) -> Vec<&VersionFormula> {
+    match formula {
+        VersionFormula::And(Binary { lhs, rhs }) => {
+            let mut conjuncts = conjuncts_of(lhs);
+            conjuncts.extend(conjuncts_of(rhs));
+            conjuncts
+        }
+        formula => vec![formula],
+    }
+}
+
+/// The alternatives of a filter, with nested `|` flattened.
+pub fn disjuncts_of(formula: &VersionFormula) -> Vec<&VersionFormula> {
+    match formula {
+        VersionFormula::Or(Binary { lhs, rhs }) => {
+            let mut disjuncts = disjuncts_of(lhs);
+            disjuncts.extend(disjuncts_of(rhs));
+            disjuncts
+        }
+        formula => vec![formula],
+    }
+}
+
+// merge a plain version constraint into one on the same package, and drop duplicates
+fn add_package(formulas: &mut Vec<PackageFormula>, formula: PackageFormula, merge: Merge) {
+    if let PackageFormula::Base {
+        name,
+        formula: VersionFormula::Version(range),
+    } = &formula
+    {
+        for existing in formulas.iter_mut() {
+            if let PackageFormula::Base {
+                name: existing_name,
+                formula: VersionFormula::Version(existing),
+            } = existing
+            {
+                if existing_name == name {
+                    *existing = HashedRange(merge(&existing.0, &range.0));
+                    return;
+                }
+            }
+        }
+    }
+    if !formulas.contains(&formula) {
+        formulas.push(formula);
+    }
+}
+
+fn add_version(formulas: &mut Vec<VersionFormula>, formula: VersionFormula, merge: Merge) {
+    if let VersionFormula::Version(range) = &formula {
+        for existing in formulas.iter_mut() {
+            if let VersionFormula::Version(existing) = existing {
+                *existing = HashedRange(merge(&existing.0, &range.0));
+                return;
+            }
+        }
+    }
+    if !formulas.contains(&formula) {
+        formulas.push(formula);
+    }
+}
+
+// rebuild a flattened formula, nested to the right like the parser's
+fn join<T>(mut formulas: Vec<T>, operator: fn(Binary<T>) -> T) -> Option<T> {
+    let last = formulas.pop()?;
+    Some(formulas.into_iter().rev().fold(last, |rhs, lhs| {
+        operator(Binary {
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        })
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deps::{FALSE_VERSION, TRUE_VERSION};
+
+    fn base(name: &str, formula: VersionFormula) -> PackageFormula {
+        PackageFormula::Base {
+            name: name.to_string(),
+            formula,
+        }
+    }
+
+    fn version(relop: RelOp, version: &str) -> VersionFormula {
+        VersionFormula::Version(HashedRange(relop_to_range(
+            &relop,
+            OpamVersion(version.to_string()),
+        )))
+    }
+
+    fn binary<T>(lhs: T, rhs: T, operator: fn(Binary<T>) -> T) -> T {
+        operator(Binary {
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        })
+    }
+
+    #[test]
+    fn test_fold_known_variables() {
+        let mut normalizer = Normalizer::new(|var: &str| match var {
+            "build" => Some(TRUE_VERSION.clone()),
+            "with-test" => Some(FALSE_VERSION.clone()),
+            "jobs" => Some(OpamVersion("8".to_string())),
+            _ => None,
+        });
+        let filtered = |filter: VersionFormula| {
+            base(
+                "a",
+                binary(version(RelOp::Geq, "1"), filter, VersionFormula::And),
+            )
+        };
+        assert_eq!(
+            normalizer.package_formula(&filtered(VersionFormula::Variable("build".to_string()))),
+            Some(base("a", version(RelOp::Geq, "1")))
+        );
+        assert_eq!(
+            normalizer
+                .package_formula(&filtered(VersionFormula::Variable("with-test".to_string()))),
+            None
+        );
+        // `"4" <= jobs` compares the variable's value
+        let jobs = comparison(
+            &RelOp::Leq,
+            &VersionFormula::Lit(OpamVersion("4".to_string())),
+            &VersionFormula::Variable("jobs".to_string()),
+        );
+        assert_eq!(normalizer.filter(&jobs), bool_formula(true));
+        // an unknown variable is left for the solver
+        let doc = VersionFormula::Variable("with-doc".to_string());
+        assert_eq!(normalizer.filter(&doc), doc);
+        assert_eq!(
+            normalizer.folded.keys().collect::<Vec<_>>(),
+            vec!["build", "jobs", "with-test"]
+        );
+    }
+
+    #[test]
+    fn test_flatten_alternatives() {
+        let mut normalizer = Normalizer::new(|var: &str| match var {
+            "with-test" => Some(FALSE_VERSION.clone()),
+            _ => None,
+        });
+        // a | (b {with-test} | (a | c))
+        let formula = binary(
+            base("a", version(RelOp::Eq, "1")),
+            binary(
+                base("b", VersionFormula::Variable("with-test".to_string())),
+                binary(
+                    base("a", version(RelOp::Eq, "2")),
+                    base("c", version(RelOp::Geq, "1")),
+                    PackageFormula::Or,
+                ),
+                PackageFormula::Or,
+            ),
+            PackageFormula::Or,
+        );
+        let alternatives = normalizer.package_formula(&formula).unwrap();
+        assert_eq!(
+            package_disjuncts(&alternatives),
+            vec![
+                &base(
+                    "a",
+                    VersionFormula::Version(HashedRange(
+                        relop_to_range(&RelOp::Eq, OpamVersion("1".to_string()))
+                            .union(&relop_to_range(&RelOp::Eq, OpamVersion("2".to_string())))
+                    ))
+                ),
+                &base("c", version(RelOp::Geq, "1")),
+            ]
+        );
+        // the ranges a conjunction puts on a package intersect
+        let formula = binary(
+            base("a", version(RelOp::Geq, "1")),
+            base("a", version(RelOp::Lt, "2")),
+            PackageFormula::And,
+        );
+        assert_eq!(
+            normalizer.package_formula(&formula),
+            Some(base(
+                "a",
+                VersionFormula::Version(HashedRange(Range::between(
+                    OpamVersion("1".to_string()),
+                    OpamVersion("2".to_string())
+                )))
+            ))
+        );
+    }
+
+    #[test]
+    fn test_boolean_comparisons() {
+        let mut normalizer = Normalizer::new(|var: &str| match var {
+            "build" => Some(TRUE_VERSION.clone()),
+            _ => None,
+        });
+        let test = VersionFormula::Variable("with-test".to_string());
+        let build = VersionFormula::Variable("build".to_string());
+        assert_eq!(
+            normalizer.filter(&comparison(&RelOp::Eq, &test, &build)),
+            test
+        );
+        assert_eq!(
+            normalizer.filter(&comparison(&RelOp::Neq, &test, &build)),
+            VersionFormula::Not("with-test".to_string())
+        );
+        assert_eq!(
+            filter_part(&binary(
+                version(RelOp::Geq, "1"),
+                test.clone(),
+                VersionFormula::And
+            )),
+            test
+        );
+    }
+}
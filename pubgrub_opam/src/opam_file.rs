@@ -0,0 +1,766 @@
+//! A parser for the opam file format. It reads `opam` files directly and
+//! converts them to the same JSON that `opam2json` produces, so they
+//! deserialise into the structures in `parse`.
+
+use serde_json::{json, Map, Value as Json};
+use std::error::Error;
+
+use crate::parse::{LogicalOp, OpamJson, RelOp, UnaryOp};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    String(String),
+    Ident(String),
+    Relop {
+        relop: RelOp,
+        lhs: Box<Value>,
+        rhs: Box<Value>,
+    },
+    PrefixRelop {
+        relop: RelOp,
+        arg: Box<Value>,
+    },
+    Logop {
+        logop: LogicalOp,
+        lhs: Box<Value>,
+        rhs: Box<Value>,
+    },
+    Pfxop {
+        op: UnaryOp,
+        arg: Box<Value>,
+    },
+    List(Vec<Value>),
+    Group(Vec<Value>),
+    Option {
+        value: Box<Value>,
+        options: Vec<Value>,
+    },
+    EnvBinding {
+        name: String,
+        op: String,
+        value: Box<Value>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Field(String, Value),
+    Section {
+        kind: String,
+        name: Option<String>,
+        items: Vec<Item>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Bool(bool),
+    Int(i64),
+    String(String),
+    Ident(String),
+    Colon,
+    LBrack,
+    RBrack,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Relop(RelOp),
+    And,
+    Or,
+    Not,
+    Defined,
+    EnvOp(String),
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    src: &'a str,
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+'
+}
+
+/// The line and column of a byte offset, for error messages.
+fn position(src: &str, offset: usize) -> String {
+    let before = &src[..offset.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    format!("{}:{}", line, column)
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str) -> Self {
+        Self {
+            chars: src.char_indices().peekable(),
+            src,
+        }
+    }
+
+    fn peek_char(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek_char() == Some(c) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error(&self, offset: usize, message: &str) -> Box<dyn Error> {
+        format!("{}: {}", position(self.src, offset), message).into()
+    }
+
+    fn skip_block_comment(&mut self, start: usize) -> Result<(), Box<dyn Error>> {
+        // block comments nest
+        let mut depth = 1;
+        while depth > 0 {
+            match self.chars.next() {
+                Some((_, '(')) if self.eat('*') => depth += 1,
+                Some((_, '*')) if self.eat(')') => depth -= 1,
+                Some(_) => {}
+                None => return Err(self.error(start, "unterminated comment")),
+            }
+        }
+        Ok(())
+    }
+
+    fn string(&mut self, start: usize) -> Result<String, Box<dyn Error>> {
+        let triple = self.src[start..].starts_with("\"\"\"");
+        if triple {
+            self.chars.next();
+            self.chars.next();
+        }
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some((i, '"')) => {
+                    if !triple {
+                        return Ok(s);
+                    }
+                    if self.src[i..].starts_with("\"\"\"") {
+                        self.chars.next();
+                        self.chars.next();
+                        return Ok(s);
+                    }
+                    s.push('"');
+                }
+                Some((i, '\\')) => match self.chars.next() {
+                    Some((_, 'n')) => s.push('\n'),
+                    Some((_, 't')) => s.push('\t'),
+                    Some((_, 'r')) => s.push('\r'),
+                    Some((_, 'b')) => s.push('\u{8}'),
+                    Some((_, '\n')) => {
+                        // an escaped newline continues the string after
+                        // the next line's indentation
+                        while matches!(self.peek_char(), Some(' ') | Some('\t')) {
+                            self.chars.next();
+                        }
+                    }
+                    Some((_, c)) => s.push(c),
+                    None => return Err(self.error(i, "unterminated string")),
+                },
+                Some((_, c)) => s.push(c),
+                None => return Err(self.error(start, "unterminated string")),
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<(usize, Token)>, Box<dyn Error>> {
+        loop {
+            let Some((start, c)) = self.chars.next() else {
+                return Ok(None);
+            };
+            let token = match c {
+                c if c.is_whitespace() => continue,
+                '#' => {
+                    while !matches!(self.chars.next(), Some((_, '\n')) | None) {}
+                    continue;
+                }
+                '(' if self.eat('*') => {
+                    self.skip_block_comment(start)?;
+                    continue;
+                }
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                '[' => Token::LBrack,
+                ']' => Token::RBrack,
+                '{' => Token::LBrace,
+                '}' => Token::RBrace,
+                '&' => Token::And,
+                '|' => Token::Or,
+                '?' => Token::Defined,
+                '"' => Token::String(self.string(start)?),
+                '!' if self.eat('=') => Token::Relop(RelOp::Neq),
+                '!' => Token::Not,
+                '<' if self.eat('=') => Token::Relop(RelOp::Leq),
+                '<' => Token::Relop(RelOp::Lt),
+                '>' if self.eat('=') => Token::Relop(RelOp::Geq),
+                '>' => Token::Relop(RelOp::Gt),
+                '=' if self.eat('+') => {
+                    if self.eat('=') {
+                        Token::EnvOp("=+=".to_string())
+                    } else {
+                        Token::EnvOp("=+".to_string())
+                    }
+                }
+                '=' if self.eat(':') => Token::EnvOp("=:".to_string()),
+                '=' => Token::Relop(RelOp::Eq),
+                '+' if self.eat('=') => Token::EnvOp("+=".to_string()),
+                ':' if self.eat('=') => Token::EnvOp(":=".to_string()),
+                ':' => Token::Colon,
+                c if c.is_ascii_digit()
+                    || (c == '-' && self.peek_char().is_some_and(|d| d.is_ascii_digit())) =>
+                {
+                    let mut end = start + c.len_utf8();
+                    while let Some((i, d)) = self.chars.peek().copied() {
+                        if !d.is_ascii_digit() {
+                            break;
+                        }
+                        end = i + d.len_utf8();
+                        self.chars.next();
+                    }
+                    let n = self.src[start..end]
+                        .parse()
+                        .map_err(|_| self.error(start, "invalid integer"))?;
+                    Token::Int(n)
+                }
+                c if is_ident_start(c) => {
+                    let mut end = start + c.len_utf8();
+                    while let Some((i, d)) = self.chars.peek().copied() {
+                        // `pkg:var` is one identifier, but `field: value` is not
+                        let colon_ident = d == ':'
+                            && self.src[i + 1..].chars().next().is_some_and(is_ident_start);
+                        if !is_ident_char(d) && !colon_ident {
+                            break;
+                        }
+                        end = i + d.len_utf8();
+                        self.chars.next();
+                    }
+                    match &self.src[start..end] {
+                        "true" => Token::Bool(true),
+                        "false" => Token::Bool(false),
+                        ident => Token::Ident(ident.to_string()),
+                    }
+                }
+                c => return Err(self.error(start, &format!("unexpected character {:?}", c))),
+            };
+            return Ok(Some((start, token)));
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    src: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Result<Self, Box<dyn Error>> {
+        let mut lexer = Lexer::new(src);
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.next_token()? {
+            tokens.push(token);
+        }
+        Ok(Self {
+            tokens,
+            pos: 0,
+            src,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, token)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn error(&self, message: &str) -> Box<dyn Error> {
+        let offset = self
+            .tokens
+            .get(self.pos)
+            .map_or(self.src.len(), |(offset, _)| *offset);
+        format!("{}: {}", position(self.src, offset), message).into()
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), Box<dyn Error>> {
+        if self.peek() == Some(&expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected {:?}, found {:?}", expected, self.peek())))
+        }
+    }
+
+    fn items(&mut self, closing: Option<Token>) -> Result<Vec<Item>, Box<dyn Error>> {
+        let mut items = Vec::new();
+        loop {
+            if self.peek() == closing.as_ref() {
+                if closing.is_some() {
+                    self.pos += 1;
+                }
+                return Ok(items);
+            }
+            let kind = match self.advance() {
+                Some(Token::Ident(kind)) => kind,
+                token => {
+                    self.pos -= 1;
+                    return Err(self.error(&format!("expected a field name, found {:?}", token)));
+                }
+            };
+            match self.peek() {
+                Some(Token::Colon) => {
+                    self.pos += 1;
+                    items.push(Item::Field(kind, self.value()?));
+                }
+                Some(Token::String(_)) | Some(Token::LBrace) => {
+                    let name = match self.peek() {
+                        Some(Token::String(name)) => {
+                            let name = name.clone();
+                            self.pos += 1;
+                            Some(name)
+                        }
+                        _ => None,
+                    };
+                    self.expect(Token::LBrace)?;
+                    let items_inner = self.items(Some(Token::RBrace))?;
+                    items.push(Item::Section {
+                        kind,
+                        name,
+                        items: items_inner,
+                    });
+                }
+                _ => return Err(self.error("expected `:` or a section")),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, Box<dyn Error>> {
+        let mut lhs = self.and_value()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            let rhs = self.and_value()?;
+            lhs = Value::Logop {
+                logop: LogicalOp::Or,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn and_value(&mut self) -> Result<Value, Box<dyn Error>> {
+        let mut lhs = self.relop_value()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            let rhs = self.relop_value()?;
+            lhs = Value::Logop {
+                logop: LogicalOp::And,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn relop_value(&mut self) -> Result<Value, Box<dyn Error>> {
+        let lhs = self.prefix_value()?;
+        match self.peek() {
+            Some(Token::Relop(relop)) => {
+                let relop = relop.clone();
+                self.pos += 1;
+                let rhs = self.prefix_value()?;
+                Ok(Value::Relop {
+                    relop,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                })
+            }
+            _ => Ok(lhs),
+        }
+    }
+
+    fn prefix_value(&mut self) -> Result<Value, Box<dyn Error>> {
+        match self.peek() {
+            Some(Token::Relop(relop)) => {
+                let relop = relop.clone();
+                self.pos += 1;
+                Ok(Value::PrefixRelop {
+                    relop,
+                    arg: Box::new(self.prefix_value()?),
+                })
+            }
+            Some(Token::Not) | Some(Token::Defined) => {
+                let op = match self.advance() {
+                    Some(Token::Not) => UnaryOp::Not,
+                    _ => UnaryOp::Defined,
+                };
+                Ok(Value::Pfxop {
+                    op,
+                    arg: Box::new(self.prefix_value()?),
+                })
+            }
+            _ => self.option_value(),
+        }
+    }
+
+    fn option_value(&mut self) -> Result<Value, Box<dyn Error>> {
+        let value = self.atom()?;
+        if self.peek() != Some(&Token::LBrace) {
+            return Ok(value);
+        }
+        self.pos += 1;
+        let options = self.values(Token::RBrace)?;
+        Ok(Value::Option {
+            value: Box::new(value),
+            options,
+        })
+    }
+
+    fn values(&mut self, closing: Token) -> Result<Vec<Value>, Box<dyn Error>> {
+        let mut values = Vec::new();
+        while self.peek() != Some(&closing) {
+            if self.peek().is_none() {
+                return Err(self.error(&format!("expected {:?}", closing)));
+            }
+            values.push(self.value()?);
+        }
+        self.pos += 1;
+        Ok(values)
+    }
+
+    fn atom(&mut self) -> Result<Value, Box<dyn Error>> {
+        match self.advance() {
+            Some(Token::Bool(b)) => Ok(Value::Bool(b)),
+            Some(Token::Int(n)) => Ok(Value::Int(n)),
+            Some(Token::String(s)) => Ok(Value::String(s)),
+            Some(Token::Ident(name)) => match self.peek() {
+                Some(Token::EnvOp(op)) => {
+                    let op = op.clone();
+                    self.pos += 1;
+                    Ok(Value::EnvBinding {
+                        name,
+                        op,
+                        value: Box::new(self.value()?),
+                    })
+                }
+                _ => Ok(Value::Ident(name)),
+            },
+            Some(Token::LBrack) => Ok(Value::List(self.values(Token::RBrack)?)),
+            Some(Token::LParen) => Ok(Value::Group(self.values(Token::RParen)?)),
+            token => {
+                self.pos -= 1;
+                Err(self.error(&format!("expected a value, found {:?}", token)))
+            }
+        }
+    }
+}
+
+/// Parse the contents of an opam file into its fields and sections.
+pub fn parse_items(src: &str) -> Result<Vec<Item>, Box<dyn Error>> {
+    let mut parser = Parser::new(src)?;
+    parser.items(None)
+}
+
+/// The variables a string interpolates with `%{var}%`, in order. A
+/// `%{pkg:var}%` reference keeps its package prefix.
+pub fn interpolated_variables(s: &str) -> Vec<&str> {
+    let mut variables = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("%{") {
+        let after = &rest[start + 2..];
+        match after.find("}%") {
+            Some(end) => {
+                variables.push(&after[..end]);
+                rest = &after[end + 2..];
+            }
+            None => break,
+        }
+    }
+    variables
+}
+
+fn relop_name(relop: &RelOp) -> &'static str {
+    match relop {
+        RelOp::Eq => "eq",
+        RelOp::Neq => "neq",
+        RelOp::Geq => "geq",
+        RelOp::Gt => "gt",
+        RelOp::Leq => "leq",
+        RelOp::Lt => "lt",
+    }
+}
+
+impl Value {
+    /// The JSON `opam2json` produces for this value.
+    pub fn to_json(&self) -> Json {
+        match self {
+            Value::Bool(b) => json!(b),
+            Value::Int(n) => json!(n),
+            Value::String(s) => json!(s),
+            Value::Ident(id) => json!({ "id": id }),
+            Value::Relop { relop, lhs, rhs } => json!({
+                "relop": relop_name(relop),
+                "lhs": lhs.to_json(),
+                "rhs": rhs.to_json()
+            }),
+            Value::PrefixRelop { relop, arg } => json!({
+                "prefix_relop": relop_name(relop),
+                "arg": arg.to_json()
+            }),
+            Value::Logop { logop, lhs, rhs } => json!({
+                "logop": match logop {
+                    LogicalOp::And => "and",
+                    LogicalOp::Or => "or",
+                },
+                "lhs": lhs.to_json(),
+                "rhs": rhs.to_json()
+            }),
+            Value::Pfxop { op, arg } => json!({
+                "pfxop": match op {
+                    UnaryOp::Not => "not",
+                    UnaryOp::Defined => "defined",
+                },
+                "arg": arg.to_json()
+            }),
+            Value::List(values) => Json::Array(values.iter().map(Value::to_json).collect()),
+            Value::Group(values) => json!({
+                "group": values.iter().map(Value::to_json).collect::<Vec<_>>()
+            }),
+            Value::Option { value, options } => json!({
+                "val": value.to_json(),
+                "conditions": options.iter().map(Value::to_json).collect::<Vec<_>>()
+            }),
+            Value::EnvBinding { name, op, value } => json!({
+                "id": name,
+                "env_update": op,
+                "value": value.to_json()
+            }),
+        }
+    }
+}
+
+/// The JSON `opam2json` produces for a whole file: an object of its fields,
+/// with each section an object of its items under the section kind, keyed by
+/// the section name if it has one.
+pub fn items_to_json(items: &[Item]) -> Json {
+    let mut object = Map::new();
+    for item in items {
+        match item {
+            Item::Field(name, value) => {
+                object.insert(name.clone(), value.to_json());
+            }
+            Item::Section { kind, name, items } => {
+                let section = items_to_json(items);
+                match name {
+                    Some(name) => {
+                        let entry = object
+                            .entry(kind.clone())
+                            .or_insert_with(|| Json::Object(Map::new()));
+                        if let Json::Object(named) = entry {
+                            named.insert(name.clone(), section);
+                        }
+                    }
+                    None => {
+                        object.insert(kind.clone(), section);
+                    }
+                }
+            }
+        }
+    }
+    Json::Object(object)
+}
+
+/// Parse an opam file into the structure an `opam.json` file deserialises to.
+pub fn parse_opam_file(src: &str) -> Result<OpamJson, Box<dyn Error>> {
+    let json = items_to_json(&parse_items(src)?);
+    Ok(serde_json::from_value(json)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_values() {
+        let items = parse_items(
+            r#"opam-version: "2.0"
+# a comment
+name: "foo" (* a (* nested *) comment *)
+depends: [
+  "ocaml" {>= "4.08" & with-test}
+  "dune" | "jbuilder"
+]
+build: [make "-j%{jobs}%"] {os != "win32"}
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            items[0],
+            Item::Field("opam-version".to_string(), Value::String("2.0".to_string()))
+        );
+        assert_eq!(
+            items[2],
+            Item::Field(
+                "depends".to_string(),
+                Value::List(vec![
+                    Value::Option {
+                        value: Box::new(Value::String("ocaml".to_string())),
+                        options: vec![Value::Logop {
+                            logop: LogicalOp::And,
+                            lhs: Box::new(Value::PrefixRelop {
+                                relop: RelOp::Geq,
+                                arg: Box::new(Value::String("4.08".to_string())),
+                            }),
+                            rhs: Box::new(Value::Ident("with-test".to_string())),
+                        }],
+                    },
+                    Value::Logop {
+                        logop: LogicalOp::Or,
+                        lhs: Box::new(Value::String("dune".to_string())),
+                        rhs: Box::new(Value::String("jbuilder".to_string())),
+                    },
+                ])
+            )
+        );
+        assert!(matches!(&items[3], Item::Field(name, Value::Option { .. }) if name == "build"));
+    }
+
+    #[test]
+    fn test_precedence() {
+        let items = parse_items("f: a | b & c = d").unwrap();
+        assert_eq!(
+            items_to_json(&items),
+            json!({ "f": {
+                "logop": "or",
+                "lhs": { "id": "a" },
+                "rhs": {
+                    "logop": "and",
+                    "lhs": { "id": "b" },
+                    "rhs": { "relop": "eq", "lhs": { "id": "c" }, "rhs": { "id": "d" } },
+                },
+            }})
+        );
+    }
+
+    #[test]
+    fn test_sections_and_literals() {
+        let items = parse_items(
+            r#"url {
+  src: "https://example.com/foo.tar.gz"
+  checksum: ["sha256=abc"]
+}
+extra-source "fix.patch" { src: "https://example.com/fix.patch" }
+flags: [avoid-version]
+x-ci-accept-failures: true
+build-env: [FOO += "bar"]
+jobs: -1
+available: ocaml:version >= "4.08" & !(os = "win32")
+synopsis: """a "quoted" word"""
+"#,
+        )
+        .unwrap();
+        let json = items_to_json(&items);
+        assert_eq!(json["url"]["src"], json!("https://example.com/foo.tar.gz"));
+        assert_eq!(
+            json["extra-source"]["fix.patch"]["src"],
+            json!("https://example.com/fix.patch")
+        );
+        assert_eq!(json["flags"], json!([{ "id": "avoid-version" }]));
+        assert_eq!(json["x-ci-accept-failures"], json!(true));
+        assert_eq!(
+            json["build-env"],
+            json!([{ "id": "FOO", "env_update": "+=", "value": "bar" }])
+        );
+        assert_eq!(json["jobs"], json!(-1));
+        assert_eq!(
+            json["available"]["lhs"]["lhs"],
+            json!({ "id": "ocaml:version" })
+        );
+        assert_eq!(json["synopsis"], json!("a \"quoted\" word"));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse_items("depends: [\"foo\"").is_err());
+        assert!(parse_items("depends \"foo\"").is_err());
+        let error = parse_items("name: \"foo\"\ndepends: [\n  \"bar\" {\n").unwrap_err();
+        assert!(error.to_string().starts_with("4:1"), "{}", error);
+    }
+
+    /// Every `opam` file under `root`, with the `opam.json` beside it if any.
+    fn opam_files(root: &str) -> Vec<(std::path::PathBuf, Option<std::path::PathBuf>)> {
+        walkdir::WalkDir::new(root)
+            .into_iter()
+            .map(|entry| entry.unwrap().into_path())
+            .filter(|path| path.file_name().is_some_and(|name| name == "opam"))
+            .map(|path| {
+                let json = path.with_file_name("opam.json");
+                let json = json.exists().then_some(json);
+                (path, json)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_example_repo_matches_opam2json() {
+        let files = opam_files("./example-repo/packages");
+        assert!(!files.is_empty());
+        for (opam, json) in files {
+            let native = items_to_json(&parse_items(&fs::read_to_string(&opam).unwrap()).unwrap());
+            let json: Json =
+                serde_json::from_str(&fs::read_to_string(json.unwrap()).unwrap()).unwrap();
+            assert_eq!(native, json, "{}", opam.display());
+        }
+    }
+
+    #[test]
+    fn test_opam_repository_corpus() {
+        let files = opam_files("./opam-repository/packages");
+        assert!(!files.is_empty());
+        for (opam, json) in files {
+            let content = fs::read_to_string(&opam).unwrap();
+            let native = items_to_json(&parse_items(&content).unwrap_or_else(|e| {
+                panic!("{}:{}", opam.display(), e);
+            }));
+            if let Some(json) = json {
+                let json: Json = serde_json::from_str(&fs::read_to_string(json).unwrap()).unwrap();
+                for field in [
+                    "depends",
+                    "depexts",
+                    "conflict-class",
+                    "conflicts",
+                    "available",
+                ] {
+                    let (native, json) = (native.get(field), json.get(field));
+                    assert_eq!(native, json, "{} {}", opam.display(), field);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_interpolated_variables() {
+        assert_eq!(
+            interpolated_variables("%{prefix}%/lib/%{ocaml:version}%"),
+            vec!["prefix", "ocaml:version"]
+        );
+        assert!(interpolated_variables("no %{ variables").is_empty());
+    }
+}
@@ -1,10 +1,14 @@
-use crate::index::{Binary, HashedRange, PackageFormula, VersionFormula};
+use crate::deps::{FALSE_VERSION, TRUE_VERSION};
+use crate::index::{
+    Binary, HashedRange, OpamSource, PackageFormula, PackageName, Pin, VersionFormula,
+};
+use crate::opam_file::parse_opam_file;
 use crate::version::OpamVersion;
 use pubgrub::Range;
 use serde::Deserialize;
 use std::error::Error;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 #[derive(Debug, Deserialize)]
@@ -13,10 +17,155 @@ pub struct OpamJson {
     pub opam_version: Option<String>,
     pub name: Option<String>,
     pub version: Option<String>,
+    pub synopsis: Option<String>,
+    pub description: Option<String>,
+    pub tags: Option<StringsField>,
+    pub maintainer: Option<StringsField>,
+    pub license: Option<StringsField>,
+    pub homepage: Option<StringsField>,
+    #[serde(rename = "dev-repo")]
+    pub dev_repo: Option<String>,
     pub depends: Option<DependsField>,
+    pub depopts: Option<DependsField>,
     pub depexts: Option<Vec<DepextField>>,
     #[serde(rename = "conflict-class")]
     pub conflict_class: Option<String>,
+    pub conflicts: Option<DependsField>,
+    pub available: Option<AvailableField>,
+    pub flags: Option<FlagsField>,
+    #[serde(rename = "x-maintenance-intent")]
+    pub x_maintenance_intent: Option<StringsField>,
+    #[serde(rename = "pin-depends")]
+    pub pin_depends: Option<PinDependsField>,
+    pub url: Option<UrlSection>,
+}
+
+impl OpamJson {
+    /// The `flags:` of the package version, like `avoid-version`.
+    pub fn flags(&self) -> Vec<String> {
+        match &self.flags {
+            Some(FlagsField::Single(flag)) => vec![flag.id.clone()],
+            Some(FlagsField::Multiple(flags)) => flags.iter().map(|flag| flag.id.clone()).collect(),
+            None => vec![],
+        }
+    }
+
+    /// The versions the maintainers intend to maintain, like `(latest)`.
+    pub fn maintenance_intent(&self) -> Vec<String> {
+        strings(&self.x_maintenance_intent)
+    }
+
+    pub fn tags(&self) -> Vec<String> {
+        strings(&self.tags)
+    }
+
+    pub fn maintainers(&self) -> Vec<String> {
+        strings(&self.maintainer)
+    }
+
+    pub fn licenses(&self) -> Vec<String> {
+        strings(&self.license)
+    }
+
+    pub fn homepages(&self) -> Vec<String> {
+        strings(&self.homepage)
+    }
+
+    /// The `depends:` formulas, one per entry of the list.
+    pub fn depends(&self) -> Result<Vec<PackageFormula>, Box<dyn Error>> {
+        package_formulas(&self.depends)
+    }
+
+    /// The `depopts:` formulas, one per entry of the list.
+    pub fn depopts(&self) -> Result<Vec<PackageFormula>, Box<dyn Error>> {
+        package_formulas(&self.depopts)
+    }
+
+    /// The `pin-depends:` of a project, each a `"package.version"` and the
+    /// source it is pinned to.
+    pub fn pin_depends(&self) -> Result<Vec<(PackageName, Pin)>, Box<dyn Error>> {
+        let pins = match &self.pin_depends {
+            Some(PinDependsField::Single(pin)) => vec![pin.clone()],
+            Some(PinDependsField::Multiple(pins)) => pins.clone(),
+            None => vec![],
+        };
+        pins.iter()
+            .map(|pin| match pin.as_slice() {
+                [atom, url] => match atom.split_once('.') {
+                    Some((package, version)) if !package.is_empty() && !version.is_empty() => Ok((
+                        package.to_string(),
+                        Pin {
+                            version: OpamVersion(version.to_string()),
+                            url: url.clone(),
+                        },
+                    )),
+                    _ => Err(format!("Pin {} isn't of the form package.version", atom).into()),
+                },
+                _ => Err(format!("Invalid pin-depends entry {:?}", pin).into()),
+            })
+            .collect()
+    }
+
+    /// Where the package version's source is fetched from, if it has any.
+    pub fn source(&self) -> Option<OpamSource> {
+        let url = self.url.as_ref()?;
+        Some(OpamSource {
+            src: url.src.clone()?,
+            checksums: strings(&url.checksum),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum PinDependsField {
+    Multiple(Vec<Vec<String>>),
+    Single(Vec<String>),
+}
+
+/// The `url { src checksum }` section of an opam file.
+#[derive(Debug, Deserialize)]
+pub struct UrlSection {
+    pub src: Option<String>,
+    pub checksum: Option<StringsField>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum FlagsField {
+    Single(Flag),
+    Multiple(Vec<Flag>),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Flag {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum StringsField {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+// a field that is either one string or a list of them
+fn strings(field: &Option<StringsField>) -> Vec<String> {
+    match field {
+        Some(StringsField::Single(value)) => vec![value.clone()],
+        Some(StringsField::Multiple(values)) => values.clone(),
+        None => vec![],
+    }
+}
+
+fn package_formulas(field: &Option<DependsField>) -> Result<Vec<PackageFormula>, Box<dyn Error>> {
+    match field {
+        Some(DependsField::Multiple(formulas)) => {
+            formulas.iter().map(parse_package_formula).collect()
+        }
+        Some(DependsField::Single(formula)) => Ok(vec![parse_package_formula(formula)?]),
+        None => Ok(vec![]),
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -26,6 +175,14 @@ pub enum DependsField {
     Multiple(Vec<OpamPackageFormula>),
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum AvailableField {
+    Literal(bool),
+    Single(FilterExpr),
+    Multiple(Vec<FilterExpr>),
+}
+
 #[derive(Debug, Deserialize)]
 pub struct DepextField {
     #[serde(rename = "val")]
@@ -110,9 +267,8 @@ pub enum FilterOrVersion {
 #[serde(untagged)]
 pub enum LiteralValue {
     Str(String),
-    // TODO implement logic for these if they're actually used anywhere
-    // Int(i64),
-    // Bool(bool),
+    Int(i64),
+    Bool(bool),
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -152,6 +308,19 @@ pub fn negate_relop(relop: RelOp) -> RelOp {
     }
 }
 
+/// The operator that keeps a comparison's meaning when its sides swap, so
+/// that `"4" <= jobs` reads as `jobs >= "4"`.
+pub fn flip_relop(relop: &RelOp) -> RelOp {
+    match relop {
+        RelOp::Eq => RelOp::Eq,
+        RelOp::Neq => RelOp::Neq,
+        RelOp::Geq => RelOp::Leq,
+        RelOp::Gt => RelOp::Lt,
+        RelOp::Leq => RelOp::Geq,
+        RelOp::Lt => RelOp::Gt,
+    }
+}
+
 pub fn relop_to_range(relop: &RelOp, version: OpamVersion) -> Range<OpamVersion> {
     match relop {
         RelOp::Eq => Range::<OpamVersion>::singleton(version),
@@ -164,7 +333,7 @@ pub fn relop_to_range(relop: &RelOp, version: OpamVersion) -> Range<OpamVersion>
 }
 
 // not quite CNF, we just move negations to the leaves
-fn normalize_negation(expr: VersionFormula) -> VersionFormula {
+pub fn normalize_negation(expr: VersionFormula) -> VersionFormula {
     match expr {
         VersionFormula::Version(version) => {
             VersionFormula::Version(HashedRange(version.0.complement()))
@@ -172,214 +341,320 @@ fn normalize_negation(expr: VersionFormula) -> VersionFormula {
         VersionFormula::Variable(variable) => VersionFormula::Not(variable),
         VersionFormula::Not(variable) => VersionFormula::Variable(variable),
         // De Morgan’s laws
-        VersionFormula::And(Binary { lhs, rhs }) => VersionFormula::Or(Binary {
-            lhs: Box::new(normalize_negation(*lhs)),
-            rhs: Box::new(normalize_negation(*rhs)),
-        }),
-        VersionFormula::Or(Binary { lhs, rhs }) => VersionFormula::And(Binary {
-            lhs: Box::new(normalize_negation(*lhs)),
-            rhs: Box::new(normalize_negation(*rhs)),
-        }),
+        VersionFormula::And(Binary { lhs, rhs }) => {
+            or_formulas(normalize_negation(*lhs), normalize_negation(*rhs))
+        }
+        VersionFormula::Or(Binary { lhs, rhs }) => {
+            and_formulas(normalize_negation(*lhs), normalize_negation(*rhs))
+        }
         VersionFormula::Comparator { relop, binary } => VersionFormula::Comparator {
             relop: negate_relop(relop),
             binary,
         },
-        _ => expr,
+        VersionFormula::Lit(lit) => match as_bool(&lit) {
+            Some(value) => bool_formula(!value),
+            None => VersionFormula::Lit(lit),
+        },
+    }
+}
+
+/// The value of a literal used as a filter. Like opam, the strings `"true"`
+/// and `"false"` count as booleans.
+pub fn as_bool(lit: &OpamVersion) -> Option<bool> {
+    if *lit == *TRUE_VERSION {
+        Some(true)
+    } else if *lit == *FALSE_VERSION {
+        Some(false)
+    } else {
+        None
     }
 }
 
-fn parse_filter_expr(filter: &FilterExpr) -> VersionFormula {
+pub fn bool_formula(value: bool) -> VersionFormula {
+    match value {
+        true => VersionFormula::Lit(TRUE_VERSION.clone()),
+        false => VersionFormula::Lit(FALSE_VERSION.clone()),
+    }
+}
+
+pub fn formula_bool(formula: &VersionFormula) -> Option<bool> {
+    match formula {
+        VersionFormula::Lit(lit) => as_bool(lit),
+        _ => None,
+    }
+}
+
+// intersect version ranges directly, so unfiltered formulas stay a single range,
+// and fold away boolean literals
+fn and_formulas(lhs: VersionFormula, rhs: VersionFormula) -> VersionFormula {
+    match (formula_bool(&lhs), formula_bool(&rhs)) {
+        (Some(true), _) => return rhs,
+        (_, Some(true)) => return lhs,
+        (Some(false), _) | (_, Some(false)) => return bool_formula(false),
+        _ => (),
+    }
+    match (lhs, rhs) {
+        (VersionFormula::Version(l), VersionFormula::Version(r)) => {
+            VersionFormula::Version(HashedRange(l.0.intersection(&r.0)))
+        }
+        (lhs, rhs) => VersionFormula::And(Binary {
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        }),
+    }
+}
+
+fn or_formulas(lhs: VersionFormula, rhs: VersionFormula) -> VersionFormula {
+    match (formula_bool(&lhs), formula_bool(&rhs)) {
+        (Some(false), _) => return rhs,
+        (_, Some(false)) => return lhs,
+        (Some(true), _) | (_, Some(true)) => return bool_formula(true),
+        _ => (),
+    }
+    match (lhs, rhs) {
+        (VersionFormula::Version(l), VersionFormula::Version(r)) => {
+            VersionFormula::Version(HashedRange(l.0.union(&r.0)))
+        }
+        (lhs, rhs) => VersionFormula::Or(Binary {
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        }),
+    }
+}
+
+// juxtaposed condition atoms, like `{>= "1" < "2"}`, are a conjunction
+fn parse_conditions(conditions: &[OpamVersionFormula]) -> Result<VersionFormula, Box<dyn Error>> {
+    Ok(conditions
+        .iter()
+        .map(parse_version_formula)
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .reduce(and_formulas)
+        .unwrap_or(VersionFormula::Version(HashedRange(Range::full()))))
+}
+
+// `?var`, whether a variable is defined, which only applies to a variable
+fn defined_formula(formula: VersionFormula) -> Result<VersionFormula, Box<dyn Error>> {
+    match formula {
+        VersionFormula::Variable(id) => Ok(VersionFormula::Comparator {
+            relop: RelOp::Neq,
+            binary: Binary {
+                lhs: Box::new(VersionFormula::Variable(id)),
+                rhs: Box::new(VersionFormula::Version(HashedRange(Range::singleton(
+                    OpamVersion("".to_string()),
+                )))),
+            },
+        }),
+        formula => Err(format!("? must be applied to a variable, not {}", formula).into()),
+    }
+}
+
+// the conjunction of a group's formulas, which can't be empty
+fn parse_group<T, F>(group: &[T], parse: F) -> Result<VersionFormula, Box<dyn Error>>
+where
+    F: Fn(&T) -> Result<VersionFormula, Box<dyn Error>>,
+{
+    group
+        .iter()
+        .map(parse)
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .reduce(and_formulas)
+        .ok_or_else(|| "Empty group".into())
+}
+
+fn parse_filter_expr(filter: &FilterExpr) -> Result<VersionFormula, Box<dyn Error>> {
     match filter {
         FilterExpr::LogOp { logop, lhs, rhs } => {
-            let left = parse_filter_expr(lhs);
-            let right = parse_filter_expr(rhs);
-            match logop {
-                LogicalOp::And => match (left.clone(), right.clone()) {
-                    (VersionFormula::Version(l), VersionFormula::Version(r)) => {
-                        VersionFormula::Version(HashedRange(l.0.intersection(&r.0)))
-                    }
-                    _ => VersionFormula::And(Binary {
-                        lhs: Box::new(left),
-                        rhs: Box::new(right),
-                    }),
-                },
-                LogicalOp::Or => match (left.clone(), right.clone()) {
-                    (VersionFormula::Version(l), VersionFormula::Version(r)) => {
-                        VersionFormula::Version(HashedRange(l.0.union(&r.0)))
-                    }
-                    _ => VersionFormula::Or(Binary {
-                        lhs: Box::new(left),
-                        rhs: Box::new(right),
-                    }),
-                },
-            }
+            let left = parse_filter_expr(lhs)?;
+            let right = parse_filter_expr(rhs)?;
+            Ok(match logop {
+                LogicalOp::And => and_formulas(left, right),
+                LogicalOp::Or => or_formulas(left, right),
+            })
         }
         FilterExpr::Unary { pfxop, arg } => match pfxop {
-            UnaryOp::Not => {
-                let inner = parse_filter_expr(*&arg);
-                normalize_negation(inner)
-            }
-            UnaryOp::Defined => match parse_filter_expr(*&arg) {
-                VersionFormula::Variable(id) => VersionFormula::Comparator {
-                    relop: RelOp::Neq,
-                    binary: Binary {
-                        lhs: Box::new(VersionFormula::Variable(id)),
-                        rhs: Box::new(VersionFormula::Version(HashedRange(Range::singleton(
-                            OpamVersion("".to_string()),
-                        )))),
-                    },
-                },
-                _ => panic!("Defined must be for a variable"),
-            },
+            UnaryOp::Not => Ok(normalize_negation(parse_filter_expr(arg)?)),
+            UnaryOp::Defined => defined_formula(parse_filter_expr(arg)?),
         },
-        FilterExpr::Group { group } => {
-            if group.is_empty() {
-                panic!("Empty group");
-            } else {
-                parse_filter_expr(&group[0])
-            }
-        }
+        FilterExpr::Group { group } => parse_group(group, parse_filter_expr),
         FilterExpr::Relop { relop, lhs, rhs } => {
-            let left = parse_filter_expr(lhs);
-            let right = parse_filter_expr(rhs);
-            VersionFormula::Comparator {
+            let left = parse_filter_expr(lhs)?;
+            let right = parse_filter_expr(rhs)?;
+            Ok(VersionFormula::Comparator {
                 relop: relop.clone(),
                 binary: Binary {
                     lhs: Box::new(left),
                     rhs: Box::new(right),
                 },
-            }
+            })
         }
-        FilterExpr::Variable { id } => VersionFormula::Variable(id.to_string()),
-        FilterExpr::Literal(lit) => match lit {
-            LiteralValue::Str(s) => {
-                let version = s.parse::<OpamVersion>().unwrap();
-                VersionFormula::Lit(version)
-            }
-        },
+        FilterExpr::Variable { id } => Ok(VersionFormula::Variable(id.to_string())),
+        FilterExpr::Literal(lit) => Ok(match lit {
+            LiteralValue::Str(s) => VersionFormula::Lit(s.parse::<OpamVersion>()?),
+            // compared against variable values, which are strings
+            LiteralValue::Int(i) => VersionFormula::Lit(OpamVersion(i.to_string())),
+            LiteralValue::Bool(b) => bool_formula(*b),
+        }),
     }
 }
 
-fn parse_version_formula(formula: &OpamVersionFormula) -> VersionFormula {
+fn parse_version_formula(formula: &OpamVersionFormula) -> Result<VersionFormula, Box<dyn Error>> {
     match formula {
         OpamVersionFormula::LogOp { logop, lhs, rhs } => {
-            let left = parse_version_formula(lhs);
-            let right = parse_version_formula(rhs);
-            match logop {
-                LogicalOp::And => match (left.clone(), right.clone()) {
-                    (VersionFormula::Version(l), VersionFormula::Version(r)) => {
-                        VersionFormula::Version(HashedRange(l.0.intersection(&r.0)))
-                    }
-                    _ => VersionFormula::And(Binary {
-                        lhs: Box::new(left),
-                        rhs: Box::new(right),
-                    }),
-                },
-                LogicalOp::Or => match (left.clone(), right.clone()) {
-                    (VersionFormula::Version(l), VersionFormula::Version(r)) => {
-                        VersionFormula::Version(HashedRange(l.0.union(&r.0)))
-                    }
-                    _ => VersionFormula::Or(Binary {
-                        lhs: Box::new(left),
-                        rhs: Box::new(right),
-                    }),
-                },
-            }
+            let left = parse_version_formula(lhs)?;
+            let right = parse_version_formula(rhs)?;
+            Ok(match logop {
+                LogicalOp::And => and_formulas(left, right),
+                LogicalOp::Or => or_formulas(left, right),
+            })
         }
         OpamVersionFormula::PrefixRelop { prefix_relop, arg } => match arg {
             FilterOrVersion::Version(version) => {
-                let version = version.parse::<OpamVersion>().unwrap();
-                let range = relop_to_range(prefix_relop, version);
-                VersionFormula::Version(HashedRange(range))
+                let range = relop_to_range(prefix_relop, version.parse::<OpamVersion>()?);
+                Ok(VersionFormula::Version(HashedRange(range)))
             }
             FilterOrVersion::Filter(filter) => parse_filter_expr(filter),
         },
-        OpamVersionFormula::Group { group } => {
-            if group.is_empty() {
-                panic!("Empty group");
-            } else {
-                parse_version_formula(&group[0])
-            }
-        }
+        OpamVersionFormula::Group { group } => parse_group(group, parse_version_formula),
         OpamVersionFormula::PrefixOperator { pfxop, arg } => match pfxop {
-            UnaryOp::Not => {
-                let inner = parse_version_formula(*&arg);
-                normalize_negation(inner)
-            }
-            UnaryOp::Defined => match parse_version_formula(*&arg) {
-                VersionFormula::Variable(id) => VersionFormula::Comparator {
-                    relop: RelOp::Neq,
-                    binary: Binary {
-                        lhs: Box::new(VersionFormula::Variable(id)),
-                        rhs: Box::new(VersionFormula::Version(HashedRange(Range::singleton(
-                            OpamVersion("".to_string()),
-                        )))),
-                    },
-                },
-                _ => panic!("Defined must be for a variable"),
-            },
+            UnaryOp::Not => Ok(normalize_negation(parse_version_formula(arg)?)),
+            UnaryOp::Defined => defined_formula(parse_version_formula(arg)?),
         },
         OpamVersionFormula::Filter(filter) => parse_filter_expr(filter),
     }
 }
 
-pub fn parse_package_formula(formula: &OpamPackageFormula) -> PackageFormula {
+pub fn parse_package_formula(
+    formula: &OpamPackageFormula,
+) -> Result<PackageFormula, Box<dyn Error>> {
     match formula {
-        OpamPackageFormula::Simple { name, conditions } => {
-            let formula = if conditions.is_empty() {
-                VersionFormula::Version(HashedRange(Range::full()))
-            } else {
-                parse_version_formula(&conditions[0])
-            };
-            PackageFormula::Base {
-                name: name.clone(),
-                formula,
-            }
-        }
+        OpamPackageFormula::Simple { name, conditions } => Ok(PackageFormula::Base {
+            name: name.clone(),
+            formula: parse_conditions(conditions)?,
+        }),
         // For a binary formula, recursively convert the left- and right-hand sides.
         OpamPackageFormula::Binary { logop, lhs, rhs } => {
-            let lhs_conv = parse_package_formula(lhs);
-            let rhs_conv = parse_package_formula(rhs);
+            let lhs_conv = parse_package_formula(lhs)?;
+            let rhs_conv = parse_package_formula(rhs)?;
             let binary = Binary {
                 lhs: Box::new(lhs_conv),
                 rhs: Box::new(rhs_conv),
             };
-            match logop {
+            Ok(match logop {
                 LogicalOp::And => PackageFormula::And(binary),
                 LogicalOp::Or => PackageFormula::Or(binary),
-            }
-        }
-        OpamPackageFormula::Group { group } => {
-            if group.is_empty() {
-                panic!("Empty group");
-            } else {
-                parse_package_formula(&group[0])
-            }
+            })
         }
-        OpamPackageFormula::Plain(s) => PackageFormula::Base {
+        // juxtaposed formulas in a group are a conjunction, as in a list
+        OpamPackageFormula::Group { group } => group
+            .iter()
+            .map(parse_package_formula)
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .reduce(|lhs, rhs| {
+                PackageFormula::And(Binary {
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                })
+            })
+            .ok_or_else(|| "Empty group".into()),
+        OpamPackageFormula::Plain(s) => Ok(PackageFormula::Base {
             name: s.clone(),
             formula: VersionFormula::Version(HashedRange(Range::full())),
-        },
+        }),
     }
 }
 
-pub fn parse_depext(depext: &DepextField) -> PackageFormula {
-    let formula = if depext.conditions.is_empty() {
-        VersionFormula::Version(HashedRange(Range::full()))
-    } else {
-        parse_filter_expr(&depext.conditions[0])
+/// Split a `conflicts:` entry into one conflict per package it names. A
+/// package conflicts with every package in a disjunction, but a conjunction
+/// would need all of them installed at once, which we can't express per
+/// package, so it's left out rather than making the package unavailable.
+pub fn parse_conflict(formula: PackageFormula, package: &str) -> Vec<PackageFormula> {
+    match formula {
+        PackageFormula::Base { name, formula } => vec![PackageFormula::Conflict {
+            name,
+            formula,
+            package: package.to_string(),
+        }],
+        PackageFormula::Or(Binary { lhs, rhs }) => {
+            let mut conflicts = parse_conflict(*lhs, package);
+            conflicts.extend(parse_conflict(*rhs, package));
+            conflicts
+        }
+        formula => {
+            tracing::warn!(
+                package = %package,
+                conflict = %formula,
+                "skipping unsupported conflict"
+            );
+            vec![]
+        }
+    }
+}
+
+/// Split a `depopts:` entry into one optional dependency per package it
+/// names.
+pub fn parse_depopt(formula: PackageFormula, package: &str) -> Vec<PackageFormula> {
+    match formula {
+        PackageFormula::Base { name, formula } => vec![PackageFormula::Depopt {
+            name,
+            formula,
+            package: package.to_string(),
+        }],
+        PackageFormula::And(Binary { lhs, rhs }) | PackageFormula::Or(Binary { lhs, rhs }) => {
+            let mut depopts = parse_depopt(*lhs, package);
+            depopts.extend(parse_depopt(*rhs, package));
+            depopts
+        }
+        formula => vec![formula],
+    }
+}
+
+/// The platforms a package is available on. The list form is a conjunction.
+pub fn parse_available(
+    available: &AvailableField,
+    package: &str,
+) -> Result<PackageFormula, Box<dyn Error>> {
+    let formula = match available {
+        AvailableField::Literal(available) => {
+            VersionFormula::Lit(OpamVersion(available.to_string()))
+        }
+        AvailableField::Single(filter) => parse_filter_expr(filter)?,
+        AvailableField::Multiple(filters) => filters
+            .iter()
+            .map(parse_filter_expr)
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .reduce(and_formulas)
+            .unwrap_or(VersionFormula::Lit(OpamVersion("true".to_string()))),
     };
-    PackageFormula::Depext {
+    Ok(PackageFormula::Available {
+        package: package.to_string(),
+        formula,
+    })
+}
+
+pub fn parse_depext(depext: &DepextField) -> Result<PackageFormula, Box<dyn Error>> {
+    let formula = depext
+        .conditions
+        .iter()
+        .map(parse_filter_expr)
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .reduce(and_formulas)
+        .unwrap_or(VersionFormula::Version(HashedRange(Range::full())));
+    Ok(PackageFormula::Depext {
         names: depext.names.clone(),
         formula,
-    }
+    })
 }
 
 /// Given a repository path and a package name, returns a vector of available versions
 /// for that package, in descending order (newest first).
 ///
 /// The repository is assumed to have the following structure:
-///   repo_path/package-name/package-name.version/opam.json
+///   repo_path/package-name/package-name.version/opam
 pub fn available_versions_from_repo(
     repo_path: &str,
     package: &str,
@@ -418,6 +693,13 @@ pub fn available_versions_from_repo(
     Ok(versions)
 }
 
+/// The directory a package version's files live in: repo_path/package/package.version
+pub fn package_version_dir(repo_path: &str, package: &str, version: &str) -> PathBuf {
+    Path::new(repo_path)
+        .join(package)
+        .join(format!("{}.{}", package, version))
+}
+
 fn get_depends(formula: Option<DependsField>) -> Vec<OpamPackageFormula> {
     match formula {
         Some(DependsField::Multiple(vec)) => vec,
@@ -433,19 +715,22 @@ fn get_depexts(depexts: Option<Vec<DepextField>>) -> Vec<DepextField> {
     }
 }
 
-/// Given a repository path, package name, and version,
-/// returns the dependency formulas for that package version.
-pub fn parse_dependencies_for_package_version(
+/// Read the metadata of a package version. The `opam` file is parsed
+/// directly when present, otherwise an `opam.json` generated by `opam2json`
+/// is read.
+pub fn read_opam_metadata(
     repo_path: &str,
     package: &str,
     version: &str,
-) -> Result<Vec<PackageFormula>, Box<dyn Error>> {
+) -> Result<OpamJson, Box<dyn Error>> {
     // Build the expected directory path.
     // For example:
-    //   repo_path/packages/A/A.2.0.0/opam.json
-    let pkg_dir = Path::new(repo_path)
-        .join(package)
-        .join(format!("{}.{}", package, version));
+    //   repo_path/packages/A/A.2.0.0/opam
+    let pkg_dir = package_version_dir(repo_path, package, version);
+    let opam_file = pkg_dir.join("opam");
+    if opam_file.exists() {
+        return read_opam_file(&opam_file);
+    }
     let opam_file = pkg_dir.join("opam.json");
 
     // Read the opam file.
@@ -461,12 +746,37 @@ pub fn parse_dependencies_for_package_version(
             content
         )
     })?;
+    Ok(opam_data)
+}
+
+/// Read an opam file, like a project's `<project>.opam`.
+pub fn read_opam_file(opam_file: &Path) -> Result<OpamJson, Box<dyn Error>> {
+    let content = fs::read_to_string(opam_file)
+        .map_err(|e| format!("Failed to read {}: {}", opam_file.display(), e))?;
+    parse_opam_file(&content)
+        .map_err(|e| format!("Error parsing {}:{}", opam_file.display(), e).into())
+}
 
+/// Given a repository path, package name, and version,
+/// returns the dependency formulas for that package version.
+pub fn parse_dependencies_for_package_version(
+    repo_path: &str,
+    package: &str,
+    version: &str,
+) -> Result<Vec<PackageFormula>, Box<dyn Error>> {
+    parse_dependencies(read_opam_metadata(repo_path, package, version)?, package)
+}
+
+/// The dependency formulas in the metadata of a version of a package.
+pub fn parse_dependencies(
+    opam_data: OpamJson,
+    package: &str,
+) -> Result<Vec<PackageFormula>, Box<dyn Error>> {
     // Convert the dependency formulas, if any.
     let mut dependencies: Vec<PackageFormula> = get_depends(opam_data.depends)
-        .into_iter()
-        .map(|pf| parse_package_formula(&pf))
-        .collect();
+        .iter()
+        .map(parse_package_formula)
+        .collect::<Result<_, _>>()?;
 
     match opam_data.conflict_class {
         Some(conflict_class) => {
@@ -478,11 +788,385 @@ pub fn parse_dependencies_for_package_version(
         _ => (),
     }
 
+    for depopt in get_depends(opam_data.depopts) {
+        dependencies.extend(parse_depopt(parse_package_formula(&depopt)?, package));
+    }
+
+    if let Some(available) = &opam_data.available {
+        dependencies.push(parse_available(available, package)?);
+    }
+
+    for conflict in get_depends(opam_data.conflicts) {
+        dependencies.extend(parse_conflict(parse_package_formula(&conflict)?, package));
+    }
+
     let external_deps: Vec<PackageFormula> = get_depexts(opam_data.depexts)
-        .into_iter()
-        .map(|d| parse_depext(&d))
-        .collect();
+        .iter()
+        .map(parse_depext)
+        .collect::<Result<_, _>>()?;
     dependencies.extend(external_deps);
 
-    Ok(dependencies)
+    Ok(dependencies
+        .into_iter()
+        .map(|formula| scope_package_formula(formula, package))
+        .collect())
+}
+
+/// Flags opam sets per package rather than for the whole solve, so that
+/// `with-test` enables the test dependencies of the requested packages only.
+pub const PACKAGE_FLAGS: [&str; 4] = ["with-test", "with-doc", "with-dev-setup", "dev"];
+
+// `_:var` is a variable of the package whose opam file it appears in, as
+// are the package flags
+fn scope_variable(variable: String, package: &str) -> String {
+    match variable.strip_prefix("_:") {
+        Some(name) => format!("{}:{}", package, name),
+        None if PACKAGE_FLAGS.contains(&variable.as_str()) => {
+            format!("{}:{}", package, variable)
+        }
+        None => variable,
+    }
+}
+
+fn scope_version_formula(formula: VersionFormula, package: &str) -> VersionFormula {
+    let scope = |binary: Binary<VersionFormula>| Binary {
+        lhs: Box::new(scope_version_formula(*binary.lhs, package)),
+        rhs: Box::new(scope_version_formula(*binary.rhs, package)),
+    };
+    match formula {
+        VersionFormula::Variable(variable) => {
+            VersionFormula::Variable(scope_variable(variable, package))
+        }
+        VersionFormula::Not(variable) => VersionFormula::Not(scope_variable(variable, package)),
+        VersionFormula::And(binary) => VersionFormula::And(scope(binary)),
+        VersionFormula::Or(binary) => VersionFormula::Or(scope(binary)),
+        VersionFormula::Comparator { relop, binary } => VersionFormula::Comparator {
+            relop,
+            binary: scope(binary),
+        },
+        formula => formula,
+    }
+}
+
+fn scope_package_formula(formula: PackageFormula, package: &str) -> PackageFormula {
+    let scope = |binary: Binary<PackageFormula>| Binary {
+        lhs: Box::new(scope_package_formula(*binary.lhs, package)),
+        rhs: Box::new(scope_package_formula(*binary.rhs, package)),
+    };
+    match formula {
+        PackageFormula::Or(binary) => PackageFormula::Or(scope(binary)),
+        PackageFormula::And(binary) => PackageFormula::And(scope(binary)),
+        PackageFormula::Base { name, formula } => PackageFormula::Base {
+            name,
+            formula: scope_version_formula(formula, package),
+        },
+        PackageFormula::Depext { names, formula } => PackageFormula::Depext {
+            names,
+            formula: scope_version_formula(formula, package),
+        },
+        PackageFormula::Conflict {
+            name,
+            formula,
+            package: conflicting,
+        } => PackageFormula::Conflict {
+            name,
+            formula: scope_version_formula(formula, package),
+            package: conflicting,
+        },
+        PackageFormula::Depopt {
+            name,
+            formula,
+            package: depending,
+        } => PackageFormula::Depopt {
+            name,
+            formula: scope_version_formula(formula, package),
+            package: depending,
+        },
+        PackageFormula::Available {
+            package: available,
+            formula,
+        } => PackageFormula::Available {
+            package: available,
+            formula: scope_version_formula(formula, package),
+        },
+        formula @ PackageFormula::ConflictClass { .. } => formula,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn version(relop: RelOp, version: &str) -> Range<OpamVersion> {
+        relop_to_range(&relop, OpamVersion(version.to_string()))
+    }
+
+    #[test]
+    fn test_group() -> Result<(), Box<dyn Error>> {
+        let formula: OpamPackageFormula = serde_json::from_str(
+            r#"{ "group": [ { "logop": "or", "lhs": "a", "rhs": "b" }, "c" ] }"#,
+        )?;
+        let full = || VersionFormula::Version(HashedRange(Range::full()));
+        let base = |name: &str| PackageFormula::Base {
+            name: name.to_string(),
+            formula: full(),
+        };
+        assert_eq!(
+            parse_package_formula(&formula)?,
+            PackageFormula::And(Binary {
+                lhs: Box::new(PackageFormula::Or(Binary {
+                    lhs: Box::new(base("a")),
+                    rhs: Box::new(base("b")),
+                })),
+                rhs: Box::new(base("c")),
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_conditions() -> Result<(), Box<dyn Error>> {
+        let formula: OpamPackageFormula = serde_json::from_str(
+            r#"{ "val": "a", "conditions": [
+                { "prefix_relop": "geq", "arg": "1.0" },
+                { "group": [ { "prefix_relop": "lt", "arg": "2.0" } ] },
+                { "id": "with-test" }
+            ] }"#,
+        )?;
+        let range = version(RelOp::Geq, "1.0").intersection(&version(RelOp::Lt, "2.0"));
+        assert_eq!(
+            parse_package_formula(&formula)?,
+            PackageFormula::Base {
+                name: "a".to_string(),
+                formula: VersionFormula::And(Binary {
+                    lhs: Box::new(VersionFormula::Version(HashedRange(range))),
+                    rhs: Box::new(VersionFormula::Variable("with-test".to_string())),
+                }),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pin_depends() -> Result<(), Box<dyn Error>> {
+        let pins = parse_opam_file(r#"pin-depends: ["foo.bar.1.0" "./foo"]"#)?.pin_depends()?;
+        assert_eq!(pins.len(), 1);
+        // package names can't contain a dot, versions can
+        assert_eq!(pins[0].0, "foo");
+        assert_eq!(pins[0].1.version, OpamVersion("bar.1.0".to_string()));
+        assert!(parse_opam_file(r#"pin-depends: [["foo" "./foo"]]"#)?
+            .pin_depends()
+            .is_err());
+        assert!(parse_opam_file(r#"pin-depends: []"#)?
+            .pin_depends()?
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_depext_conditions() -> Result<(), Box<dyn Error>> {
+        let depext: DepextField = serde_json::from_str(
+            r#"{ "val": ["libgmp-dev"], "conditions": [
+                { "lhs": { "id": "os-family" }, "relop": "eq", "rhs": "debian" },
+                { "id": "build" }
+            ] }"#,
+        )?;
+        let PackageFormula::Depext { formula, .. } = parse_depext(&depext)? else {
+            return Err("expected a depext".into());
+        };
+        assert!(matches!(formula, VersionFormula::And(_)), "{}", formula);
+        Ok(())
+    }
+
+    #[test]
+    fn test_literals() -> Result<(), Box<dyn Error>> {
+        let filter = |json: &str| -> Result<VersionFormula, Box<dyn Error>> {
+            parse_filter_expr(&serde_json::from_str(json)?)
+        };
+        assert_eq!(
+            filter(r#"{ "logop": "and", "lhs": { "id": "with-test" }, "rhs": true }"#)?,
+            VersionFormula::Variable("with-test".to_string())
+        );
+        assert_eq!(
+            filter(r#"{ "logop": "or", "lhs": { "id": "with-test" }, "rhs": true }"#)?,
+            VersionFormula::Lit(TRUE_VERSION.clone())
+        );
+        assert_eq!(
+            filter(
+                r#"{ "pfxop": "not", "arg": { "logop": "and", "lhs": false, "rhs": { "id": "build" } } }"#
+            )?,
+            VersionFormula::Lit(TRUE_VERSION.clone())
+        );
+        assert_eq!(
+            filter(r#"{ "lhs": { "id": "jobs" }, "relop": "geq", "rhs": 4 }"#)?,
+            VersionFormula::Comparator {
+                relop: RelOp::Geq,
+                binary: Binary {
+                    lhs: Box::new(VersionFormula::Variable("jobs".to_string())),
+                    rhs: Box::new(VersionFormula::Lit(OpamVersion("4".to_string()))),
+                },
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_formulas() -> Result<(), Box<dyn Error>> {
+        // `?` only applies to a variable, and a group can't be empty
+        let filter: FilterExpr = serde_json::from_str(r#"{ "pfxop": "defined", "arg": "1.0" }"#)?;
+        assert!(parse_filter_expr(&filter).is_err());
+        let filter: FilterExpr = serde_json::from_str(r#"{ "group": [] }"#)?;
+        assert!(parse_filter_expr(&filter).is_err());
+        let formula: OpamPackageFormula =
+            serde_json::from_str(r#"{ "val": "a", "conditions": [ { "group": [] } ] }"#)?;
+        assert!(parse_package_formula(&formula).is_err());
+        let formula: OpamPackageFormula = serde_json::from_str(r#"{ "group": [] }"#)?;
+        assert!(parse_package_formula(&formula).is_err());
+        Ok(())
+    }
+
+    /// The packages an opam formula names, with their conditions.
+    fn opam_atoms(
+        formula: &OpamPackageFormula,
+        atoms: &mut Vec<(String, Vec<OpamVersionFormula>)>,
+    ) {
+        match formula {
+            OpamPackageFormula::Binary { lhs, rhs, .. } => {
+                opam_atoms(lhs, atoms);
+                opam_atoms(rhs, atoms);
+            }
+            OpamPackageFormula::Group { group } => {
+                group.iter().for_each(|formula| opam_atoms(formula, atoms))
+            }
+            OpamPackageFormula::Simple { name, conditions } => {
+                atoms.push((name.clone(), conditions.clone()))
+            }
+            OpamPackageFormula::Plain(name) => atoms.push((name.clone(), vec![])),
+        }
+    }
+
+    fn parsed_atoms(formula: &PackageFormula, atoms: &mut Vec<(String, VersionFormula)>) {
+        match formula {
+            PackageFormula::And(binary) | PackageFormula::Or(binary) => {
+                parsed_atoms(&binary.lhs, atoms);
+                parsed_atoms(&binary.rhs, atoms);
+            }
+            PackageFormula::Base { name, formula } => atoms.push((name.clone(), formula.clone())),
+            _ => (),
+        }
+    }
+
+    fn filter_variables(filter: &FilterExpr, variables: &mut BTreeSet<String>) {
+        match filter {
+            FilterExpr::LogOp { lhs, rhs, .. } | FilterExpr::Relop { lhs, rhs, .. } => {
+                filter_variables(lhs, variables);
+                filter_variables(rhs, variables);
+            }
+            FilterExpr::Unary { arg, .. } => filter_variables(arg, variables),
+            FilterExpr::Group { group } => group
+                .iter()
+                .for_each(|filter| filter_variables(filter, variables)),
+            FilterExpr::Variable { id } => {
+                variables.insert(id.clone());
+            }
+            FilterExpr::Literal(_) => (),
+        }
+    }
+
+    fn opam_variables(formula: &OpamVersionFormula, variables: &mut BTreeSet<String>) {
+        match formula {
+            OpamVersionFormula::LogOp { lhs, rhs, .. } => {
+                opam_variables(lhs, variables);
+                opam_variables(rhs, variables);
+            }
+            OpamVersionFormula::Group { group } => group
+                .iter()
+                .for_each(|formula| opam_variables(formula, variables)),
+            OpamVersionFormula::PrefixOperator { arg, .. } => opam_variables(arg, variables),
+            OpamVersionFormula::PrefixRelop { arg, .. } => match arg {
+                FilterOrVersion::Version(_) => (),
+                FilterOrVersion::Filter(filter) => filter_variables(filter, variables),
+            },
+            OpamVersionFormula::Filter(filter) => filter_variables(filter, variables),
+        }
+    }
+
+    fn parsed_variables(formula: &VersionFormula, variables: &mut BTreeSet<String>) {
+        match formula {
+            VersionFormula::Variable(variable) | VersionFormula::Not(variable) => {
+                variables.insert(variable.clone());
+            }
+            VersionFormula::And(binary)
+            | VersionFormula::Or(binary)
+            | VersionFormula::Comparator { binary, .. } => {
+                parsed_variables(&binary.lhs, variables);
+                parsed_variables(&binary.rhs, variables);
+            }
+            VersionFormula::Version(_) | VersionFormula::Lit(_) => (),
+        }
+    }
+
+    /// Every package, variable and version bound in a formula survives parsing.
+    fn assert_nothing_dropped(path: &Path, formula: &OpamPackageFormula) {
+        let mut expected = vec![];
+        opam_atoms(formula, &mut expected);
+        let mut parsed = vec![];
+        let parsed_formula =
+            parse_package_formula(formula).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        parsed_atoms(&parsed_formula, &mut parsed);
+        assert_eq!(
+            expected.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            parsed.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            "{}",
+            path.display()
+        );
+        for ((name, conditions), (_, formula)) in expected.iter().zip(parsed.iter()) {
+            let mut variables = BTreeSet::new();
+            conditions
+                .iter()
+                .for_each(|condition| opam_variables(condition, &mut variables));
+            let mut parsed_vars = BTreeSet::new();
+            parsed_variables(formula, &mut parsed_vars);
+            // unless a boolean literal decided the whole filter
+            if !matches!(formula, VersionFormula::Lit(_)) {
+                assert_eq!(variables, parsed_vars, "{} {}", path.display(), name);
+            }
+            // a conjunction of version bounds is within each of them
+            if let VersionFormula::Version(range) = formula {
+                for condition in conditions {
+                    if let Ok(VersionFormula::Version(bound)) = parse_version_formula(condition) {
+                        assert_eq!(
+                            range.0.intersection(&bound.0),
+                            range.0,
+                            "{} {}",
+                            path.display(),
+                            name
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_opam_repository_no_dropped_constraints() {
+        let files: Vec<_> = walkdir::WalkDir::new("./opam-repository/packages")
+            .into_iter()
+            .map(|entry| entry.unwrap().into_path())
+            .filter(|path| path.file_name().is_some_and(|name| name == "opam"))
+            .collect();
+        assert!(!files.is_empty());
+        for path in files {
+            let content = fs::read_to_string(&path).unwrap();
+            let opam =
+                parse_opam_file(&content).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            for formula in get_depends(opam.depends)
+                .iter()
+                .chain(get_depends(opam.conflicts).iter())
+            {
+                assert_nothing_dropped(&path, formula);
+            }
+        }
+    }
 }
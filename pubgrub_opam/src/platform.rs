@@ -0,0 +1,244 @@
+use crate::version::OpamVersion;
+
+/// A value opam reports for `os-distribution`, and the `os`, `os-family`
+/// and `os-version` values that go with it.
+pub struct Distribution {
+    pub name: &'static str,
+    pub os: &'static str,
+    pub family: &'static str,
+    pub versions: &'static [&'static str],
+}
+
+/// The platforms opam runs on, most common first. `os-family` is the first
+/// word of `ID_LIKE` in `/etc/os-release` where there is one, as opam reads
+/// it, and the distribution itself otherwise.
+pub const DISTRIBUTIONS: &[Distribution] = &[
+    Distribution {
+        name: "debian",
+        os: "linux",
+        family: "debian",
+        versions: &["10", "11", "12", "13"],
+    },
+    Distribution {
+        name: "ubuntu",
+        os: "linux",
+        family: "debian",
+        versions: &["20.04", "22.04", "24.04", "24.10"],
+    },
+    Distribution {
+        name: "alpine",
+        os: "linux",
+        family: "alpine",
+        versions: &["3.18", "3.19", "3.20", "3.21"],
+    },
+    Distribution {
+        name: "fedora",
+        os: "linux",
+        family: "fedora",
+        versions: &["39", "40", "41"],
+    },
+    Distribution {
+        name: "centos",
+        os: "linux",
+        family: "rhel",
+        versions: &["8", "9"],
+    },
+    Distribution {
+        name: "rhel",
+        os: "linux",
+        family: "fedora",
+        versions: &["8", "9"],
+    },
+    Distribution {
+        name: "rocky",
+        os: "linux",
+        family: "rhel",
+        versions: &["8", "9"],
+    },
+    Distribution {
+        name: "ol",
+        os: "linux",
+        family: "fedora",
+        versions: &["8", "9"],
+    },
+    Distribution {
+        name: "arch",
+        os: "linux",
+        family: "arch",
+        versions: &[],
+    },
+    Distribution {
+        name: "opensuse-leap",
+        os: "linux",
+        family: "suse",
+        versions: &["15.5", "15.6"],
+    },
+    Distribution {
+        name: "opensuse-tumbleweed",
+        os: "linux",
+        family: "suse",
+        versions: &[],
+    },
+    Distribution {
+        name: "gentoo",
+        os: "linux",
+        family: "gentoo",
+        versions: &[],
+    },
+    Distribution {
+        name: "nixos",
+        os: "linux",
+        family: "nixos",
+        versions: &["24.05", "24.11"],
+    },
+    Distribution {
+        name: "homebrew",
+        os: "macos",
+        family: "homebrew",
+        versions: &["13", "14", "15"],
+    },
+    Distribution {
+        name: "macports",
+        os: "macos",
+        family: "macports",
+        versions: &["13", "14", "15"],
+    },
+    Distribution {
+        name: "freebsd",
+        os: "freebsd",
+        family: "bsd",
+        versions: &["13.4", "14.1", "14.2"],
+    },
+    Distribution {
+        name: "openbsd",
+        os: "openbsd",
+        family: "bsd",
+        versions: &["7.5", "7.6"],
+    },
+    Distribution {
+        name: "netbsd",
+        os: "netbsd",
+        family: "bsd",
+        versions: &["10.0"],
+    },
+    Distribution {
+        name: "dragonfly",
+        os: "dragonfly",
+        family: "bsd",
+        versions: &["6.4"],
+    },
+    Distribution {
+        name: "cygwin",
+        os: "cygwin",
+        family: "windows",
+        versions: &[],
+    },
+    Distribution {
+        name: "msys2",
+        os: "win32",
+        family: "windows",
+        versions: &[],
+    },
+];
+
+/// The architectures opam reports, after its normalisation of `uname -m`.
+pub const ARCHITECTURES: &[&str] = &[
+    "x86_64", "arm64", "x86_32", "arm32", "ppc64", "ppc32", "s390x", "riscv64",
+];
+
+// distinct values in the order they're first seen
+fn unique<'a>(values: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut unique: Vec<&str> = vec![];
+    for value in values {
+        if !unique.contains(&value) {
+            unique.push(value);
+        }
+    }
+    unique
+}
+
+/// The known values of a platform variable, or `None` for other variables.
+pub fn platform_values(var: &str) -> Option<Vec<&'static str>> {
+    let distributions = DISTRIBUTIONS.iter();
+    match var {
+        "os" => Some(unique(distributions.map(|d| d.os))),
+        "os-family" => Some(unique(distributions.map(|d| d.family))),
+        "os-distribution" => Some(unique(distributions.map(|d| d.name))),
+        "os-version" => Some(unique(
+            distributions.flat_map(|d| d.versions.iter().copied()),
+        )),
+        "arch" => Some(ARCHITECTURES.to_vec()),
+        _ => None,
+    }
+}
+
+/// The values other platform variables can take alongside a value of this
+/// one, so that a solve can't pick `os-distribution = "ubuntu"` on
+/// `os = "macos"`. Values the table doesn't know imply nothing.
+pub fn platform_implications(
+    var: &str,
+    value: &OpamVersion,
+) -> Vec<(&'static str, Vec<&'static str>)> {
+    let value = value.0.as_str();
+    let matching = |matches: &dyn Fn(&Distribution) -> bool| -> Vec<&Distribution> {
+        DISTRIBUTIONS.iter().filter(|d| matches(d)).collect()
+    };
+    match var {
+        "os-distribution" => matching(&|d| d.name == value)
+            .into_iter()
+            .flat_map(|d| [("os", vec![d.os]), ("os-family", vec![d.family])])
+            .collect(),
+        "os-family" => {
+            let distributions = matching(&|d| d.family == value);
+            if distributions.is_empty() {
+                return vec![];
+            }
+            vec![("os", unique(distributions.iter().map(|d| d.os)))]
+        }
+        "os-version" => {
+            let distributions = matching(&|d| d.versions.contains(&value));
+            if distributions.is_empty() {
+                return vec![];
+            }
+            vec![(
+                "os-distribution",
+                unique(distributions.iter().map(|d| d.name)),
+            )]
+        }
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_platform_values() {
+        let arch = platform_values("arch").unwrap();
+        assert_eq!(arch.len(), unique(arch.iter().copied()).len());
+        assert_eq!(platform_values("os").unwrap()[0], "linux");
+        assert!(platform_values("os-distribution")
+            .unwrap()
+            .contains(&"homebrew"));
+        assert_eq!(platform_values("with-test"), None);
+    }
+
+    #[test]
+    fn test_platform_implications() {
+        let value = |value: &str| OpamVersion(value.to_string());
+        assert_eq!(
+            platform_implications("os-distribution", &value("ubuntu")),
+            vec![("os", vec!["linux"]), ("os-family", vec!["debian"])]
+        );
+        assert_eq!(
+            platform_implications("os-family", &value("bsd")),
+            vec![("os", vec!["freebsd", "openbsd", "netbsd", "dragonfly"])]
+        );
+        assert_eq!(
+            platform_implications("os-version", &value("22.04")),
+            vec![("os-distribution", vec!["ubuntu"])]
+        );
+        assert!(platform_implications("os-distribution", &value("haiku")).is_empty());
+    }
+}
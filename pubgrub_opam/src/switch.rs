@@ -0,0 +1,268 @@
+//! The state of an existing opam switch, so that a solve can start from what
+//! is installed and report the changes it makes as opam would.
+
+use pubgrub::Range;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use crate::deps::{OpamPackage, ABSENT_VERSION};
+use crate::export::OpamSolution;
+use crate::index::{OpamRepository, PackageName, Pin};
+use crate::opam_file::{parse_items, Item, Value};
+use crate::version::OpamVersion;
+
+/// The packages of a switch, as its `switch-state` file or `opam switch
+/// export` lists them, and the switch's variables.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SwitchState {
+    pub installed: BTreeMap<PackageName, OpamVersion>,
+    /// The installed packages that were asked for, rather than pulled in as
+    /// dependencies.
+    pub roots: BTreeSet<PackageName>,
+    pub pinned: BTreeMap<PackageName, OpamVersion>,
+    pub variables: BTreeMap<String, OpamVersion>,
+    /// Where the switch keeps the opam files of its pinned packages.
+    pub overlay: Option<PathBuf>,
+}
+
+/// A change a solution makes to a switch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SwitchAction {
+    Install(PackageName, OpamVersion),
+    Upgrade(PackageName, OpamVersion, OpamVersion),
+    Downgrade(PackageName, OpamVersion, OpamVersion),
+    Remove(PackageName, OpamVersion),
+}
+
+impl Display for SwitchAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SwitchAction::Install(name, version) => write!(f, "install {} {}", name, version),
+            SwitchAction::Upgrade(name, from, to) => {
+                write!(f, "upgrade {} {} to {}", name, from, to)
+            }
+            SwitchAction::Downgrade(name, from, to) => {
+                write!(f, "downgrade {} {} to {}", name, from, to)
+            }
+            SwitchAction::Remove(name, version) => write!(f, "remove {} {}", name, version),
+        }
+    }
+}
+
+impl SwitchState {
+    /// Parse a `switch-state` file or the output of `opam switch export`. A
+    /// `variables` section, as in a switch's `switch-config`, sets variables.
+    pub fn parse(src: &str) -> Result<Self, Box<dyn Error>> {
+        let mut state = SwitchState::default();
+        for item in parse_items(src)? {
+            match item {
+                Item::Field(field, value) => match field.as_str() {
+                    "installed" => state.installed.extend(package_atoms(&field, &value)?),
+                    "roots" => state.roots.extend(
+                        package_atoms(&field, &value)?
+                            .into_iter()
+                            .map(|(name, _)| name),
+                    ),
+                    "pinned" => state.pinned.extend(package_atoms(&field, &value)?),
+                    _ => {}
+                },
+                Item::Section { kind, items, .. } if kind == "variables" => {
+                    for item in items {
+                        if let Item::Field(var, value) = item {
+                            let value = variable_value(&var, &value)?;
+                            state.variables.insert(var, value);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(state)
+    }
+
+    /// Read a `switch-state` file, an `opam switch export` file, or the
+    /// switch with this prefix directory. A switch directory also gives the
+    /// switch's variables and the opam files of its pinned packages.
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.is_dir() {
+            return parse_file(path);
+        }
+        let meta = path.join(".opam-switch");
+        let mut state = parse_file(&meta.join("switch-state"))?;
+        let config = meta.join("switch-config");
+        if config.is_file() {
+            state.variables.extend(parse_file(&config)?.variables);
+        }
+        state.overlay = Some(meta.join("overlay"));
+        Ok(state)
+    }
+
+    /// What a solve starting from this switch has to keep: every root at
+    /// some version, and pinned packages, if installed, at their pinned
+    /// version.
+    pub fn requirements(&self) -> Vec<(OpamPackage, Range<OpamVersion>)> {
+        let roots = self
+            .roots
+            .iter()
+            .map(|name| (OpamPackage::Base(name.clone()), Range::full()));
+        let pins = self.pinned.iter().map(|(name, version)| {
+            let range =
+                Range::singleton(version.clone()).union(&Range::singleton(ABSENT_VERSION.clone()));
+            (OpamPackage::Installed(name.clone()), range)
+        });
+        roots.chain(pins).collect()
+    }
+
+    /// Pin the pinned packages the switch keeps opam files for, so that
+    /// versions no repository has, like `dev`, can be solved for.
+    pub fn pin(&self, mut repository: OpamRepository) -> OpamRepository {
+        let Some(overlay) = &self.overlay else {
+            return repository;
+        };
+        for (name, version) in &self.pinned {
+            let dir = overlay.join(name);
+            if dir.join("opam").is_file() {
+                let pin = Pin {
+                    version: version.clone(),
+                    url: dir.to_string_lossy().to_string(),
+                };
+                repository = repository.with_pin(name.clone(), pin);
+            }
+        }
+        repository
+    }
+
+    /// The changes that take the switch to a solution, by package name.
+    /// Installed packages the solution doesn't need are removed, as
+    /// `opam install --autoremove` would.
+    pub fn actions(&self, solution: &OpamSolution) -> Vec<SwitchAction> {
+        let names: BTreeSet<&PackageName> = self
+            .installed
+            .keys()
+            .chain(solution.packages.keys())
+            .collect();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let name = name.clone();
+                match (self.installed.get(&name), solution.packages.get(&name)) {
+                    (None, Some(to)) => Some(SwitchAction::Install(name, to.clone())),
+                    (Some(from), None) => Some(SwitchAction::Remove(name, from.clone())),
+                    (Some(from), Some(to)) if to > from => {
+                        Some(SwitchAction::Upgrade(name, from.clone(), to.clone()))
+                    }
+                    (Some(from), Some(to)) if to < from => {
+                        Some(SwitchAction::Downgrade(name, from.clone(), to.clone()))
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
+
+fn parse_file(path: &Path) -> Result<SwitchState, Box<dyn Error>> {
+    let src = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    SwitchState::parse(&src).map_err(|e| format!("Error parsing {}: {}", path.display(), e).into())
+}
+
+// `"name.version"` atoms, in a list or on their own
+fn package_atoms(
+    field: &str,
+    value: &Value,
+) -> Result<Vec<(PackageName, OpamVersion)>, Box<dyn Error>> {
+    let values = match value {
+        Value::List(values) => values.as_slice(),
+        value => std::slice::from_ref(value),
+    };
+    values
+        .iter()
+        .map(|value| match value {
+            Value::String(atom) => match atom.split_once('.') {
+                Some((name, version)) if !name.is_empty() && !version.is_empty() => {
+                    Ok((name.to_string(), OpamVersion(version.to_string())))
+                }
+                _ => Err(format!("Invalid package {} in {}", atom, field).into()),
+            },
+            value => Err(format!("Invalid package {:?} in {}", value, field).into()),
+        })
+        .collect()
+}
+
+fn variable_value(var: &str, value: &Value) -> Result<OpamVersion, Box<dyn Error>> {
+    match value {
+        Value::String(value) => Ok(OpamVersion(value.clone())),
+        Value::Bool(value) => Ok(OpamVersion(value.to_string())),
+        Value::Int(value) => Ok(OpamVersion(value.to_string())),
+        value => Err(format!("Invalid value for switch variable {}: {:?}", var, value).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(version: &str) -> OpamVersion {
+        OpamVersion(version.to_string())
+    }
+
+    #[test]
+    fn test_parse_switch_export() -> Result<(), Box<dyn Error>> {
+        let state = SwitchState::parse(
+            r#"
+            opam-version: "2.0"
+            compiler: ["ocaml-base-compiler.5.3.0"]
+            roots: ["dune.3.17.2" "ocaml-base-compiler.5.3.0"]
+            installed: ["dune.3.17.2" "ocaml.5.3.0" "ocaml-base-compiler.5.3.0"]
+            pinned: "dune.3.17.2"
+            "#,
+        )?;
+        assert_eq!(state.installed.len(), 3);
+        assert_eq!(state.installed["ocaml"], version("5.3.0"));
+        assert!(state.roots.contains("ocaml-base-compiler"));
+        assert!(!state.roots.contains("ocaml"));
+        assert_eq!(state.pinned["dune"], version("3.17.2"));
+        assert!(SwitchState::parse("installed: [\"dune\"]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_actions() {
+        let state = SwitchState {
+            installed: [
+                ("a", "1"),
+                ("b", "2"),
+                ("c", "1"),
+                ("d", "1"),
+                ("f", "1.01"),
+            ]
+            .map(|(name, v)| (name.to_string(), version(v)))
+            .into(),
+            ..SwitchState::default()
+        };
+        let solution = OpamSolution {
+            // opam takes 1.01 and 1.1 to be the same version
+            packages: [("a", "1"), ("b", "1"), ("c", "2"), ("e", "1"), ("f", "1.1")]
+                .map(|(name, v)| (name.to_string(), version(v)))
+                .into(),
+            ..OpamSolution::default()
+        };
+        let actions: Vec<String> = state
+            .actions(&solution)
+            .iter()
+            .map(|action| action.to_string())
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                "downgrade b 2 to 1",
+                "upgrade c 1 to 2",
+                "remove d 1",
+                "install e 1"
+            ]
+        );
+    }
+}
@@ -1,13 +1,18 @@
 use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::str::FromStr;
 
-#[derive(Clone, Eq, PartialEq, Hash, Debug, serde::Serialize, serde::Deserialize)]
+/// An opam version. Versions are compared as opam compares them, so ones
+/// that only differ in leading zeros, like `1.01` and `1.1`, are equal.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct OpamVersion(pub String);
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Hash)]
 enum Token {
-    Num(u64),
+    /// The digits of a number, without leading zeros, so that numbers of any
+    /// length compare by value.
+    Num(String),
     Str(String),
 }
 
@@ -35,13 +40,7 @@ fn tokenize(version: &str) -> Vec<Token> {
             }
             Some(_) => {
                 // Type changed: push the current token and start a new one.
-                if is_digit.unwrap() {
-                    // Numeric token – parse it (ignoring leading zeros)
-                    let num = current.parse::<u64>().unwrap_or(0);
-                    tokens.push(Token::Num(num));
-                } else {
-                    tokens.push(Token::Str(current.clone()));
-                }
+                tokens.push(token(&current, is_digit.unwrap()));
                 current.clear();
                 current.push(ch);
                 is_digit = Some(ch_is_digit);
@@ -55,20 +54,26 @@ fn tokenize(version: &str) -> Vec<Token> {
     }
     // Push the final token.
     if let Some(current_is_digit) = is_digit {
-        if current_is_digit {
-            let num = current.parse::<u64>().unwrap_or(0);
-            tokens.push(Token::Num(num));
-        } else {
-            tokens.push(Token::Str(current));
-        }
+        tokens.push(token(&current, current_is_digit));
     }
     tokens
 }
 
+fn token(current: &str, is_digit: bool) -> Token {
+    if is_digit {
+        Token::Num(current.trim_start_matches('0').to_string())
+    } else {
+        Token::Str(current.to_string())
+    }
+}
+
 /// Compare two non-numeric tokens according to Debian’s rules:
 /// - Compare character by character.
-/// - Letters are always considered lower than non-letters.
+/// - ASCII letters are always considered lower than other characters.
 /// - The tilde character (`~`) sorts even before the end of a token.
+///
+/// As in opam, only ASCII letters count as letters: anything else, including
+/// non-ASCII letters, sorts by its UTF-8 bytes after every ASCII letter.
 fn compare_str_token(a: &str, b: &str) -> Ordering {
     let mut it1 = a.chars();
     let mut it2 = b.chars();
@@ -109,8 +114,8 @@ fn compare_str_token(a: &str, b: &str) -> Ordering {
                     }
                 }
                 // Letters are considered lower than non-letters.
-                let is_letter1 = c1.is_alphabetic();
-                let is_letter2 = c2.is_alphabetic();
+                let is_letter1 = c1.is_ascii_alphabetic();
+                let is_letter2 = c2.is_ascii_alphabetic();
                 if is_letter1 != is_letter2 {
                     return if is_letter1 {
                         Ordering::Less
@@ -118,7 +123,7 @@ fn compare_str_token(a: &str, b: &str) -> Ordering {
                         Ordering::Greater
                     };
                 }
-                // Fallback: compare by ASCII value.
+                // Fallback: compare by code point, which orders as UTF-8 bytes do.
                 return c1.cmp(&c2);
             }
         }
@@ -128,7 +133,8 @@ fn compare_str_token(a: &str, b: &str) -> Ordering {
 /// Compare two tokens.
 fn compare_tokens(a: &Token, b: &Token) -> Ordering {
     match (a, b) {
-        (Token::Num(n1), Token::Num(n2)) => n1.cmp(n2),
+        // without leading zeros, a longer number is a larger one
+        (Token::Num(n1), Token::Num(n2)) => n1.len().cmp(&n2.len()).then_with(|| n1.cmp(n2)),
         (Token::Str(s1), Token::Str(s2)) => compare_str_token(s1, s2),
         // In practice, token types should alternate.
         (Token::Num(_), Token::Str(_)) => Ordering::Greater,
@@ -136,46 +142,66 @@ fn compare_tokens(a: &Token, b: &Token) -> Ordering {
     }
 }
 
-impl Ord for OpamVersion {
-    fn cmp(&self, other: &Self) -> Ordering {
-        let tokens1 = tokenize(&self.0);
-        let tokens2 = tokenize(&other.0);
-        let max = tokens1.len().max(tokens2.len());
-
-        for i in 0..max {
-            let token1 = tokens1.get(i);
-            let token2 = tokens2.get(i);
-            let ord = match (token1, token2) {
-                (Some(t1), Some(t2)) => compare_tokens(t1, t2),
-                (None, Some(t2)) => {
-                    if let Token::Str(s2) = t2 {
-                        if s2.starts_with('~') {
-                            Ordering::Greater
-                        } else {
-                            Ordering::Less
-                        }
+/// Compare versions as opam's `OpamVersionCompare` does.
+fn compare_versions(v1: &str, v2: &str) -> Ordering {
+    let tokens1 = tokenize(v1);
+    let tokens2 = tokenize(v2);
+    let max = tokens1.len().max(tokens2.len());
+
+    for i in 0..max {
+        let token1 = tokens1.get(i);
+        let token2 = tokens2.get(i);
+        let ord = match (token1, token2) {
+            (Some(t1), Some(t2)) => compare_tokens(t1, t2),
+            (None, Some(t2)) => {
+                if let Token::Str(s2) = t2 {
+                    if s2.starts_with('~') {
+                        Ordering::Greater
                     } else {
                         Ordering::Less
                     }
+                } else {
+                    Ordering::Less
                 }
-                (Some(t1), None) => {
-                    if let Token::Str(s1) = t1 {
-                        if s1.starts_with('~') {
-                            Ordering::Less
-                        } else {
-                            Ordering::Greater
-                        }
+            }
+            (Some(t1), None) => {
+                if let Token::Str(s1) = t1 {
+                    if s1.starts_with('~') {
+                        Ordering::Less
                     } else {
                         Ordering::Greater
                     }
+                } else {
+                    Ordering::Greater
                 }
-                (None, None) => Ordering::Equal,
-            };
-            if ord != Ordering::Equal {
-                return ord;
             }
+            (None, None) => Ordering::Equal,
+        };
+        if ord != Ordering::Equal {
+            return ord;
         }
-        Ordering::Equal
+    }
+    Ordering::Equal
+}
+
+impl Ord for OpamVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_versions(&self.0, &other.0)
+    }
+}
+
+impl PartialEq for OpamVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0 || self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OpamVersion {}
+
+// equal versions have the same tokens, leading zeros aside
+impl Hash for OpamVersion {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        tokenize(&self.0).hash(state);
     }
 }
 
@@ -212,16 +238,16 @@ mod tests {
             Token::Str(s) => assert!(s.is_empty(), "Expected first token to be empty"),
             _ => panic!("Expected first token to be a string"),
         }
-        match tokens[1] {
-            Token::Num(n) => assert_eq!(n, 1),
+        match &tokens[1] {
+            Token::Num(n) => assert_eq!(n, "1"),
             _ => panic!("Expected second token to be a number"),
         }
         match &tokens[2] {
             Token::Str(s) => assert_eq!(s, "."),
             _ => panic!("Expected third token to be a string"),
         }
-        match tokens[3] {
-            Token::Num(n) => assert_eq!(n, 2),
+        match &tokens[3] {
+            Token::Num(n) => assert_eq!(n, "2"),
             _ => panic!("Expected fourth token to be a number"),
         }
     }
@@ -260,4 +286,237 @@ mod tests {
 
         assert_eq!(sorted_versions, expected_order);
     }
+
+    // `OpamVersionCompare.compare_chunks` from opam, line for line, to check
+    // the tokenized comparison against
+    fn opam_compare(x: &str, y: &str) -> Ordering {
+        let (x, y) = (x.as_bytes(), y.as_bytes());
+        let compare_chars = |c1: u8, c2: u8| match (c1, c2) {
+            (b'~', b'~') => Ordering::Equal,
+            (b'~', _) => Ordering::Less,
+            (_, b'~') => Ordering::Greater,
+            _ => match (c1.is_ascii_alphabetic(), c2.is_ascii_alphabetic()) {
+                (true, false) => Ordering::Less,
+                (false, true) => Ordering::Greater,
+                _ => c1.cmp(&c2),
+            },
+        };
+        let skip = |s: &[u8], mut i: usize, f: fn(&u8) -> bool| {
+            while i < s.len() && f(&s[i]) {
+                i += 1;
+            }
+            i
+        };
+        let (mut xi, mut yi) = (0, 0);
+        loop {
+            match (xi == x.len(), yi == y.len()) {
+                (true, true) => return Ordering::Equal,
+                (true, false) if y[yi].is_ascii_digit() => return Ordering::Less,
+                (true, false) if y[yi] == b'~' => return Ordering::Greater,
+                (true, false) => return Ordering::Less,
+                (false, true) if x[xi].is_ascii_digit() => return Ordering::Greater,
+                (false, true) if x[xi] == b'~' => return Ordering::Less,
+                (false, true) => return Ordering::Greater,
+                (false, false) => {}
+            }
+            match (x[xi].is_ascii_digit(), y[yi].is_ascii_digit()) {
+                (true, true) => {
+                    xi = skip(x, xi, |c| *c == b'0');
+                    yi = skip(y, yi, |c| *c == b'0');
+                    let nx = skip(x, xi, u8::is_ascii_digit);
+                    let ny = skip(y, yi, u8::is_ascii_digit);
+                    let ord = (nx - xi).cmp(&(ny - yi)).then(x[xi..nx].cmp(&y[yi..ny]));
+                    if ord != Ordering::Equal {
+                        return ord;
+                    }
+                    (xi, yi) = (nx, ny);
+                }
+                (true, false) if y[yi] == b'~' => return Ordering::Greater,
+                (true, false) => return Ordering::Less,
+                (false, true) if x[xi] == b'~' => return Ordering::Less,
+                (false, true) => return Ordering::Greater,
+                (false, false) => match compare_chars(x[xi], y[yi]) {
+                    Ordering::Equal => (xi, yi) = (xi + 1, yi + 1),
+                    ord => return ord,
+                },
+            }
+        }
+    }
+
+    // (a, b, how opam orders a against b)
+    const CONFORMANCE: &[(&str, &str, Ordering)] = &[
+        // the opam manual's examples
+        ("~~", "~", Ordering::Less),
+        ("~", "~beta2", Ordering::Less),
+        ("~beta2", "~beta10", Ordering::Less),
+        ("~beta10", "0.1", Ordering::Less),
+        ("0.1", "1.0~beta", Ordering::Less),
+        ("1.0~beta", "1.0", Ordering::Less),
+        ("1.0", "1.0-test", Ordering::Less),
+        ("1.0-test", "1.0.1", Ordering::Less),
+        ("1.0.1", "1.0.10", Ordering::Less),
+        ("1.0.10", "dev", Ordering::Less),
+        ("dev", "trunk", Ordering::Less),
+        // prefixes and empty versions
+        ("", "", Ordering::Equal),
+        ("~", "", Ordering::Less),
+        ("", "0", Ordering::Less),
+        ("", "a", Ordering::Less),
+        ("1", "1.0", Ordering::Less),
+        ("1.0", "1.0.0", Ordering::Less),
+        ("1.", "1.0", Ordering::Less),
+        ("1", "1a", Ordering::Less),
+        ("v1.0", "1.0", Ordering::Greater),
+        // leading zeros
+        ("1.01", "1.1", Ordering::Equal),
+        ("1.001", "1.01", Ordering::Equal),
+        ("0", "00", Ordering::Equal),
+        ("1.0a", "1.00", Ordering::Greater),
+        ("1.010", "1.1", Ordering::Greater),
+        ("1.10", "1.9", Ordering::Greater),
+        ("1.09", "1.10", Ordering::Less),
+        // numbers too large for any machine integer
+        ("18446744073709551616", "1", Ordering::Greater),
+        (
+            "99999999999999999999",
+            "100000000000000000000",
+            Ordering::Less,
+        ),
+        (
+            "1.20250101000000000000",
+            "1.20250101000000000001",
+            Ordering::Less,
+        ),
+        (
+            "0018446744073709551616",
+            "18446744073709551616",
+            Ordering::Equal,
+        ),
+        // `~` inside tokens
+        ("1.0~~", "1.0~", Ordering::Less),
+        ("1.0~~a", "1.0~~", Ordering::Greater),
+        ("1.0~beta", "1.0~beta2", Ordering::Less),
+        ("1.0a~", "1.0a", Ordering::Less),
+        ("1.0a~b", "1.0a", Ordering::Less),
+        ("1.0a~b", "1.0a~", Ordering::Greater),
+        ("1.0a~1", "1.0a", Ordering::Less),
+        ("1.0a~1", "1.0a~", Ordering::Greater),
+        ("1~a", "1~", Ordering::Greater),
+        ("1.0+~", "1.0+", Ordering::Less),
+        ("a~~", "a~", Ordering::Less),
+        ("4.14.0~alpha1+options", "4.14.0", Ordering::Less),
+        ("2.0.0~rc1", "2.0.0~beta3", Ordering::Greater),
+        ("5.3.0", "5.3.0~rc1", Ordering::Greater),
+        // letters before everything but `~`, then by byte
+        ("1.0a", "1.0b", Ordering::Less),
+        ("1.0A", "1.0a", Ordering::Less),
+        ("1.0z", "1.0+", Ordering::Less),
+        ("1.0z", "1.0_", Ordering::Less),
+        ("1.0+", "1.0-", Ordering::Less),
+        ("1.0-", "1.0.", Ordering::Less),
+        ("1.0.", "1.0_", Ordering::Less),
+        ("1a", "1.0", Ordering::Less),
+        ("1.0-1", "1.0.1", Ordering::Less),
+        ("4.14.0+options", "4.14.0", Ordering::Greater),
+        ("4.14.0+options", "4.14.1", Ordering::Less),
+        // non-ASCII letters aren't letters to opam
+        ("1.0é", "1.0z", Ordering::Greater),
+        ("1.0é", "1.0+", Ordering::Greater),
+        ("1.0é", "1.0ü", Ordering::Less),
+        ("1.0é", "1.0~", Ordering::Greater),
+        ("1.0ß", "1.0", Ordering::Greater),
+        // dpkg's own cases for `verrevcmp`, which `OpamVersionCompare`
+        // ports; opam has no epochs, so none of these has one
+        ("1.0", "2.0", Ordering::Less),
+        ("0foo", "0foo", Ordering::Equal),
+        ("0foo", "0fo", Ordering::Greater),
+        ("0foo~1", "0foo", Ordering::Less),
+        ("0foo~foo+Bar", "0foo~foo+bar", Ordering::Less),
+        ("0foo~~", "0foo~", Ordering::Less),
+        ("1~", "1", Ordering::Less),
+        ("0foo.bar", "0foobar", Ordering::Greater),
+        ("0foo.bar", "0foo1bar", Ordering::Greater),
+        ("0foo.bar", "0foo0bar", Ordering::Greater),
+        ("0foo2.0", "0foo2", Ordering::Greater),
+        ("0foo2.0.0", "0foo2.10.0", Ordering::Less),
+        ("0foo2.0", "0foo2.0.0", Ordering::Less),
+        ("0foo2.0", "0foo2.10", Ordering::Less),
+        ("0foo2.1", "0foo2.10", Ordering::Less),
+        ("1.09", "1.9", Ordering::Equal),
+        ("1.0.8+nmu1", "1.0.8", Ordering::Greater),
+        ("3.11", "3.10+nmu1", Ordering::Greater),
+        ("0.9j-20080306-4", "0.9i-20070324-2", Ordering::Greater),
+        ("1.2.0~b7-1", "1.2.0~b6-1", Ordering::Greater),
+        ("1.011-1", "1.06-2", Ordering::Greater),
+        ("0.0.9+dfsg1-1", "0.0.8+dfsg1-3", Ordering::Greater),
+        ("4.6.99+svn6582-1", "4.6.99+svn6496-1", Ordering::Greater),
+        ("53", "52", Ordering::Greater),
+        ("0.9.9~pre122-1", "0.9.9~pre111-1", Ordering::Greater),
+    ];
+
+    #[test]
+    fn test_conformance() {
+        for &(a, b, expected) in CONFORMANCE {
+            assert_eq!(opam_compare(a, b), expected, "opam: {} vs {}", a, b);
+            assert_eq!(compare_versions(a, b), expected, "{} vs {}", a, b);
+            assert_eq!(compare_versions(b, a), expected.reverse(), "{} vs {}", b, a);
+            let (v1, v2) = (OpamVersion(a.to_string()), OpamVersion(b.to_string()));
+            assert_eq!(v1.cmp(&v2), expected, "{} vs {}", a, b);
+            assert_eq!(v1 == v2, expected == Ordering::Equal, "{} == {}", a, b);
+        }
+    }
+
+    // every version up to three characters long from an alphabet that hits
+    // each case of the comparison
+    fn small_versions() -> Vec<String> {
+        let alphabet = ['0', '1', '9', 'a', 'Z', '~', '.', '+', 'é'];
+        let mut versions = vec![String::new()];
+        let mut last = versions.clone();
+        for _ in 0..3 {
+            last = last
+                .iter()
+                .flat_map(|v| alphabet.iter().map(move |c| format!("{}{}", v, c)))
+                .collect();
+            versions.extend(last.iter().cloned());
+        }
+        versions
+    }
+
+    #[test]
+    fn test_agrees_with_opam() {
+        let versions = small_versions();
+        for a in &versions {
+            for b in &versions {
+                assert_eq!(compare_versions(a, b), opam_compare(a, b), "{} vs {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_total_order() {
+        let mut versions: Vec<OpamVersion> =
+            small_versions().into_iter().map(OpamVersion).collect();
+        versions.sort();
+        // once sorted, no version may be above one after it, and two are
+        // equal exactly when they compare equal and then hash alike: that
+        // makes the order antisymmetric, transitive and consistent with `Eq`
+        let hash = |version: &OpamVersion| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            version.hash(&mut hasher);
+            hasher.finish()
+        };
+        for (i, a) in versions.iter().enumerate() {
+            assert_eq!(a.cmp(a), Ordering::Equal);
+            for b in &versions[i + 1..] {
+                let ord = a.cmp(b);
+                assert_ne!(ord, Ordering::Greater, "{} vs {}", a, b);
+                assert_eq!(b.cmp(a), ord.reverse(), "{} vs {}", b, a);
+                assert_eq!(a.partial_cmp(b), Some(ord));
+                assert_eq!(a == b, ord == Ordering::Equal, "{} == {}", a, b);
+                if a == b {
+                    assert_eq!(hash(a), hash(b), "{} and {}", a, b);
+                }
+            }
+        }
+    }
 }